@@ -1,10 +1,16 @@
 use std::io::{self, Write};
 
-use ferrous_db::FerrousDB;
+use ferrous_db::{FerrousDB, DEFAULT_DB_PATH};
 
 /// Starts a Read-Eval-Print Loop (REPL) for interacting with FerrousDB.
 pub fn repl() {
-    let mut db = FerrousDB::new();
+    let mut db = match FerrousDB::open(DEFAULT_DB_PATH) {
+        Ok(db) => db,
+        Err(err) => {
+            eprintln!("Could not open '{}': {}", DEFAULT_DB_PATH, err);
+            return;
+        }
+    };
     loop {
         print!("sql> ");
         io::stdout().flush().unwrap();
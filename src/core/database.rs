@@ -0,0 +1,315 @@
+//! A thread-safe handle around [`FerrousDB`].
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    thread::{self, ThreadId},
+};
+
+use super::{
+    db::{FerrousDB, PageResult},
+    error_handling::FerrousDBError,
+    expr::Expr,
+    parser::sql_parser::parse_sql,
+    result_set::{QueryResult, ResultSet},
+    row::Row,
+};
+
+/// A database that can be shared between threads.
+///
+/// Cloning the handle is cheap and every clone refers to the same database.
+/// Any number of threads can read at once and always see the state as of the
+/// last commit: the rows changed by an open write transaction keep their
+/// committed versions until it commits. Only one write transaction runs at a
+/// time, the others wait for it to finish. A thread with an open
+/// [`WriteTransaction`] can't write through the handle itself until it ends,
+/// since it would wait for itself.
+#[derive(Clone)]
+pub struct Database {
+    db: Arc<RwLock<FerrousDB>>,
+    writer: Arc<Mutex<()>>,
+    /// Thread of the open write transaction, if any.
+    writer_thread: Arc<Mutex<Option<ThreadId>>>,
+}
+
+impl Database {
+    pub fn new(db: FerrousDB) -> Self {
+        Database {
+            db: Arc::new(RwLock::new(db)),
+            writer: Arc::new(Mutex::new(())),
+            writer_thread: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Opens the database stored at `path`, see [`FerrousDB::open`].
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, FerrousDBError> {
+        Ok(Database::new(FerrousDB::open(path)?))
+    }
+
+    pub fn open_in_memory() -> Self {
+        Database::new(FerrousDB::open_in_memory())
+    }
+
+    /// Runs a statement. Read-only statements run against the last commit
+    /// without waiting for writers, any other statement runs as a transaction
+    /// of its own once no write transaction is open. Use
+    /// [`Database::begin`] rather than `BEGIN` for multi-statement
+    /// transactions.
+    pub fn execute_sql(&self, sql: &str) -> Result<QueryResult, FerrousDBError> {
+        let command = parse_sql(sql)?;
+        if command.is_read_only() {
+            let db = self.read();
+            return db.query_at(db.committed_snapshot(), command);
+        }
+        if command.is_transaction_control() {
+            return Err(FerrousDBError::TransactionError(
+                "use Database::begin to run a transaction on a shared database".to_string(),
+            ));
+        }
+        let _writer = self.lock_writer()?;
+        self.write().execute(command)
+    }
+
+    /// Runs a `SELECT` against the last commit and returns its columns and
+    /// rows, see [`FerrousDB::select`].
+    pub fn select(&self, sql: &str) -> Result<ResultSet, FerrousDBError> {
+        let command = parse_sql(sql)?;
+        let db = self.read();
+        db.select_at(db.committed_snapshot(), &command)
+    }
+
+    /// Returns a page of rows as of the last commit, see
+    /// [`FerrousDB::get_page`].
+    pub fn get_page(
+        &self,
+        table_name: &str,
+        page_number: usize,
+        page_size: usize,
+        filter: Option<&Expr>,
+        group_by: Option<String>,
+        order_by: Option<(String, bool)>,
+    ) -> Result<Vec<Row>, FerrousDBError> {
+        let db = self.read();
+        match db.get_committed_page(
+            table_name,
+            page_number,
+            page_size,
+            filter,
+            group_by,
+            order_by,
+        ) {
+            PageResult::TableNotFound => Err(FerrousDBError::TableNotFound(table_name.to_string())),
+            PageResult::PageOutOfRange => Err(FerrousDBError::ParseError(format!(
+                "Page number {} out of range for table '{}'",
+                page_number, table_name
+            ))),
+            PageResult::Error(e) => Err(e),
+            PageResult::Page(rows) => Ok(rows
+                .into_iter()
+                .map(|row| Row::new(row.data.clone()))
+                .collect()),
+        }
+    }
+
+    /// Starts a write transaction, waiting until the current one, if any,
+    /// has finished. Fails if the current one belongs to this thread.
+    pub fn begin(&self) -> Result<WriteTransaction<'_>, FerrousDBError> {
+        let writer = self.lock_writer()?;
+        self.write().begin()?;
+        *self.writer_thread() = Some(thread::current().id());
+        Ok(WriteTransaction {
+            database: self,
+            _writer: writer,
+            finished: false,
+        })
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, FerrousDB> {
+        self.db.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, FerrousDB> {
+        self.db.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Waits for the writer lock, unless this thread holds it through an
+    /// open [`WriteTransaction`] and would wait forever.
+    fn lock_writer(&self) -> Result<MutexGuard<'_, ()>, FerrousDBError> {
+        if *self.writer_thread() == Some(thread::current().id()) {
+            return Err(FerrousDBError::TransactionError(
+                "this thread already has an open write transaction".to_string(),
+            ));
+        }
+        Ok(self.writer.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn writer_thread(&self) -> MutexGuard<'_, Option<ThreadId>> {
+        self.writer_thread.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The write transaction of a [`Database`], rolled back when dropped without
+/// being committed. An error of that rollback is ignored, so call
+/// [`WriteTransaction::rollback`] to see it.
+///
+/// Readers don't see its changes until [`WriteTransaction::commit`], and the
+/// database is only locked while each statement runs, so they never wait for
+/// the whole transaction.
+pub struct WriteTransaction<'a> {
+    database: &'a Database,
+    _writer: MutexGuard<'a, ()>,
+    finished: bool,
+}
+
+impl WriteTransaction<'_> {
+    /// Runs a statement inside the transaction. Reads see the transaction's
+    /// own changes. `SAVEPOINT`, `RELEASE` and `ROLLBACK TO` are supported,
+    /// while the transaction itself ends with [`WriteTransaction::commit`] or
+    /// [`WriteTransaction::rollback`].
+    pub fn execute_sql(&mut self, sql: &str) -> Result<QueryResult, FerrousDBError> {
+        let command = parse_sql(sql)?;
+        if command.is_transaction_control() {
+            return Err(FerrousDBError::TransactionError(
+                "use WriteTransaction::commit or rollback to end the transaction".to_string(),
+            ));
+        }
+        self.database.write().execute(command)
+    }
+
+    pub fn commit(mut self) -> Result<(), FerrousDBError> {
+        self.finished = true;
+        self.database.write().commit()
+    }
+
+    pub fn rollback(mut self) -> Result<(), FerrousDBError> {
+        self.finished = true;
+        self.database.write().rollback()
+    }
+}
+
+impl Drop for WriteTransaction<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.database.write().rollback();
+        }
+        *self.database.writer_thread() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Barrier, thread};
+
+    fn count_users(db: &Database) -> usize {
+        db.get_page("users", 1, 10_000, None, None, None)
+            .map_or(0, |rows| rows.len())
+    }
+
+    #[test]
+    fn test_readers_do_not_see_open_transaction() {
+        let db = Database::open_in_memory();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        db.execute_sql("INSERT INTO users (name, age) VALUES ('Bob', 42)")
+            .unwrap();
+
+        let mut tx = db.begin().unwrap();
+        tx.execute_sql("INSERT INTO users (name, age) VALUES ('Eve', 7)")
+            .unwrap();
+        tx.execute_sql("UPDATE users SET age = 43 WHERE age = 42")
+            .unwrap();
+
+        let reader = db.clone();
+        let rows =
+            thread::spawn(move || reader.get_page("users", 1, 10, None, None, None).unwrap())
+                .join()
+                .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].data["age"], crate::DataType::Integer(42));
+        assert!(db.execute_sql("SELECT * FROM users").is_ok());
+
+        tx.commit().unwrap();
+        let rows = db.get_page("users", 1, 10, None, None, None).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].data["age"], crate::DataType::Integer(43));
+    }
+
+    #[test]
+    fn test_dropped_transaction_rolls_back() {
+        let db = Database::open_in_memory();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        {
+            let mut tx = db.begin().unwrap();
+            tx.execute_sql("INSERT INTO users (name, age) VALUES ('Eve', 7)")
+                .unwrap();
+            assert!(tx.execute_sql("COMMIT").is_err());
+        }
+        assert_eq!(count_users(&db), 0);
+        assert!(db.execute_sql("BEGIN").is_err());
+
+        // The thread holding the transaction gets an error instead of
+        // waiting for itself
+        let tx = db.begin().unwrap();
+        assert!(matches!(
+            db.execute_sql("INSERT INTO users (name, age) VALUES ('Bob', 42)"),
+            Err(FerrousDBError::TransactionError(_))
+        ));
+        assert!(matches!(
+            db.begin(),
+            Err(FerrousDBError::TransactionError(_))
+        ));
+        drop(tx);
+        db.execute_sql("INSERT INTO users (name, age) VALUES ('Bob', 42)")
+            .unwrap();
+        assert_eq!(count_users(&db), 1);
+
+        // The writer lock was released along with the transaction
+        let tx = db.begin().unwrap();
+        tx.rollback().unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_readers_see_whole_commits() {
+        let db = Database::open_in_memory();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+
+        const BATCHES: usize = 20;
+        const BATCH_SIZE: usize = 5;
+        let start = Arc::new(Barrier::new(3));
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let db = db.clone();
+                let start = Arc::clone(&start);
+                thread::spawn(move || {
+                    start.wait();
+                    loop {
+                        let count = count_users(&db);
+                        assert_eq!(count % BATCH_SIZE, 0, "saw a partial commit");
+                        if count == BATCHES * BATCH_SIZE {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        start.wait();
+        for batch in 0..BATCHES {
+            let mut tx = db.begin().unwrap();
+            for i in 0..BATCH_SIZE {
+                tx.execute_sql(&format!(
+                    "INSERT INTO users (name, age) VALUES ('user', {})",
+                    batch * BATCH_SIZE + i
+                ))
+                .unwrap();
+            }
+            tx.commit().unwrap();
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
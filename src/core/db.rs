@@ -1,33 +1,120 @@
 use std::{
-    collections::HashMap,
-    fs::File,
-    io::{self, Read, Write},
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{Arc, Mutex, MutexGuard},
 };
 
 use druid::Data;
-use serde::{Deserialize, Serialize};
 
 use super::{
     error_handling::FerrousDBError,
+    evaluator::{self, Subqueries},
+    expr::{Expr, Join, OrderByItem, SetOperator, TableRef},
     index::{Index, IndexType},
+    join::{self, JoinTable, Joined},
+    query::{self, Clauses, Outer, Scope},
+    result_set::{QueryResult, ResultSet},
     row::Row,
+    storage::{pager::AccessMode, Storage},
     table::{ColumnSchema, Constraint, Table},
-    write_ahead_log::WriteAheadLog,
+    transaction::{Savepoint, Snapshot, UndoEntry},
+    write_ahead_log::{LogEntry, LogRecord, WriteAheadLog},
+};
+use crate::{
+    core::parser::command::{Cte, InsertSource, InsertValue, SQLCommand},
+    core::parser::sql_parser::parse_sql,
+    DataType,
 };
-use crate::{core::parser::command::SQLCommand, core::parser::sql_parser::parse_sql, DataType};
 
 pub enum PageResult<'a> {
     TableNotFound,
     PageOutOfRange,
     Page(Vec<&'a Row>),
+    /// The filter refers to an unknown column or can't be evaluated.
+    Error(FerrousDBError),
+}
+
+/// The rows of a table the paging API reads: those for which `filter` holds,
+/// the first of each value of the `group_by` column, sorted by the
+/// `order_by` column, ascending or not, and skipping `offset` of them to
+/// return at most `limit`.
+struct RowQuery<'a> {
+    filter: Option<&'a Expr>,
+    group_by: Option<String>,
+    order_by: Option<(String, bool)>,
+    limit: Option<usize>,
+    offset: usize,
+}
+
+/// A row read by a query, borrowed from its table or built by a join.
+type SourceRow<'a> = Cow<'a, HashMap<String, DataType>>;
+
+/// The results of the common table expressions a query can read, by name.
+type Ctes = HashMap<String, Rc<Table>>;
+
+/// How many times the recursive term of a recursive CTE may run before the
+/// query fails, as it would otherwise never end.
+const MAX_RECURSIVE_ITERATIONS: usize = 1000;
+
+/// Runs the subqueries of a statement for the rows of its scope.
+struct SubqueryRunner<'a> {
+    db: &'a FerrousDB,
+    snapshot: Snapshot,
+    scope: &'a Scope,
+    ctes: Ctes,
+    /// Results of the subqueries that read nothing of the row, by their SQL,
+    /// so that they run only once.
+    results: RefCell<HashMap<String, Rc<ResultSet>>>,
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Clone)]
 /// Represents the FerrousDB database.
 pub struct FerrousDB {
     pub tables: HashMap<String, Table>,
     pub indexes: HashMap<String, Index>,
-    is_loaded: bool,
+    /// Database file backing this instance, `None` for in-memory databases.
+    path: Option<PathBuf>,
+    /// Write-ahead log file, `None` for in-memory databases.
+    wal_path: Option<PathBuf>,
+    /// Paged storage for the database file, `None` for in-memory databases.
+    storage: Option<Arc<Mutex<Storage>>>,
+    /// Set while a statement or recovery groups several mutations into a
+    /// single flush.
+    batching: bool,
+    /// Log size in bytes past which a statement triggers a checkpoint.
+    checkpoint_threshold: u64,
+    /// Id of the most recently started transaction.
+    last_txn_id: u64,
+    /// Changes made since the last commit, waiting to be logged.
+    pending_log: Vec<LogEntry>,
+    /// How to revert the changes in `pending_log`, oldest first.
+    undo_log: Vec<UndoEntry>,
+    /// Set between `BEGIN` and `COMMIT` or `ROLLBACK`. Changes are neither
+    /// logged nor flushed until the transaction commits.
+    in_transaction: bool,
+    /// Savepoints of the open transaction, oldest first.
+    savepoints: Vec<Savepoint>,
+    /// Set for databases opened with [`FerrousDB::open_read_only`].
+    read_only: bool,
+}
+
+/// Write-ahead log statistics reported by [`FerrousDB::wal_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalStats {
+    /// Current size of the log file in bytes.
+    pub log_size: u64,
+    /// LSN at which the last checkpoint started the current log file.
+    pub last_checkpoint_lsn: u64,
+}
+
+impl PartialEq for FerrousDB {
+    fn eq(&self, other: &Self) -> bool {
+        self.tables == other.tables && self.indexes == other.indexes && self.path == other.path
+    }
 }
 
 impl Data for FerrousDB {
@@ -42,36 +129,237 @@ impl Default for FerrousDB {
     }
 }
 
+/// Database file used by [`FerrousDB::new`].
+pub const DEFAULT_DB_PATH: &str = "data.ferrous";
+
+/// Log size that triggers an automatic checkpoint, 4 MiB.
+pub const DEFAULT_CHECKPOINT_THRESHOLD: u64 = 4 * 1024 * 1024;
+
 impl FerrousDB {
+    /// Opens the database stored in [`DEFAULT_DB_PATH`] in the current
+    /// directory, see [`FerrousDB::open`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the database can't be opened, e.g. while another process
+    /// holds its lock. Use [`FerrousDB::open`] to handle that error instead.
     pub fn new() -> Self {
-        match FerrousDB::load_from_file("data.ferrous") {
-            Ok(db) => db,
-            Err(_) => FerrousDB {
-                tables: HashMap::new(),
-                indexes: HashMap::new(),
-                is_loaded: false,
-            },
+        FerrousDB::open(DEFAULT_DB_PATH)
+            .unwrap_or_else(|e| panic!("could not open '{}': {}", DEFAULT_DB_PATH, e))
+    }
+
+    /// Opens the database stored at `path`, creating an empty one if the file
+    /// does not exist yet. The write-ahead log lives next to it with a `.log`
+    /// extension.
+    ///
+    /// The file stays exclusively locked until the database is dropped, so
+    /// opening it again, from this process or another one, fails with
+    /// [`FerrousDBError::DatabaseLocked`].
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, FerrousDBError> {
+        FerrousDB::open_with(path.as_ref(), AccessMode::ReadWrite)
+    }
+
+    /// Opens an existing database for reading only. Any number of read-only
+    /// handles can share the file, but not with a writer opened through
+    /// [`FerrousDB::open`]. Committed changes still in the write-ahead log are
+    /// applied in memory only.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self, FerrousDBError> {
+        FerrousDB::open_with(path.as_ref(), AccessMode::ReadOnly)
+    }
+
+    fn open_with(path: &Path, mode: AccessMode) -> Result<Self, FerrousDBError> {
+        let path = path.to_path_buf();
+        let mut storage = Storage::open(&path, mode).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::WouldBlock {
+                FerrousDBError::DatabaseLocked(path.display().to_string())
+            } else {
+                e.into()
+            }
+        })?;
+        let catalog = storage.read_catalog()?;
+
+        let mut db = FerrousDB::open_in_memory();
+        for entry in catalog.tables {
+            let rows = storage.scan(&entry.name)?;
+            let table = Table {
+                name: entry.name.clone(),
+                schema: entry.schema,
+                rows,
+            };
+            db.tables.insert(entry.name, table);
+        }
+        for entry in catalog.indexes {
+            let index = db.build_index(&entry.table_name, &entry.column_name, entry.index_type)?;
+            db.indexes
+                .insert(format!("{}_{}", entry.table_name, entry.column_name), index);
+        }
+
+        db.wal_path = Some(path.with_extension("log"));
+        db.path = Some(path);
+        db.storage = Some(Arc::new(Mutex::new(storage)));
+        db.recover(mode)?;
+        db.read_only = mode == AccessMode::ReadOnly;
+        Ok(db)
+    }
+
+    /// Replays the statements committed to the write-ahead log after the
+    /// database file was last flushed, then flushes the result. Read-only
+    /// databases leave both files untouched and keep the result in memory.
+    fn recover(&mut self, mode: AccessMode) -> Result<(), FerrousDBError> {
+        let (Some(wal_path), Some(mut storage)) = (self.wal_path.clone(), self.storage()) else {
+            return Ok(());
+        };
+        let start = storage.wal_position()?;
+        let checkpoint_lsn = storage.checkpoint_lsn()?;
+        let last_txn_id = storage.last_txn_id()?;
+        drop(storage);
+        self.last_txn_id = last_txn_id;
+
+        let recovered = match mode {
+            AccessMode::ReadWrite => {
+                WriteAheadLog::recover_rotation(&wal_path, checkpoint_lsn)?;
+                if fs::metadata(&wal_path).map_or(true, |m| m.len() == 0) {
+                    WriteAheadLog::prepare_rotation(&wal_path, checkpoint_lsn)?;
+                    WriteAheadLog::complete_rotation(&wal_path)?;
+                }
+                WriteAheadLog::recover(&wal_path, start, checkpoint_lsn)?
+            }
+            AccessMode::ReadOnly => {
+                WriteAheadLog::read_committed(&wal_path, start, checkpoint_lsn)?
+            }
+        };
+        self.last_txn_id = self.last_txn_id.max(recovered.last_txn_id);
+        if recovered.end == start {
+            return Ok(());
+        }
+
+        self.batching = true;
+        let result = recovered
+            .records
+            .into_iter()
+            .try_for_each(|record| self.replay(record));
+        self.batching = false;
+        self.pending_log.clear();
+        let undo_log = std::mem::take(&mut self.undo_log);
+        result?;
+        self.freeze(&undo_log)?;
+        match mode {
+            AccessMode::ReadWrite => self.flush_through(recovered.end),
+            AccessMode::ReadOnly => Ok(()),
+        }
+    }
+
+    fn replay(&mut self, record: LogRecord) -> Result<(), FerrousDBError> {
+        let result = match record.entry {
+            LogEntry::CreateTable { name, columns } => self.create_table(&name, columns),
+            LogEntry::CreateIndex {
+                table,
+                column,
+                index_type,
+            } => self.create_index(&table, &column, index_type),
+            LogEntry::Insert { table, row } => self.insert_into(&table, row),
+            LogEntry::Update { table, old, new } => self
+                .find_row(&table, &old)
+                .and_then(|row_idx| self.overwrite_row(&table, row_idx, new)),
+            LogEntry::Delete { table, row } => self
+                .find_row(&table, &row)
+                .and_then(|row_idx| self.remove_rows(&table, vec![row_idx])),
+            LogEntry::Commit | LogEntry::Checkpoint => Ok(()),
+        };
+        result.map_err(|e| {
+            FerrousDBError::RecoveryError(format!(
+                "failed to replay log record at LSN {}: {}",
+                record.lsn, e
+            ))
+        })
+    }
+
+    /// Finds the first row holding exactly `data`. Identical rows are
+    /// interchangeable, so any match is as good as the one that was logged.
+    fn find_row(
+        &self,
+        table_name: &str,
+        data: &HashMap<String, DataType>,
+    ) -> Result<usize, FerrousDBError> {
+        self.tables
+            .get(table_name)
+            .ok_or_else(|| FerrousDBError::TableNotFound(table_name.to_string()))?
+            .visible_rows(self.own_snapshot())
+            .find(|(_, row)| &row.data == data)
+            .map(|(row_idx, _)| row_idx)
+            .ok_or_else(|| {
+                FerrousDBError::RecoveryError(format!(
+                    "logged row not found in table '{}'",
+                    table_name
+                ))
+            })
+    }
+
+    /// Creates a database that lives only in memory and is never persisted.
+    pub fn open_in_memory() -> Self {
+        FerrousDB {
+            tables: HashMap::new(),
+            indexes: HashMap::new(),
+            path: None,
+            wal_path: None,
+            storage: None,
+            batching: false,
+            checkpoint_threshold: DEFAULT_CHECKPOINT_THRESHOLD,
+            last_txn_id: 0,
+            pending_log: Vec::new(),
+            undo_log: Vec::new(),
+            in_transaction: false,
+            savepoints: Vec::new(),
+            read_only: false,
         }
     }
 
+    /// Returns the database file path, or `None` for in-memory databases.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
     pub fn create_table(
         &mut self,
         name: &str,
         columns: Vec<ColumnSchema>,
     ) -> Result<(), FerrousDBError> {
+        self.check_writable()?;
         if self.tables.contains_key(name) {
             return Err(FerrousDBError::TableExists(name.to_string()));
         }
 
         let table = Table {
             name: name.to_string(),
-            schema: columns,
+            schema: columns.clone(),
             rows: Vec::new(),
         };
+        for column in &columns {
+            for constraint in &column.constraints {
+                match constraint {
+                    Constraint::Check(expr) => {
+                        Scope::table(&table, name).bind_condition(expr)?;
+                    }
+                    Constraint::Default(value) if value.get_type() != column.data_type => {
+                        return Err(FerrousDBError::TypeMismatch(column.name.clone()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if let Some(mut storage) = self.storage() {
+            storage.create_heap(name)?;
+        }
         self.tables.insert(name.to_string(), table);
-        self.save_to_file("data.ferrous")
-            .expect("Failed to save to file");
-        Ok(())
+        self.undo_log.push(UndoEntry::CreateTable {
+            name: name.to_string(),
+        });
+        self.write_catalog()?;
+        self.pending_log.push(LogEntry::CreateTable {
+            name: name.to_string(),
+            columns,
+        });
+        self.persist()
     }
 
     pub fn create_index(
@@ -80,6 +368,29 @@ impl FerrousDB {
         column_name: &str,
         index_type: IndexType,
     ) -> Result<(), FerrousDBError> {
+        self.check_writable()?;
+        let index = self.build_index(table_name, column_name, index_type.clone())?;
+        let index_name = format!("{}_{}", table_name, column_name);
+        let replaced = self.indexes.insert(index_name.clone(), index);
+        self.undo_log.push(UndoEntry::CreateIndex {
+            name: index_name,
+            replaced,
+        });
+        self.write_catalog()?;
+        self.pending_log.push(LogEntry::CreateIndex {
+            table: table_name.to_string(),
+            column: column_name.to_string(),
+            index_type,
+        });
+        self.persist()
+    }
+
+    fn build_index(
+        &self,
+        table_name: &str,
+        column_name: &str,
+        index_type: IndexType,
+    ) -> Result<Index, FerrousDBError> {
         let table = self
             .tables
             .get(table_name)
@@ -90,19 +401,16 @@ impl FerrousDB {
             return Err(FerrousDBError::ColumnNotFound(column_name.to_string()));
         }
 
-        let index_name = format!("{}_{}", table_name, column_name);
         let mut index = Index::new(table_name.to_string(), column_name.to_string(), index_type);
 
         // Build initial index
-        for (row_idx, row) in table.rows.iter().enumerate() {
+        for (row_idx, row) in table.visible_rows(self.own_snapshot()) {
             if let Some(value) = row.data.get(column_name) {
                 index.insert(value.clone(), row_idx);
             }
         }
 
-        self.indexes.insert(index_name, index);
-        self.save_to_file("data.ferrous")?;
-        Ok(())
+        Ok(index)
     }
 
     fn validate_constraints(
@@ -126,20 +434,6 @@ impl FerrousDB {
                             )));
                         }
                     }
-                    Constraint::Unique => {
-                        if let Some(value) = values.get(&column.name) {
-                            for row in &table.rows {
-                                if let Some(existing_value) = row.data.get(&column.name) {
-                                    if existing_value == value {
-                                        return Err(FerrousDBError::ConstraintViolation(format!(
-                                            "UNIQUE constraint failed: {}",
-                                            column.name
-                                        )));
-                                    }
-                                }
-                            }
-                        }
-                    }
                     Constraint::ForeignKey {
                         ref_table,
                         ref_column,
@@ -150,13 +444,15 @@ impl FerrousDB {
                                 .get(ref_table)
                                 .ok_or_else(|| FerrousDBError::TableNotFound(ref_table.clone()))?;
 
-                            let found = referenced_table.rows.iter().any(|row| {
-                                if let Some(ref_value) = row.data.get(ref_column) {
-                                    ref_value == value
-                                } else {
-                                    false
-                                }
-                            });
+                            let found = referenced_table
+                                .visible_rows(self.own_snapshot())
+                                .any(|(_, row)| {
+                                    if let Some(ref_value) = row.data.get(ref_column) {
+                                        ref_value == value
+                                    } else {
+                                        false
+                                    }
+                                });
 
                             if !found {
                                 return Err(FerrousDBError::ConstraintViolation(format!(
@@ -173,10 +469,60 @@ impl FerrousDB {
         Ok(())
     }
 
+    /// Fails if the values of a UNIQUE column in `rows` repeat each other or
+    /// a row of the table. Each such column is read once however many rows
+    /// there are.
+    fn check_unique(
+        &self,
+        table_name: &str,
+        rows: &[HashMap<String, DataType>],
+    ) -> Result<(), FerrousDBError> {
+        let table = self
+            .tables
+            .get(table_name)
+            .ok_or_else(|| FerrousDBError::TableNotFound(table_name.to_string()))?;
+
+        let unique_columns = table.schema.iter().filter(|column| {
+            column
+                .constraints
+                .iter()
+                .any(|constraint| matches!(constraint, Constraint::Unique))
+        });
+        for column in unique_columns {
+            if rows.iter().all(|row| !row.contains_key(&column.name)) {
+                continue;
+            }
+            let mut seen: HashSet<&DataType> = table
+                .visible_rows(self.own_snapshot())
+                .filter_map(|(_, row)| row.data.get(&column.name))
+                .collect();
+            for value in rows.iter().filter_map(|row| row.get(&column.name)) {
+                if !seen.insert(value) {
+                    return Err(FerrousDBError::ConstraintViolation(format!(
+                        "UNIQUE constraint failed: {}",
+                        column.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn insert_into(
         &mut self,
         table_name: &str,
         values: HashMap<String, DataType>,
+    ) -> Result<(), FerrousDBError> {
+        self.check_writable()?;
+        self.check_unique(table_name, std::slice::from_ref(&values))?;
+        self.append_row(table_name, values)
+    }
+
+    /// Inserts a row whose UNIQUE columns were already checked.
+    fn append_row(
+        &mut self,
+        table_name: &str,
+        values: HashMap<String, DataType>,
     ) -> Result<(), FerrousDBError> {
         // Validate constraints before inserting
         self.validate_constraints(table_name, &values)?;
@@ -193,493 +539,3648 @@ impl FerrousDB {
                     return Err(FerrousDBError::TypeMismatch(column_name.clone()));
                 }
             }
+            check_row(table, &values)?;
             let row_index = table.rows.len();
-            let row = Row {
-                data: values.clone(),
-            };
+            let mut row = Row::new(values.clone());
+            row.xmin = self.last_txn_id + 1;
+            if let Some(storage) = &self.storage {
+                let mut storage = storage.lock().unwrap_or_else(|e| e.into_inner());
+                row.location = Some(storage.insert_row(table_name, &row)?);
+            }
             table.rows.push(row);
+            self.undo_log.push(UndoEntry::Insert {
+                table: table_name.to_string(),
+                position: row_index,
+            });
 
             // Update indexes
-            for (column_name, value) in values {
+            for (column_name, value) in &values {
                 let index_name = format!("{}_{}", table_name, column_name);
                 if let Some(index) = self.indexes.get_mut(&index_name) {
-                    index.insert(value, row_index);
+                    index.insert(value.clone(), row_index);
                 }
             }
 
-            self.save_to_file("data.ferrous")?;
-            Ok(())
+            self.pending_log.push(LogEntry::Insert {
+                table: table_name.to_string(),
+                row: values,
+            });
+            self.persist()
         } else {
             Err(FerrousDBError::TableNotFound(table_name.to_string()))
         }
     }
 
+    /// Inserts the rows of `source` into a table, giving `columns` the values
+    /// of each row in order, or all of its columns when there are none, and
+    /// returns how many there were. The query of an `INSERT ... SELECT` sees
+    /// the tables as they were before the first row is inserted.
+    fn insert_rows(
+        &mut self,
+        table_name: &str,
+        columns: &[String],
+        source: InsertSource,
+    ) -> Result<usize, FerrousDBError> {
+        let table = self
+            .tables
+            .get(table_name)
+            .ok_or_else(|| FerrousDBError::TableNotFound(table_name.to_string()))?;
+        let columns: Vec<String> = if columns.is_empty() {
+            table
+                .schema
+                .iter()
+                .map(|column| column.name.clone())
+                .collect()
+        } else {
+            for (i, column) in columns.iter().enumerate() {
+                if !table.schema.iter().any(|c| &c.name == column) {
+                    return Err(FerrousDBError::ColumnNotFound(column.clone()));
+                }
+                if columns[..i].contains(column) {
+                    return Err(FerrousDBError::ParseError(format!(
+                        "Column '{}' specified more than once",
+                        column
+                    )));
+                }
+            }
+            columns.to_vec()
+        };
+        // Every row starts from the defaults, NULL leaving a column out
+        let defaults: HashMap<String, DataType> = table
+            .schema
+            .iter()
+            .filter_map(|column| Some((column.name.clone(), column.default_value()?.clone())))
+            .collect();
+
+        let rows = match source {
+            InsertSource::Values(rows) => rows,
+            InsertSource::Query(query) => {
+                let result = self.select_at(self.own_snapshot(), &query)?;
+                check_insert_width(result.columns.len(), columns.len())?;
+                result
+                    .rows
+                    .into_iter()
+                    .map(|row| {
+                        row.into_iter()
+                            .map(|value| value.map_or(InsertValue::Null, InsertValue::Value))
+                            .collect()
+                    })
+                    .collect()
+            }
+            InsertSource::DefaultValues => {
+                self.insert_into(table_name, defaults)?;
+                return Ok(1);
+            }
+        };
+        let mut new_rows = Vec::with_capacity(rows.len());
+        for row in rows {
+            check_insert_width(row.len(), columns.len())?;
+            let mut values = defaults.clone();
+            for (column, value) in columns.iter().zip(row) {
+                match value {
+                    InsertValue::Value(value) => {
+                        values.insert(column.clone(), value);
+                    }
+                    InsertValue::Null => {
+                        values.remove(column);
+                    }
+                    InsertValue::Default => {}
+                }
+            }
+            new_rows.push(values);
+        }
+
+        // The whole batch is checked at once, including against itself
+        self.check_unique(table_name, &new_rows)?;
+        let count = new_rows.len();
+        for values in new_rows {
+            self.append_row(table_name, values)?;
+        }
+        Ok(count)
+    }
+
     pub fn update(
         &mut self,
         table_name: &str,
         assignments: HashMap<String, DataType>,
-        condition: Option<String>,
+        condition: Option<Expr>,
     ) -> Result<usize, FerrousDBError> {
+        self.check_writable()?;
         // Validate constraints before updating
         self.validate_constraints(table_name, &assignments)?;
+        self.check_unique(table_name, std::slice::from_ref(&assignments))?;
 
         let table = self
             .tables
-            .get_mut(table_name)
+            .get(table_name)
             .ok_or_else(|| FerrousDBError::TableNotFound(table_name.to_string()))?;
 
-        let mut updated_count = 0;
-        for (row_idx, row) in table.rows.iter_mut().enumerate() {
+        for col in assignments.keys() {
+            if !table.schema.iter().any(|c| &c.name == col) {
+                return Err(FerrousDBError::ColumnNotFound(col.clone()));
+            }
+        }
+
+        let scope = Scope::table(table, table_name);
+        let condition = condition
+            .map(|condition| scope.bind_condition(&condition))
+            .transpose()?;
+        let subqueries = SubqueryRunner::new(self, self.own_snapshot(), &scope, Ctes::new());
+
+        let mut rows_to_update = Vec::new();
+        for (row_idx, row) in table.visible_rows(self.own_snapshot()) {
             let should_update = match &condition {
-                Some(cond) => {
-                    let parts: Vec<&str> = cond.split('=').collect();
-                    if parts.len() != 2 {
-                        return Err(FerrousDBError::ParseError(
-                            "Invalid condition format".to_string(),
-                        ));
-                    }
-                    if let Some(value) = row.data.get(parts[0].trim()) {
-                        match value {
-                            DataType::Integer(n) => {
-                                if let Ok(comparison_value) = parts[1].trim().trim_matches('\'').parse::<i64>() {
-                                    *n == comparison_value
-                                } else {
-                                    false
-                                }
-                            },
-                            DataType::Text(s) => s == parts[1].trim().trim_matches('\''),
-                            DataType::Boolean(b) => {
-                                if let Ok(comparison_value) = parts[1].trim().trim_matches('\'').parse::<bool>() {
-                                    *b == comparison_value
-                                } else {
-                                    false
-                                }
-                            }
-                        }
-                    } else {
-                        false
-                    }
-                }
+                Some(condition) => evaluator::matches_with(condition, &row.data, &subqueries)?,
                 None => true,
             };
 
             if should_update {
-                // Update indexes before modifying the row
-                for (col, new_value) in &assignments {
-                    let index_name = format!("{}_{}", table_name, col);
-                    if let Some(index) = self.indexes.get_mut(&index_name) {
-                        if let Some(old_value) = row.data.get(col) {
-                            index.update(old_value, new_value.clone(), row_idx);
-                        }
-                    }
-                }
-
-                // Update the row
+                let mut new_data = row.data.clone();
                 for (col, value) in &assignments {
-                    if !table.schema.iter().any(|c| &c.name == col) {
-                        return Err(FerrousDBError::ColumnNotFound(col.clone()));
-                    }
-                    row.data.insert(col.clone(), value.clone());
+                    new_data.insert(col.clone(), value.clone());
                 }
-                updated_count += 1;
+                check_row(table, &new_data)?;
+                rows_to_update.push((row_idx, new_data));
             }
         }
 
-        self.save_to_file("data.ferrous")?;
+        let updated_count = rows_to_update.len();
+        for (row_idx, new_data) in rows_to_update {
+            self.overwrite_row(table_name, row_idx, new_data)?;
+        }
+
+        self.persist()?;
         Ok(updated_count)
     }
 
     pub fn delete_from(
         &mut self,
         table_name: &str,
-        condition: Option<String>,
+        condition: Option<Expr>,
     ) -> Result<usize, FerrousDBError> {
+        self.check_writable()?;
         let table = self
             .tables
-            .get_mut(table_name)
+            .get(table_name)
             .ok_or_else(|| FerrousDBError::TableNotFound(table_name.to_string()))?;
 
-        let initial_count = table.rows.len();
+        let scope = Scope::table(table, table_name);
+        let condition = condition
+            .map(|condition| scope.bind_condition(&condition))
+            .transpose()?;
+        let subqueries = SubqueryRunner::new(self, self.own_snapshot(), &scope, Ctes::new());
         let mut rows_to_delete = Vec::new();
-
-        // First pass: identify rows to delete
-        if let Some(cond) = condition {
-            let parts: Vec<&str> = cond.split('=').collect();
-            if parts.len() != 2 {
-                return Err(FerrousDBError::ParseError(
-                    "Invalid condition format".to_string(),
-                ));
-            }
-            let column = parts[0].trim();
-            let value = parts[1].trim().trim_matches('\'');
-
-            for (idx, row) in table.rows.iter().enumerate() {
-                if let Some(row_value) = row.data.get(column) {
-                    let should_delete = match row_value {
-                        DataType::Integer(n) => {
-                            if let Ok(comparison_value) = value.parse::<i64>() {
-                                *n == comparison_value
-                            } else {
-                                false
-                            }
-                        },
-                        DataType::Text(s) => s == value,
-                        DataType::Boolean(b) => {
-                            if let Ok(comparison_value) = value.parse::<bool>() {
-                                *b == comparison_value
-                            } else {
-                                false
-                            }
-                        }
-                    };
-                    if should_delete {
-                        rows_to_delete.push(idx);
-                    }
-                }
+        for (idx, row) in table.visible_rows(self.own_snapshot()) {
+            let should_delete = match &condition {
+                Some(condition) => evaluator::matches_with(condition, &row.data, &subqueries)?,
+                None => true,
+            };
+            if should_delete {
+                rows_to_delete.push(idx);
             }
-        } else {
-            rows_to_delete.extend(0..table.rows.len());
         }
 
-        // Update indexes
-        for idx in &rows_to_delete {
-            let row = &table.rows[*idx];
-            for (col_name, value) in &row.data {
-                let index_name = format!("{}_{}", table_name, col_name);
-                if let Some(index) = self.indexes.get_mut(&index_name) {
-                    index.remove(value, *idx);
+        let deleted_count = rows_to_delete.len();
+        self.remove_rows(table_name, rows_to_delete)?;
+        self.persist()?;
+        Ok(deleted_count)
+    }
+
+    /// Replaces the data of one row, keeping its indexes, its page and the
+    /// log in step.
+    fn overwrite_row(
+        &mut self,
+        table_name: &str,
+        row_idx: usize,
+        new_data: HashMap<String, DataType>,
+    ) -> Result<(), FerrousDBError> {
+        let txn_id = self.last_txn_id + 1;
+        let table = self
+            .tables
+            .get_mut(table_name)
+            .ok_or_else(|| FerrousDBError::TableNotFound(table_name.to_string()))?;
+        let row = &mut table.rows[row_idx];
+
+        for (col, new_value) in &new_data {
+            let index_name = format!("{}_{}", table_name, col);
+            if let Some(index) = self.indexes.get_mut(&index_name) {
+                match row.data.get(col) {
+                    Some(old_value) => index.update(old_value, new_value.clone(), row_idx),
+                    None => index.insert(new_value.clone(), row_idx),
                 }
             }
         }
 
-        // Remove rows in reverse order to maintain correct indices
-        rows_to_delete.sort_unstable_by(|a, b| b.cmp(a));
-        for idx in rows_to_delete {
-            table.rows.remove(idx);
+        let old = row.clone();
+        // Readers keep seeing the version from before this transaction until
+        // it commits
+        if row.xmin != txn_id {
+            let mut previous = row.clone();
+            previous.xmax = Some(txn_id);
+            row.previous = Some(Box::new(previous));
+            row.xmin = txn_id;
         }
-
-        let deleted_count = initial_count - table.rows.len();
-        self.save_to_file("data.ferrous")?;
-        Ok(deleted_count)
+        let old_data = std::mem::replace(&mut row.data, new_data);
+        if let (Some(storage), Some(location)) = (&self.storage, row.location) {
+            let mut storage = storage.lock().unwrap_or_else(|e| e.into_inner());
+            row.location = Some(storage.update_row(table_name, location, row)?);
+        }
+        self.pending_log.push(LogEntry::Update {
+            table: table_name.to_string(),
+            old: old_data,
+            new: row.data.clone(),
+        });
+        self.undo_log.push(UndoEntry::Update {
+            table: table_name.to_string(),
+            position: row_idx,
+            old,
+        });
+        Ok(())
     }
 
-    pub fn get_page(
+    /// Marks the rows at the given positions deleted and rebuilds the
+    /// table's indexes. The rows stay in place for readers of older snapshots
+    /// until the deletion commits.
+    fn remove_rows(
         &mut self,
         table_name: &str,
-        page_number: usize,
-        page_size: usize,
-        group_by: Option<String>,
-        order_by: Option<(String, bool)>,
-    ) -> PageResult {
-        if !self.is_loaded {
-            if let Ok(mut db) = FerrousDB::load_from_file("data.ferrous") {
-                self.tables.extend(db.tables.drain());
-                self.is_loaded = true;
-            }
-        }
-
-        if let Some(table) = self.tables.get(table_name) {
-            let mut rows: Vec<&Row> = table.rows.iter().collect();
-
-            // Apply GROUP BY if specified
-            if let Some(group_by_col) = group_by {
-                let mut grouped_rows = HashMap::new();
-                for row in rows {
-                    if let Some(value) = row.data.get(&group_by_col) {
-                        grouped_rows
-                            .entry(value.clone())
-                            .or_insert_with(Vec::new)
-                            .push(row);
-                    }
-                }
-                rows = grouped_rows
-                    .into_iter()
-                    .map(|(_, group)| group[0])
-                    .collect();
-            }
-
-            // Apply ORDER BY if specified
-            if let Some((col, is_ascending)) = order_by {
-                rows.sort_by(|a, b| {
-                    let a_val = a.data.get(&col).map(|v| v.get_value());
-                    let b_val = b.data.get(&col).map(|v| v.get_value());
-                    if is_ascending {
-                        a_val.cmp(&b_val)
-                    } else {
-                        b_val.cmp(&a_val)
-                    }
-                });
-            }
-
-            let start = (page_number - 1) * page_size;
-            let end = start + page_size;
+        mut rows_to_delete: Vec<usize>,
+    ) -> Result<(), FerrousDBError> {
+        let txn_id = self.last_txn_id + 1;
+        let table = self
+            .tables
+            .get_mut(table_name)
+            .ok_or_else(|| FerrousDBError::TableNotFound(table_name.to_string()))?;
 
-            if start >= rows.len() {
-                PageResult::PageOutOfRange
-            } else {
-                PageResult::Page(rows[start..end.min(rows.len())].to_vec())
+        // Log in table order so replay finds each row where it was
+        rows_to_delete.sort_unstable();
+        rows_to_delete.dedup();
+        for idx in rows_to_delete {
+            let row = &mut table.rows[idx];
+            self.undo_log.push(UndoEntry::Delete {
+                table: table_name.to_string(),
+                position: idx,
+                row: row.clone(),
+            });
+            if let (Some(storage), Some(location)) = (&self.storage, row.location) {
+                let mut storage = storage.lock().unwrap_or_else(|e| e.into_inner());
+                storage.delete_row(table_name, location)?;
             }
-        } else {
-            PageResult::TableNotFound
+            row.xmax = Some(txn_id);
+            self.pending_log.push(LogEntry::Delete {
+                table: table_name.to_string(),
+                row: row.data.clone(),
+            });
         }
+
+        self.reindex(table_name)
     }
 
-    pub fn total_pages(&self, table_name: &str, page_size: usize) -> Option<usize> {
-        self.tables
+    fn reindex(&mut self, table_name: &str) -> Result<(), FerrousDBError> {
+        let stale: Vec<(String, String, IndexType)> = self
+            .indexes
             .iter()
-            .find(|t| t.1.name == table_name)
-            .map(|table| table.1.total_pages(page_size))
+            .filter(|(_, index)| index.table_name() == table_name)
+            .map(|(name, index)| {
+                (
+                    name.clone(),
+                    index.column_name().to_string(),
+                    index.index_type().clone(),
+                )
+            })
+            .collect();
+        for (index_name, column_name, index_type) in stale {
+            let index = self.build_index(table_name, &column_name, index_type)?;
+            self.indexes.insert(index_name, index);
+        }
+        Ok(())
     }
 
-    pub fn execute_sql(&mut self, sql: &str) -> Result<String, FerrousDBError> {
-        let mut wal = WriteAheadLog::new("ferrousdb.log").unwrap();
-        wal.log(sql).unwrap();
-        let command = parse_sql(sql)?;
-        match command {
-            SQLCommand::CreateTable { name, columns } => {
-                let columns_ref: Vec<ColumnSchema> = columns;
-                self.create_table(&name, columns_ref).unwrap();
-                Ok(format!("Table '{}' created successfully", name))
-            }
-            SQLCommand::InsertInto { table, values } => {
-                self.insert_into(&table, values).unwrap();
-                Ok(format!("Data inserted into table '{}' successfully", table))
-            }
-            SQLCommand::SelectFrom {
-                table,
-                page_size,
-                page,
+    /// Returns a page of rows as this instance sees them, including the
+    /// changes of its open transaction.
+    pub fn get_page(
+        &self,
+        table_name: &str,
+        page_number: usize,
+        page_size: usize,
+        filter: Option<&Expr>,
+        group_by: Option<String>,
+        order_by: Option<(String, bool)>,
+    ) -> PageResult<'_> {
+        self.page_at(
+            self.own_snapshot(),
+            table_name,
+            page_number,
+            page_size,
+            RowQuery {
+                filter,
                 group_by,
                 order_by,
-            } => match self.get_page(&table, page, page_size, group_by, order_by) {
-                PageResult::TableNotFound => Err(FerrousDBError::TableNotFound(format!(
-                    "Table '{}' not found",
-                    table
-                ))),
-                PageResult::PageOutOfRange => Err(FerrousDBError::ParseError(format!(
-                    "Page number {} out of range for table '{}'",
-                    page, table
-                ))),
-                PageResult::Page(rows) => {
-                    for row in rows {
-                        println!("{:?}", row);
-                    }
-                    if let Some(total_pages) = self.total_pages(&table, page_size) {
-                        println!("Page {} of {}", page, total_pages);
-                    }
-                    Ok(format!("Data selected from table '{}' successfully", table))
-                }
-            },
-            SQLCommand::DeleteFrom { table, condition } => {
-                match self.delete_from(&table, condition) {
-                    Ok(count) => Ok(format!("{} row(s) deleted from table '{}'", count, table)),
-                    Err(e) => Err(e),
-                }
-            }
-            SQLCommand::Update {
-                table,
-                assignments,
-                condition,
-            } => match self.update(&table, assignments, condition) {
-                Ok(count) => Ok(format!("{} row(s) updated in table '{}'", count, table)),
-                Err(e) => Err(e),
+                limit: None,
+                offset: 0,
             },
-            SQLCommand::CreateView {
-                name,
-                query,
-                columns,
-            } => {
-                // Por enquanto apenas retornamos um erro indicando que a feature não está implementada
-                Err(FerrousDBError::ParseError(
-                    "Views are not implemented yet".to_string(),
-                ))
-            }
-        }
+        )
     }
 
-    fn save_to_file(&self, filename: &str) -> io::Result<()> {
-        let encoded: Vec<u8> = bincode::serialize(&self).expect("Failed to serialize database");
-        let mut file = File::create(filename)?;
-        file.write_all(&encoded)?;
-        Ok(())
-    }
-
-    fn load_from_file(filename: &str) -> io::Result<Self> {
-        let mut file = File::open(filename)?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
-        let db: FerrousDB = bincode::deserialize(&buffer).expect("Failed to deserialize database");
-        Ok(db)
+    /// Returns a page of rows as of the last commit, leaving out the changes
+    /// of a transaction that is still open.
+    pub fn get_committed_page(
+        &self,
+        table_name: &str,
+        page_number: usize,
+        page_size: usize,
+        filter: Option<&Expr>,
+        group_by: Option<String>,
+        order_by: Option<(String, bool)>,
+    ) -> PageResult<'_> {
+        self.page_at(
+            self.committed_snapshot(),
+            table_name,
+            page_number,
+            page_size,
+            RowQuery {
+                filter,
+                group_by,
+                order_by,
+                limit: None,
+                offset: 0,
+            },
+        )
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Returns page `page_number` of the rows `row_query` reads, counting
+    /// from 1, in place of its own limit and offset.
+    fn page_at(
+        &self,
+        snapshot: Snapshot,
+        table_name: &str,
+        page_number: usize,
+        page_size: usize,
+        row_query: RowQuery,
+    ) -> PageResult<'_> {
+        if let Err(e) = check_page_size(page_size) {
+            return PageResult::Error(e);
+        }
+        let Some(offset) = page_number
+            .checked_sub(1)
+            .and_then(|page| page.checked_mul(page_size))
+        else {
+            return PageResult::PageOutOfRange;
+        };
+        let row_query = RowQuery {
+            limit: Some(page_size),
+            offset,
+            ..row_query
+        };
+        match self.rows_at(snapshot, table_name, row_query) {
+            Ok(rows) if rows.is_empty() => PageResult::PageOutOfRange,
+            Ok(rows) => PageResult::Page(rows),
+            Err(FerrousDBError::TableNotFound(_)) => PageResult::TableNotFound,
+            Err(e) => PageResult::Error(e),
+        }
+    }
+
+    /// The rows `row_query` reads of a table visible to `snapshot`.
+    fn rows_at(
+        &self,
+        snapshot: Snapshot,
+        table_name: &str,
+        row_query: RowQuery,
+    ) -> Result<Vec<&Row>, FerrousDBError> {
+        let table = self
+            .tables
+            .get(table_name)
+            .ok_or_else(|| FerrousDBError::TableNotFound(table_name.to_string()))?;
+        let mut rows = self.filtered_rows(snapshot, table, row_query.filter)?;
+
+        // Apply GROUP BY if specified, keeping the first row of each group
+        if let Some(group_by_col) = row_query.group_by {
+            let mut seen = HashSet::new();
+            rows.retain(|row| match row.data.get(&group_by_col) {
+                Some(value) => seen.insert(value.clone()),
+                None => false,
+            });
+        }
+
+        // Apply ORDER BY if specified
+        if let Some((col, is_ascending)) = row_query.order_by {
+            let order = OrderByItem::new(Expr::column(&col), is_ascending);
+            rows.sort_by(|a, b| order.compare(a.data.get(&col), b.data.get(&col)));
+        }
+
+        query::limit(&mut rows, row_query.limit, row_query.offset);
+        Ok(rows)
+    }
+
+    /// The rows of `table` visible to `snapshot` for which `filter` holds.
+    fn filtered_rows<'a>(
+        &self,
+        snapshot: Snapshot,
+        table: &'a Table,
+        filter: Option<&Expr>,
+    ) -> Result<Vec<&'a Row>, FerrousDBError> {
+        let rows = table.visible_rows(snapshot).map(|(_, row)| row);
+        let Some(filter) = filter else {
+            return Ok(rows.collect());
+        };
+        let filter = Scope::table(table, &table.name).bind_condition(filter)?;
+        let mut matching = Vec::new();
+        for row in rows {
+            if evaluator::matches(&filter, &row.data)? {
+                matching.push(row);
+            }
+        }
+        Ok(matching)
+    }
+
+    /// Returns how many pages of `page_size` rows the table fills, as this
+    /// instance sees it.
+    pub fn total_pages(&self, table_name: &str, page_size: usize) -> Result<usize, FerrousDBError> {
+        self.total_pages_at(self.own_snapshot(), table_name, page_size, None)
+    }
+
+    fn total_pages_at(
+        &self,
+        snapshot: Snapshot,
+        table_name: &str,
+        page_size: usize,
+        filter: Option<&Expr>,
+    ) -> Result<usize, FerrousDBError> {
+        check_page_size(page_size)?;
+        let table = self
+            .tables
+            .get(table_name)
+            .ok_or_else(|| FerrousDBError::TableNotFound(table_name.to_string()))?;
+        let row_count = self.filtered_rows(snapshot, table, filter)?.len();
+        Ok(row_count.div_ceil(page_size))
+    }
+
+    /// Sets the log size in bytes past which statements trigger an automatic
+    /// checkpoint.
+    pub fn set_checkpoint_threshold(&mut self, bytes: u64) {
+        self.checkpoint_threshold = bytes;
+    }
+
+    pub fn wal_stats(&self) -> Result<WalStats, FerrousDBError> {
+        let (Some(wal_path), Some(mut storage)) = (&self.wal_path, self.storage()) else {
+            return Ok(WalStats {
+                log_size: 0,
+                last_checkpoint_lsn: 0,
+            });
+        };
+        Ok(WalStats {
+            log_size: fs::metadata(wal_path).map_or(0, |m| m.len()),
+            last_checkpoint_lsn: storage.checkpoint_lsn()?,
+        })
+    }
+
+    /// Makes every logged change durable in the database file and starts a new,
+    /// empty log. Returns the LSN of the checkpoint.
+    ///
+    /// LSNs are byte positions in the stream of all logs ever written, so the
+    /// new log starts at the previous checkpoint LSN plus the old log's size.
+    pub fn checkpoint(&mut self) -> Result<u64, FerrousDBError> {
+        self.check_writable()?;
+        if self.in_transaction {
+            return Err(FerrousDBError::TransactionError(
+                "CHECKPOINT cannot run inside a transaction".to_string(),
+            ));
+        }
+        let (Some(wal_path), Some(mut storage)) = (&self.wal_path, self.storage()) else {
+            return Ok(0);
+        };
+        storage.flush()?;
+
+        let log_size = fs::metadata(wal_path).map_or(0, |m| m.len());
+        let lsn = storage.checkpoint_lsn()? + log_size;
+        let marker_len = WriteAheadLog::prepare_rotation(wal_path, lsn)?;
+        storage.set_checkpoint_lsn(lsn)?;
+        storage.set_wal_position(marker_len)?;
+        storage.flush()?;
+        WriteAheadLog::complete_rotation(wal_path)?;
+        Ok(lsn)
+    }
+
+    /// Runs a statement. A `SELECT` returns its rows, an `INSERT`, `UPDATE`
+    /// or `DELETE` the number of rows it changed, and any other statement a
+    /// message saying what it did.
+    pub fn execute_sql(&mut self, sql: &str) -> Result<QueryResult, FerrousDBError> {
+        self.execute(parse_sql(sql)?)
+    }
+
+    /// Runs an already parsed statement, see [`FerrousDB::execute_sql`].
+    pub(crate) fn execute(&mut self, command: SQLCommand) -> Result<QueryResult, FerrousDBError> {
+        if command.is_read_only() {
+            return self.query_at(self.own_snapshot(), command);
+        }
+        self.check_writable()?;
+
+        // All changes made by the statement are logged as one transaction
+        let (undo_mark, log_mark) = (self.undo_log.len(), self.pending_log.len());
+        self.batching = true;
+        let result = self.execute_command(command);
+        self.batching = false;
+        match result {
+            Ok(result) => {
+                self.persist()?;
+                Ok(result)
+            }
+            Err(e) => {
+                // A failed statement leaves no changes behind
+                if self.in_transaction {
+                    self.undo_to(undo_mark, log_mark)?;
+                } else {
+                    self.discard_changes()?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Starts a transaction. Changes made until [`FerrousDB::commit`] are
+    /// only visible to this instance and never reach the log or the database
+    /// file unless committed.
+    pub fn begin(&mut self) -> Result<(), FerrousDBError> {
+        self.check_writable()?;
+        if self.in_transaction {
+            return Err(FerrousDBError::TransactionError(
+                "a transaction is already active".to_string(),
+            ));
+        }
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    /// Logs every change made since [`FerrousDB::begin`] as one transaction
+    /// and makes it durable.
+    pub fn commit(&mut self) -> Result<(), FerrousDBError> {
+        if !self.in_transaction {
+            return Err(FerrousDBError::TransactionError(
+                "no transaction is active".to_string(),
+            ));
+        }
+        self.in_transaction = false;
+        self.savepoints.clear();
+        self.persist()
+    }
+
+    /// Reverts every change made since [`FerrousDB::begin`].
+    pub fn rollback(&mut self) -> Result<(), FerrousDBError> {
+        if !self.in_transaction {
+            return Err(FerrousDBError::TransactionError(
+                "no transaction is active".to_string(),
+            ));
+        }
+        self.in_transaction = false;
+        self.savepoints.clear();
+        self.discard_changes()
+    }
+
+    /// Sets a savepoint named `name` in the open transaction. An older
+    /// savepoint with the same name stays but is hidden until this one is
+    /// released.
+    pub fn savepoint(&mut self, name: &str) -> Result<(), FerrousDBError> {
+        if !self.in_transaction {
+            return Err(FerrousDBError::TransactionError(
+                "SAVEPOINT can only be used inside a transaction".to_string(),
+            ));
+        }
+        self.savepoints.push(Savepoint {
+            name: name.to_string(),
+            undo_mark: self.undo_log.len(),
+            log_mark: self.pending_log.len(),
+        });
+        Ok(())
+    }
+
+    /// Forgets the savepoint named `name` and every savepoint set after it,
+    /// keeping the changes made since.
+    pub fn release_savepoint(&mut self, name: &str) -> Result<(), FerrousDBError> {
+        let position = self.find_savepoint(name)?;
+        self.savepoints.truncate(position);
+        Ok(())
+    }
+
+    /// Reverts every change made since the savepoint named `name` was set,
+    /// including index changes. The savepoint itself stays, savepoints set
+    /// after it are forgotten, and the transaction remains open.
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> Result<(), FerrousDBError> {
+        let position = self.find_savepoint(name)?;
+        self.savepoints.truncate(position + 1);
+        let savepoint = &self.savepoints[position];
+        let (undo_mark, log_mark) = (savepoint.undo_mark, savepoint.log_mark);
+        self.undo_to(undo_mark, log_mark)
+    }
+
+    fn find_savepoint(&self, name: &str) -> Result<usize, FerrousDBError> {
+        if !self.in_transaction {
+            return Err(FerrousDBError::TransactionError(
+                "no transaction is active".to_string(),
+            ));
+        }
+        self.savepoints
+            .iter()
+            .rposition(|savepoint| savepoint.name == name)
+            .ok_or_else(|| {
+                FerrousDBError::TransactionError(format!("savepoint '{}' does not exist", name))
+            })
+    }
+
+    /// Returns whether a transaction started with [`FerrousDB::begin`] is
+    /// still open.
+    pub fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    /// Runs a read-only statement against the state as of the last commit,
+    /// without the changes of a transaction that is still open. Statements
+    /// that would change the database are rejected.
+    pub fn query(&self, sql: &str) -> Result<QueryResult, FerrousDBError> {
+        self.query_at(self.committed_snapshot(), parse_sql(sql)?)
+    }
+
+    /// Runs a `SELECT` and returns its columns and rows as this instance
+    /// sees them, including the changes of its open transaction.
+    pub fn select(&self, sql: &str) -> Result<ResultSet, FerrousDBError> {
+        self.select_at(self.own_snapshot(), &parse_sql(sql)?)
+    }
+
+    pub(crate) fn select_at(
+        &self,
+        snapshot: Snapshot,
+        command: &SQLCommand,
+    ) -> Result<ResultSet, FerrousDBError> {
+        self.select_in(snapshot, command, &Ctes::new(), None)
+    }
+
+    /// Runs a `SELECT` reading `ctes`, as a subquery running for a row of
+    /// `outer`'s query if there is one.
+    fn select_in(
+        &self,
+        snapshot: Snapshot,
+        command: &SQLCommand,
+        ctes: &Ctes,
+        outer: Option<Rc<Outer>>,
+    ) -> Result<ResultSet, FerrousDBError> {
+        if let SQLCommand::With {
+            recursive,
+            ctes: definitions,
+            query,
+        } = command
+        {
+            let mut ctes = ctes.clone();
+            for cte in definitions {
+                let table = self.cte_table(snapshot, cte, *recursive, &ctes, outer.clone())?;
+                ctes.insert(cte.name.clone(), Rc::new(table));
+            }
+            return self.select_in(snapshot, query, &ctes, outer);
+        }
+        if let SQLCommand::SetOperation {
+            op,
+            all,
+            left,
+            right,
+            order_by,
+            limit,
+            offset,
+        } = command
+        {
+            let left = self.select_in(snapshot, left, ctes, outer.clone())?;
+            let right = self.select_in(snapshot, right, ctes, outer)?;
+            let mut result = query::combine(*op, *all, left, right, order_by)?;
+            query::limit(&mut result.rows, *limit, *offset);
+            return Ok(result);
+        }
+        let SQLCommand::SelectFrom {
+            table,
+            joins,
+            projection,
+            distinct,
+            limit,
+            offset,
+            filter,
+            group_by,
+            having,
+            order_by,
+        } = command
+        else {
+            return Err(FerrousDBError::ParseError(format!(
+                "'{}' is not a SELECT statement",
+                command.to_string()
+            )));
+        };
+        let (scope, mut rows) = self.source_rows(snapshot, table, joins, ctes, outer)?;
+        let subqueries = SubqueryRunner::new(self, snapshot, &scope, ctes.clone());
+        if let Some(filter) = filter {
+            let filter = scope.bind_condition(filter)?;
+            let mut matching = Vec::with_capacity(rows.len());
+            for row in rows {
+                if evaluator::matches_with(&filter, &row, &subqueries)? {
+                    matching.push(row);
+                }
+            }
+            rows = matching;
+        }
+        let rows = rows.iter().map(|row| row.as_ref()).collect();
+        let clauses = Clauses {
+            projection,
+            distinct: distinct.as_ref(),
+            group_by,
+            having: having.as_ref(),
+            order_by,
+        };
+        let mut result = query::select(&scope, rows, &clauses, &subqueries)?;
+        query::limit(&mut result.rows, *limit, *offset);
+        Ok(result)
+    }
+
+    /// The rows of the FROM clause as `snapshot` sees them, with the scope
+    /// naming their columns. The rows of a single stored table or CTE are
+    /// borrowed, joined rows and those of derived tables are built.
+    fn source_rows<'a>(
+        &'a self,
+        snapshot: Snapshot,
+        table: &TableRef,
+        joins: &[Join],
+        ctes: &'a Ctes,
+        outer: Option<Rc<Outer>>,
+    ) -> Result<(Scope, Vec<SourceRow<'a>>), FerrousDBError> {
+        let source = self.source_table(snapshot, table, ctes, outer.clone())?;
+        if joins.is_empty() {
+            let scope = Scope::table(&source, table.qualifier()).within(outer);
+            let rows = match source {
+                Cow::Borrowed(source) => source
+                    .visible_rows(snapshot)
+                    .map(|(_, row)| Cow::Borrowed(&row.data))
+                    .collect(),
+                Cow::Owned(source) => source
+                    .rows
+                    .into_iter()
+                    .map(|row| Cow::Owned(row.data))
+                    .collect(),
+            };
+            return Ok((scope, rows));
+        }
+
+        let mut joined = Joined::table(&source, table.qualifier(), snapshot);
+        joined.scope = joined.scope.within(outer.clone());
+        for join in joins {
+            let right = self.source_table(snapshot, &join.table, ctes, outer.clone())?;
+            // Indexes follow the rows this instance sees, older versions of
+            // rows changed since the last commit aren't in them
+            let covered = self.undo_log.is_empty() || snapshot.includes(self.last_txn_id + 1);
+            let indexes = match right {
+                Cow::Borrowed(_) if covered && !ctes.contains_key(&join.table.name) => self
+                    .indexes
+                    .values()
+                    .filter(|index| index.table_name() == join.table.name)
+                    .map(|index| (index.column_name(), index))
+                    .collect(),
+                _ => HashMap::new(),
+            };
+            let right = JoinTable {
+                table: &right,
+                qualifier: join.table.qualifier(),
+                snapshot,
+                indexes,
+            };
+            joined = join::join(joined, right, join.kind, join.condition.as_ref())?;
+        }
+        let rows = joined.rows.into_iter().map(Cow::Owned).collect();
+        Ok((joined.scope, rows))
+    }
+
+    /// A table of the FROM clause: a CTE, a stored table, or a derived table
+    /// holding the result of its query. CTEs hide the tables they share a
+    /// name with.
+    fn source_table<'a>(
+        &'a self,
+        snapshot: Snapshot,
+        table: &TableRef,
+        ctes: &'a Ctes,
+        outer: Option<Rc<Outer>>,
+    ) -> Result<Cow<'a, Table>, FerrousDBError> {
+        match &table.subquery {
+            Some(query) => {
+                let result = self.select_in(snapshot, query, ctes, outer)?;
+                derived_table(&table.name, result).map(Cow::Owned)
+            }
+            None => match ctes.get(&table.name) {
+                Some(cte) => Ok(Cow::Borrowed(cte)),
+                None => self.table(&table.name).map(Cow::Borrowed),
+            },
+        }
+    }
+
+    /// Computes the result of a CTE as a table. In a recursive `WITH`, a
+    /// `UNION` whose right side reads the CTE itself runs to a fixpoint,
+    /// that side reading the rows found by the previous iteration.
+    fn cte_table(
+        &self,
+        snapshot: Snapshot,
+        cte: &Cte,
+        recursive: bool,
+        ctes: &Ctes,
+        outer: Option<Rc<Outer>>,
+    ) -> Result<Table, FerrousDBError> {
+        let result = match &cte.query {
+            SQLCommand::SetOperation {
+                op: SetOperator::Union,
+                all,
+                left,
+                right,
+                order_by,
+                limit: None,
+                offset: 0,
+            } if recursive && order_by.is_empty() && right.reads_table(&cte.name) => {
+                let seed = self.select_in(snapshot, left, ctes, outer.clone())?;
+                let seed = name_cte_columns(cte, seed)?;
+                let mut ctes = ctes.clone();
+                query::fixpoint(&cte.name, *all, seed, MAX_RECURSIVE_ITERATIONS, |found| {
+                    ctes.insert(cte.name.clone(), Rc::new(derived_table(&cte.name, found)?));
+                    self.select_in(snapshot, right, &ctes, outer.clone())
+                })?
+            }
+            query => name_cte_columns(cte, self.select_in(snapshot, query, ctes, outer)?)?,
+        };
+        derived_table(&cte.name, result)
+    }
+
+    fn table(&self, name: &str) -> Result<&Table, FerrousDBError> {
+        self.tables
+            .get(name)
+            .ok_or_else(|| FerrousDBError::TableNotFound(name.to_string()))
+    }
+
+    pub(crate) fn query_at(
+        &self,
+        snapshot: Snapshot,
+        command: SQLCommand,
+    ) -> Result<QueryResult, FerrousDBError> {
+        match command {
+            SQLCommand::SelectFrom { .. }
+            | SQLCommand::SetOperation { .. }
+            | SQLCommand::With { .. } => Ok(QueryResult::Rows(self.select_at(snapshot, &command)?)),
+            _ => Err(FerrousDBError::TransactionError(format!(
+                "'{}' is not a read-only statement",
+                command.to_string()
+            ))),
+        }
+    }
+
+    fn execute_command(&mut self, command: SQLCommand) -> Result<QueryResult, FerrousDBError> {
+        let message = match command {
+            SQLCommand::CreateTable { name, columns } => {
+                self.create_table(&name, columns)?;
+                format!("Table '{}' created successfully", name)
+            }
+            SQLCommand::InsertInto {
+                table,
+                columns,
+                source,
+            } => {
+                let count = self.insert_rows(&table, &columns, source)?;
+                return Ok(QueryResult::RowsAffected(count));
+            }
+            SQLCommand::SelectFrom { .. }
+            | SQLCommand::SetOperation { .. }
+            | SQLCommand::With { .. } => return self.query_at(self.own_snapshot(), command),
+            SQLCommand::DeleteFrom { table, condition } => {
+                let count = self.delete_from(&table, condition)?;
+                return Ok(QueryResult::RowsAffected(count));
+            }
+            SQLCommand::Update {
+                table,
+                assignments,
+                condition,
+            } => {
+                let count = self.update(&table, assignments, condition)?;
+                return Ok(QueryResult::RowsAffected(count));
+            }
+            SQLCommand::Checkpoint => {
+                let lsn = self.checkpoint()?;
+                format!("Checkpoint completed at LSN {}", lsn)
+            }
+            SQLCommand::Begin => {
+                self.begin()?;
+                "Transaction started".to_string()
+            }
+            SQLCommand::Commit => {
+                self.commit()?;
+                "Transaction committed".to_string()
+            }
+            SQLCommand::Rollback => {
+                self.rollback()?;
+                "Transaction rolled back".to_string()
+            }
+            SQLCommand::Savepoint { name } => {
+                self.savepoint(&name)?;
+                format!("Savepoint '{}' set", name)
+            }
+            SQLCommand::Release { name } => {
+                self.release_savepoint(&name)?;
+                format!("Savepoint '{}' released", name)
+            }
+            SQLCommand::RollbackTo { name } => {
+                self.rollback_to_savepoint(&name)?;
+                format!("Rolled back to savepoint '{}'", name)
+            }
+            SQLCommand::CreateView {
+                name,
+                query,
+                columns,
+            } => {
+                // Por enquanto apenas retornamos um erro indicando que a feature não está implementada
+                return Err(FerrousDBError::ParseError(
+                    "Views are not implemented yet".to_string(),
+                ));
+            }
+        };
+        Ok(QueryResult::Message(message))
+    }
+
+    fn check_writable(&self) -> Result<(), FerrousDBError> {
+        if self.read_only {
+            let path = self.path.as_deref().unwrap_or(Path::new(""));
+            return Err(FerrousDBError::ReadOnly(path.display().to_string()));
+        }
+        Ok(())
+    }
+
+    fn storage(&self) -> Option<MutexGuard<'_, Storage>> {
+        self.storage
+            .as_ref()
+            .map(|storage| storage.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn write_catalog(&self) -> Result<(), FerrousDBError> {
+        if let Some(mut storage) = self.storage() {
+            storage.write_catalog(&self.tables, &self.indexes)?;
+        }
+        Ok(())
+    }
+
+    /// Logs the pending changes as one committed transaction, then writes the
+    /// pages they dirtied to disk. Does nothing while changes are being
+    /// batched until the end of the current statement or transaction.
+    fn persist(&mut self) -> Result<(), FerrousDBError> {
+        if self.batching || self.in_transaction {
+            return Ok(());
+        }
+        if self.pending_log.is_empty() {
+            self.undo_log.clear();
+            return Ok(());
+        }
+
+        // The transaction is only durable once its log records are synced;
+        // the database pages follow right after.
+        let txn_id = self.last_txn_id + 1;
+        let wal_position = match self.log_pending(txn_id) {
+            Ok(position) => position,
+            Err(e) => {
+                // Some records may have reached the log without a commit
+                // record, so the transaction ID is used up either way, just
+                // as recovery would count it
+                self.last_txn_id = txn_id;
+                self.discard_changes()?;
+                return Err(e);
+            }
+        };
+        self.pending_log.clear();
+        let undo_log = std::mem::take(&mut self.undo_log);
+        self.last_txn_id = txn_id;
+        self.freeze(&undo_log)?;
+
+        if let Some(position) = wal_position {
+            self.flush_through(position)?;
+            if position >= self.checkpoint_threshold {
+                self.checkpoint()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the pending changes to the log as transaction `txn_id` and
+    /// returns the log position after its commit record, if there is a log.
+    fn log_pending(&self, txn_id: u64) -> Result<Option<u64>, FerrousDBError> {
+        let wal_path = match &self.wal_path {
+            Some(wal_path) => wal_path,
+            None => return Ok(None),
+        };
+        let mut wal = WriteAheadLog::new(wal_path)?;
+        for change in &self.pending_log {
+            wal.log(txn_id, change)?;
+        }
+        wal.commit(txn_id)?;
+        Ok(Some(wal.position()?))
+    }
+
+    /// Snapshot of this instance, which sees the changes of its own open
+    /// transaction.
+    fn own_snapshot(&self) -> Snapshot {
+        Snapshot::new(self.last_txn_id + 1)
+    }
+
+    /// Snapshot of the last commit.
+    pub(crate) fn committed_snapshot(&self) -> Snapshot {
+        Snapshot::new(self.last_txn_id)
+    }
+
+    /// Once a transaction commits, nobody needs the versions it replaced:
+    /// rows it deleted are dropped for good and the rows it wrote forget their
+    /// older versions and are marked committed, just like rows read from disk.
+    fn freeze(&mut self, undo_log: &[UndoEntry]) -> Result<(), FerrousDBError> {
+        let mut deleted: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (table_name, position) in undo_log.iter().filter_map(UndoEntry::row) {
+            let row = self
+                .tables
+                .get_mut(table_name)
+                .ok_or_else(|| FerrousDBError::TableNotFound(table_name.to_string()))?
+                .rows
+                .get_mut(position)
+                .ok_or_else(|| {
+                    FerrousDBError::TransactionError(format!(
+                        "row {} of table '{}' is gone",
+                        position, table_name
+                    ))
+                })?;
+            row.xmin = 0;
+            row.previous = None;
+            if row.xmax.is_some() {
+                deleted.entry(table_name).or_default().push(position);
+            }
+        }
+
+        for (table_name, mut positions) in deleted {
+            positions.sort_unstable_by(|a, b| b.cmp(a));
+            positions.dedup();
+            let rows = &mut self.tables.get_mut(table_name).expect("table was found above").rows;
+            for position in positions {
+                rows.remove(position);
+            }
+            self.reindex(table_name)?;
+        }
+        Ok(())
+    }
+
+    /// Reverts every uncommitted change. The tables and indexes are restored
+    /// from the undo log, while the database pages are simply dropped from the
+    /// cache, since nothing uncommitted is ever flushed.
+    fn discard_changes(&mut self) -> Result<(), FerrousDBError> {
+        self.pending_log.clear();
+        let undo_log = std::mem::take(&mut self.undo_log);
+        self.revert(undo_log, false)?;
+        if let Some(mut storage) = self.storage() {
+            storage.discard_changes()?;
+        }
+        Ok(())
+    }
+
+    /// Reverts the changes made since the undo log had `undo_mark` entries
+    /// and the pending log `log_mark`, in memory and in the database pages,
+    /// leaving earlier uncommitted changes in place.
+    fn undo_to(&mut self, undo_mark: usize, log_mark: usize) -> Result<(), FerrousDBError> {
+        let undo_log = self.undo_log.split_off(undo_mark);
+        self.pending_log.truncate(log_mark);
+        let catalog_changed = undo_log.iter().any(|entry| entry.row().is_none());
+        self.revert(undo_log, true)?;
+        if catalog_changed {
+            self.write_catalog()?;
+        }
+        Ok(())
+    }
+
+    /// Applies undo entries newest first, then rebuilds the indexes of the
+    /// tables whose rows moved.
+    fn revert(
+        &mut self,
+        undo_log: Vec<UndoEntry>,
+        restore_pages: bool,
+    ) -> Result<(), FerrousDBError> {
+        let mut touched = HashSet::new();
+        for entry in undo_log.into_iter().rev() {
+            if let Some((table, _)) = entry.row() {
+                touched.insert(table.to_string());
+            }
+            self.undo(entry, restore_pages)?;
+        }
+        for table in touched {
+            if self.tables.contains_key(&table) {
+                self.reindex(&table)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reverts a single change. With `restore_pages` the database pages are
+    /// changed back as well, otherwise only the in-memory tables and indexes.
+    fn undo(&mut self, entry: UndoEntry, restore_pages: bool) -> Result<(), FerrousDBError> {
+        let mut storage = self
+            .storage
+            .as_ref()
+            .filter(|_| restore_pages)
+            .map(|storage| storage.lock().unwrap_or_else(|e| e.into_inner()));
+        match entry {
+            UndoEntry::CreateTable { name } => {
+                self.tables.remove(&name);
+                if let Some(storage) = &mut storage {
+                    storage.drop_heap(&name);
+                }
+            }
+            UndoEntry::CreateIndex { name, replaced } => match replaced {
+                Some(index) => {
+                    self.indexes.insert(name, index);
+                }
+                None => {
+                    self.indexes.remove(&name);
+                }
+            },
+            UndoEntry::Insert { table, position } => {
+                let rows = &mut self
+                    .tables
+                    .get_mut(&table)
+                    .ok_or_else(|| FerrousDBError::TableNotFound(table.clone()))?
+                    .rows;
+                let row = rows.remove(position);
+                if let (Some(storage), Some(location)) = (&mut storage, row.location) {
+                    storage.delete_row(&table, location)?;
+                }
+            }
+            UndoEntry::Update {
+                table,
+                position,
+                mut old,
+            } => {
+                let rows = &mut self
+                    .tables
+                    .get_mut(&table)
+                    .ok_or_else(|| FerrousDBError::TableNotFound(table.clone()))?
+                    .rows;
+                if let (Some(storage), Some(location)) = (&mut storage, rows[position].location) {
+                    old.location = Some(storage.update_row(&table, location, &old)?);
+                }
+                rows[position] = old;
+            }
+            UndoEntry::Delete {
+                table,
+                position,
+                mut row,
+            } => {
+                let rows = &mut self
+                    .tables
+                    .get_mut(&table)
+                    .ok_or_else(|| FerrousDBError::TableNotFound(table.clone()))?
+                    .rows;
+                if let Some(storage) = &mut storage {
+                    row.location = Some(storage.insert_row(&table, &row)?);
+                }
+                rows[position] = row;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes dirty pages and records that they include every log record up
+    /// to `wal_position`.
+    fn flush_through(&self, wal_position: u64) -> Result<(), FerrousDBError> {
+        if let Some(mut storage) = self.storage() {
+            storage.set_wal_position(wal_position)?;
+            storage.set_last_txn_id(self.last_txn_id)?;
+            storage.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> SubqueryRunner<'a> {
+    fn new(db: &'a FerrousDB, snapshot: Snapshot, scope: &'a Scope, ctes: Ctes) -> Self {
+        SubqueryRunner {
+            db,
+            snapshot,
+            scope,
+            ctes,
+            results: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl Subqueries for SubqueryRunner<'_> {
+    fn run(
+        &self,
+        query: &SQLCommand,
+        row: &HashMap<String, DataType>,
+    ) -> Result<Rc<ResultSet>, FerrousDBError> {
+        let sql = query.to_string();
+        if let Some(result) = self.results.borrow().get(&sql) {
+            return Ok(Rc::clone(result));
+        }
+        let outer = Rc::new(Outer::new(self.scope.clone(), row.clone()));
+        let result = Rc::new(self.db.select_in(
+            self.snapshot,
+            query,
+            &self.ctes,
+            Some(Rc::clone(&outer)),
+        )?);
+        if !outer.was_read() {
+            self.results.borrow_mut().insert(sql, Rc::clone(&result));
+        }
+        Ok(result)
+    }
+}
+
+/// Renames the first columns of a CTE's result after the column names the
+/// CTE gives.
+fn name_cte_columns(cte: &Cte, mut result: ResultSet) -> Result<ResultSet, FerrousDBError> {
+    if cte.columns.len() > result.columns.len() {
+        return Err(FerrousDBError::InvalidExpression(format!(
+            "WITH query '{}' has {} columns available but {} columns specified",
+            cte.name,
+            result.columns.len(),
+            cte.columns.len()
+        )));
+    }
+    for (column, name) in result.columns.iter_mut().zip(&cte.columns) {
+        column.name = name.clone();
+    }
+    Ok(result)
+}
+
+/// Holds the result of a derived table's query as a table of committed
+/// rows, NULLs being left out like in stored rows.
+fn derived_table(name: &str, result: ResultSet) -> Result<Table, FerrousDBError> {
+    let mut table = Table::new(name.to_string(), Vec::with_capacity(result.columns.len()));
+    for column in &result.columns {
+        if table.schema.iter().any(|c| c.name == column.name) {
+            return Err(FerrousDBError::InvalidExpression(format!(
+                "column '{}' specified more than once in '{}'",
+                column.name, name
+            )));
+        }
+        table.schema.push(ColumnSchema::new(
+            column.name.clone(),
+            column.data_type.clone(),
+        ));
+    }
+    for values in result.rows {
+        let data = table
+            .schema
+            .iter()
+            .zip(values)
+            .filter_map(|(column, value)| Some((column.name.clone(), value?)))
+            .collect();
+        table.rows.push(Row::new(data));
+    }
+    Ok(table)
+}
+
+/// Rejects pages of no rows, which could never cover a table.
+fn check_page_size(page_size: usize) -> Result<(), FerrousDBError> {
+    if page_size == 0 {
+        return Err(FerrousDBError::InvalidExpression(
+            "page size must be at least 1".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that an `INSERT` gives as many values as it has target columns.
+fn check_insert_width(values: usize, columns: usize) -> Result<(), FerrousDBError> {
+    if values > columns {
+        return Err(FerrousDBError::ParseError(
+            "INSERT has more expressions than target columns".to_string(),
+        ));
+    }
+    if values < columns {
+        return Err(FerrousDBError::ParseError(
+            "INSERT has more target columns than expressions".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks a new version of a row against the `CHECK` constraints of `table`.
+fn check_row(table: &Table, data: &HashMap<String, DataType>) -> Result<(), FerrousDBError> {
+    let scope = Scope::table(table, &table.name);
+    for column in &table.schema {
+        for constraint in &column.constraints {
+            if let Constraint::Check(expr) = constraint {
+                // Unlike WHERE, only a false condition fails: NULL passes
+                let condition = scope.bind(expr)?;
+                if evaluator::evaluate(&condition, data)? == Some(DataType::Boolean(false)) {
+                    return Err(FerrousDBError::ConstraintViolation(format!(
+                        "CHECK constraint failed: {}",
+                        expr
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
-    use std::fs;
+    use crate::core::parser::sql_parser::parse_expr;
+    use std::{fs, io::Write};
+
+    fn setup() -> FerrousDB {
+        FerrousDB::open_in_memory()
+    }
+
+    fn temp_db_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{}.ferrous", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("log"));
+        path
+    }
+
+    #[test]
+    fn test_create_table() {
+        let mut db = setup();
+        let result = db.create_table(
+            "users",
+            vec![
+                ColumnSchema::new("name".to_string(), "TEXT".to_string()),
+                ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
+            ],
+        );
+        assert!(result.is_ok());
+        assert_eq!(db.tables.len(), 1);
+        assert_eq!(
+            db.tables.get("users").unwrap().schema,
+            vec![
+                ColumnSchema::new("name".to_string(), "TEXT".to_string()),
+                ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_insert_into() {
+        let mut db = setup();
+        let create_result = db.create_table(
+            "users",
+            vec![
+                ColumnSchema::new("name".to_string(), "TEXT".to_string()),
+                ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
+            ],
+        );
+        assert!(create_result.is_ok());
+
+        let mut values = HashMap::new();
+        values.insert("name".to_string(), DataType::Text("Alice".to_string()));
+        values.insert("age".to_string(), DataType::Integer(30));
+        let insert_result = db.insert_into("users", values);
+        assert!(insert_result.is_ok());
+
+        assert_eq!(db.tables.get("users").unwrap().rows.len(), 1);
+        let row = &db.tables.get("users").unwrap().rows[0];
+        assert_eq!(row.data.get("name").unwrap().get_value(), "Alice");
+        assert_eq!(row.data.get("age").unwrap().get_value(), "30");
+    }
+
+    #[test]
+    fn test_select_from_with_limit_and_offset() {
+        let mut db = setup();
+        let create_result = db.create_table(
+            "users",
+            vec![
+                ColumnSchema::new("name".to_string(), "TEXT".to_string()),
+                ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
+            ],
+        );
+        assert!(create_result.is_ok());
+
+        // Insert 5 users
+        for i in 1..=5 {
+            let mut values = HashMap::new();
+            values.insert("name".to_string(), DataType::Text(format!("User{}", i)));
+            values.insert("age".to_string(), DataType::Integer(20 + i));
+            let insert_result = db.insert_into("users", values);
+            assert!(insert_result.is_ok());
+        }
+
+        // Test with limit 2 and offset 1
+        let table = db.get_page("users", 2, 2, None, None, None);
+        match table {
+            PageResult::Page(rows) => {
+                assert_eq!(rows.len(), 2);
+                assert_eq!(rows[0].data.get("name").unwrap().get_value(), "User3");
+                assert_eq!(rows[1].data.get("name").unwrap().get_value(), "User4");
+            }
+            _ => panic!("Expected PageResult::Page"),
+        };
+
+        // Test with limit 3 and offset 3
+        let table = db.get_page("users", 3, 2, None, None, None);
+        match table {
+            PageResult::Page(rows) => {
+                assert_eq!(rows.len(), 1); // Only 1 row left
+                assert_eq!(rows[0].data.get("name").unwrap().get_value(), "User5");
+            }
+            _ => panic!("Expected PageResult::Page"),
+        };
+
+        assert_eq!(db.total_pages("users", 2).unwrap(), 3);
+        assert!(matches!(
+            db.total_pages("users", 0),
+            Err(FerrousDBError::InvalidExpression(_))
+        ));
+        assert!(matches!(
+            db.get_page("users", 1, 0, None, None, None),
+            PageResult::Error(FerrousDBError::InvalidExpression(_))
+        ));
+        assert!(matches!(
+            db.total_pages("posts", 2),
+            Err(FerrousDBError::TableNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_update() {
+        let mut db = setup();
+        let create_result = db.create_table(
+            "users",
+            vec![
+                ColumnSchema::new("name".to_string(), "TEXT".to_string()),
+                ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
+            ],
+        );
+        assert!(create_result.is_ok());
+
+        // Insert initial data
+        let mut values = HashMap::new();
+        values.insert("name".to_string(), DataType::Text("Alice".to_string()));
+        values.insert("age".to_string(), DataType::Integer(30));
+        let insert_result = db.insert_into("users", values);
+        assert!(insert_result.is_ok());
+
+        // Verify initial data
+        match db.get_page("users", 1, 10, None, None, None) {
+            PageResult::Page(rows) => {
+                assert_eq!(rows.len(), 1);
+                assert_eq!(rows[0].data.get("name").unwrap().get_value(), "Alice");
+                assert_eq!(rows[0].data.get("age").unwrap().get_value(), "30");
+            }
+            _ => panic!("Expected to find inserted row"),
+        }
+
+        // Perform update
+        let mut assignments = HashMap::new();
+        assignments.insert("age".to_string(), DataType::Integer(31));
+        let condition = parse_expr("name = 'Alice'").unwrap();
+        let update_result = db.update("users", assignments, Some(condition));
+        assert!(update_result.is_ok());
+        assert_eq!(update_result.unwrap(), 1); // Should update exactly 1 row
+
+        // Verify the update was successful
+        match db.get_page("users", 1, 10, None, None, None) {
+            PageResult::Page(rows) => {
+                assert_eq!(rows.len(), 1);
+                assert_eq!(rows[0].data.get("name").unwrap().get_value(), "Alice");
+                assert_eq!(rows[0].data.get("age").unwrap().get_value(), "31");
+            }
+            _ => panic!("Expected to find the updated row"),
+        }
+    }
+
+    #[test]
+    fn test_delete_from() {
+        let mut db = setup();
+        let create_result = db.create_table(
+            "users",
+            vec![
+                ColumnSchema::new("name".to_string(), "TEXT".to_string()),
+                ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
+            ],
+        );
+        assert!(create_result.is_ok());
+
+        let mut values = HashMap::new();
+        values.insert("name".to_string(), DataType::Text("Alice".to_string()));
+        values.insert("age".to_string(), DataType::Integer(30));
+        let insert_result = db.insert_into("users", values);
+        assert!(insert_result.is_ok());
+
+        let condition = parse_expr("name = 'Alice'").unwrap();
+        let delete_result = db.delete_from("users", Some(condition));
+        assert!(delete_result.is_ok());
+
+        assert_eq!(db.tables.get("users").unwrap().rows.len(), 0);
+    }
+
+    #[test]
+    fn test_open_persists_to_given_path() {
+        let path = temp_db_path("ferrous_open");
+
+        {
+            let mut db = FerrousDB::open(&path).unwrap();
+            db.create_table(
+                "users",
+                vec![ColumnSchema::new("name".to_string(), "TEXT".to_string())],
+            )
+            .unwrap();
+            let mut values = HashMap::new();
+            values.insert("name".to_string(), DataType::Text("Alice".to_string()));
+            db.insert_into("users", values).unwrap();
+        }
+
+        let db = FerrousDB::open(&path).unwrap();
+        assert_eq!(db.path(), Some(path.as_path()));
+        assert_eq!(db.tables.get("users").unwrap().rows.len(), 1);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_open_in_memory_does_not_touch_disk() {
+        let mut db = FerrousDB::open_in_memory();
+        assert!(db.path().is_none());
+        db.create_table(
+            "users",
+            vec![ColumnSchema::new("name".to_string(), "TEXT".to_string())],
+        )
+        .unwrap();
+        assert!(db.execute_sql("INSERT INTO users (name) VALUES ('Alice')").is_ok());
+        assert!(db.execute_sql("SELECT * FROM users").is_ok());
+    }
+
+    #[test]
+    fn test_reopen_after_update_and_delete_across_pages() {
+        let path = temp_db_path("ferrous_pages");
+
+        {
+            let mut db = FerrousDB::open(&path).unwrap();
+            db.create_table(
+                "users",
+                vec![
+                    ColumnSchema::new("name".to_string(), "TEXT".to_string()),
+                    ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
+                ],
+            )
+            .unwrap();
+            db.create_index("users", "age", IndexType::Hash).unwrap();
+            // Enough rows to spill over several heap pages
+            for i in 0..500 {
+                let mut values = HashMap::new();
+                values.insert("name".to_string(), DataType::Text(format!("User{}", i)));
+                values.insert("age".to_string(), DataType::Integer(i));
+                db.insert_into("users", values).unwrap();
+            }
+
+            let mut assignments = HashMap::new();
+            assignments.insert("name".to_string(), DataType::Text("x".repeat(200)));
+            db.update("users", assignments, Some(parse_expr("age = 7").unwrap()))
+                .unwrap();
+            db.delete_from("users", Some(parse_expr("age = 3").unwrap()))
+                .unwrap();
+        }
+
+        let db = FerrousDB::open(&path).unwrap();
+        let table = db.tables.get("users").unwrap();
+        assert_eq!(table.rows.len(), 499);
+        assert!(!table
+            .rows
+            .iter()
+            .any(|row| row.data.get("age") == Some(&DataType::Integer(3))));
+        let updated = table
+            .rows
+            .iter()
+            .find(|row| row.data.get("age") == Some(&DataType::Integer(7)))
+            .unwrap();
+        assert_eq!(updated.data.get("name").unwrap().get_value(), "x".repeat(200));
+        assert!(db.indexes.contains_key("users_age"));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_flush_interrupted_by_crash_is_completed_on_open() {
+        // The heap pages reach the file without the header, then the reverse
+        for header_written in [false, true] {
+            let path = temp_db_path("ferrous_torn_flush");
+            let log_path = path.with_extension("log");
+            let mut db = FerrousDB::open(&path).unwrap();
+            db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+                .unwrap();
+            db.insert_into("users", insert_row("Bob", 42)).unwrap();
+
+            // Commit a statement to the log as persist does, then crash while
+            // its pages are written
+            db.batching = true;
+            for age in 0..100 {
+                db.insert_into("users", insert_row("Eve", age)).unwrap();
+            }
+            let txn_id = db.last_txn_id + 1;
+            let mut wal = WriteAheadLog::new(&log_path).unwrap();
+            for change in &db.pending_log {
+                wal.log(txn_id, change).unwrap();
+            }
+            wal.commit(txn_id).unwrap();
+            let mut storage = db.storage().unwrap();
+            storage.set_wal_position(wal.position().unwrap()).unwrap();
+            storage.set_last_txn_id(txn_id).unwrap();
+            storage
+                .flush_torn(|id| (id == 0) == header_written)
+                .unwrap();
+            drop(storage);
+            drop(db);
+
+            // Neither lost nor replayed twice, by a reader that leaves the
+            // journal in place, a writer, and the next open
+            let db = FerrousDB::open_read_only(&path).unwrap();
+            assert_eq!(db.tables["users"].rows.len(), 101);
+            drop(db);
+            for _ in 0..2 {
+                let db = FerrousDB::open(&path).unwrap();
+                assert_eq!(db.tables["users"].rows.len(), 101);
+            }
+            assert!(!path.with_extension("journal").exists());
+
+            let _ = fs::remove_file(&path);
+            let _ = fs::remove_file(&log_path);
+        }
+    }
+
+    fn insert_row(name: &str, age: i64) -> HashMap<String, DataType> {
+        let mut row = HashMap::new();
+        row.insert("name".to_string(), DataType::Text(name.to_string()));
+        row.insert("age".to_string(), DataType::Integer(age));
+        row
+    }
+
+    /// Runs a query and renders each row as its comma separated values.
+    fn select_rows(db: &FerrousDB, sql: &str) -> Vec<String> {
+        db.select(sql)
+            .unwrap()
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|value| value.as_ref().map_or("NULL".to_string(), |v| v.get_value()))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect()
+    }
+
+    fn insert_entry(name: &str, age: i64) -> LogEntry {
+        LogEntry::Insert {
+            table: "users".to_string(),
+            row: insert_row(name, age),
+        }
+    }
+
+    #[test]
+    fn test_recovery_replays_committed_log_and_truncates_torn_tail() {
+        let path = temp_db_path("ferrous_recovery");
+        let log_path = path.with_extension("log");
+        {
+            let mut db = FerrousDB::open(&path).unwrap();
+            db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+                .unwrap();
+        }
+
+        // Simulate a crash after the log was synced but before the database
+        // pages were flushed, followed by an uncommitted and a torn record.
+        let committed_len = {
+            let mut wal = WriteAheadLog::new(&log_path).unwrap();
+            wal.log(100, &insert_entry("Bob", 42)).unwrap();
+            wal.commit(100).unwrap();
+            let committed_len = wal.position().unwrap();
+            wal.log(101, &insert_entry("Eve", 7)).unwrap();
+            committed_len
+        };
+        let mut log = fs::OpenOptions::new().append(true).open(&log_path).unwrap();
+        log.write_all(&[42, 0, 0, 0, 7]).unwrap();
+        drop(log);
+
+        let db = FerrousDB::open(&path).unwrap();
+        assert_eq!(db.last_txn_id, 101);
+        let rows = &db.tables.get("users").unwrap().rows;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].data.get("age"), Some(&DataType::Integer(42)));
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), committed_len);
+        drop(db);
+
+        // Replayed changes are flushed, so reopening doesn't apply them twice
+        let db = FerrousDB::open(&path).unwrap();
+        assert_eq!(db.tables.get("users").unwrap().rows.len(), 1);
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&log_path);
+    }
+
+    #[test]
+    fn test_recovery_reports_where_log_diverged() {
+        let path = temp_db_path("ferrous_corrupt_log");
+        let log_path = path.with_extension("log");
+        drop(FerrousDB::open(&path).unwrap());
+
+        let corrupt_at = {
+            let mut wal = WriteAheadLog::new(&log_path).unwrap();
+            let corrupt_at = wal.position().unwrap();
+            wal.log(1, &insert_entry("Bob", 42)).unwrap();
+            wal.commit(1).unwrap();
+            corrupt_at
+        };
+        // Flip a payload byte of the first record after the checkpoint marker
+        let mut bytes = fs::read(&log_path).unwrap();
+        bytes[corrupt_at as usize + 30] ^= 0xFF;
+        fs::write(&log_path, bytes).unwrap();
+
+        match FerrousDB::open(&path) {
+            Err(FerrousDBError::RecoveryError(message)) => {
+                assert!(message.contains(&format!("LSN {}", corrupt_at)));
+            }
+            _ => panic!("Expected a recovery error"),
+        }
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&log_path);
+    }
+
+    #[test]
+    fn test_recovery_rejects_damaged_length_before_committed_records() {
+        let path = temp_db_path("ferrous_bad_length");
+        let log_path = path.with_extension("log");
+        drop(FerrousDB::open(&path).unwrap());
+
+        let corrupt_at = {
+            let mut wal = WriteAheadLog::new(&log_path).unwrap();
+            let corrupt_at = wal.position().unwrap() as usize;
+            wal.log(1, &insert_entry("Bob", 42)).unwrap();
+            wal.commit(1).unwrap();
+            wal.log(2, &insert_entry("Eve", 7)).unwrap();
+            wal.commit(2).unwrap();
+            corrupt_at
+        };
+        let bytes = fs::read(&log_path).unwrap();
+
+        // A length running past the end of the log, then one that doesn't:
+        // neither passes for a torn write that would cut the log short
+        for (byte, mask) in [(corrupt_at + 6, 0xFF), (corrupt_at + 4, 0x01)] {
+            let mut damaged = bytes.clone();
+            damaged[byte] ^= mask;
+            fs::write(&log_path, &damaged).unwrap();
+            match FerrousDB::open(&path) {
+                Err(FerrousDBError::RecoveryError(message)) => {
+                    assert!(message.contains(&format!("LSN {}", corrupt_at)));
+                }
+                _ => panic!("Expected a recovery error"),
+            }
+            assert_eq!(fs::read(&log_path).unwrap(), damaged);
+        }
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&log_path);
+    }
+
+    #[test]
+    fn test_checkpoint_truncates_log() {
+        let path = temp_db_path("ferrous_checkpoint");
+        let mut db = FerrousDB::open(&path).unwrap();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        db.execute_sql("INSERT INTO users (name, age) VALUES ('Bob', 42)")
+            .unwrap();
+
+        let before = db.wal_stats().unwrap();
+        assert_eq!(before.last_checkpoint_lsn, 0);
+
+        db.execute_sql("CHECKPOINT").unwrap();
+        let after = db.wal_stats().unwrap();
+        assert_eq!(after.last_checkpoint_lsn, before.log_size);
+        assert!(after.log_size < before.log_size);
+        drop(db);
+
+        let db = FerrousDB::open(&path).unwrap();
+        assert_eq!(db.tables.get("users").unwrap().rows.len(), 1);
+        assert_eq!(db.wal_stats().unwrap(), after);
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("log"));
+    }
+
+    #[test]
+    fn test_automatic_checkpoint_past_threshold() {
+        let path = temp_db_path("ferrous_auto_checkpoint");
+        let mut db = FerrousDB::open(&path).unwrap();
+        db.set_checkpoint_threshold(512);
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        for i in 0..20 {
+            db.execute_sql(&format!(
+                "INSERT INTO users (name, age) VALUES ('User{}', {})",
+                i, i
+            ))
+            .unwrap();
+        }
+
+        let stats = db.wal_stats().unwrap();
+        assert!(stats.last_checkpoint_lsn > 0);
+        assert!(stats.log_size < 512);
+        drop(db);
+
+        let db = FerrousDB::open(&path).unwrap();
+        assert_eq!(db.tables.get("users").unwrap().rows.len(), 20);
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("log"));
+    }
+
+    #[test]
+    fn test_log_records_only_mutations_as_row_images() {
+        let path = temp_db_path("ferrous_row_images");
+        let log_path = path.with_extension("log");
+        let mut db = FerrousDB::open(&path).unwrap();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        db.insert_into("users", insert_row("Bob", 42)).unwrap();
+        db.insert_into("users", insert_row("Eve", 7)).unwrap();
+
+        let size = db.wal_stats().unwrap().log_size;
+        db.execute_sql("SELECT * FROM users").unwrap();
+        assert!(db.execute_sql("SELEC * FROM users").is_err());
+        assert_eq!(db.wal_stats().unwrap().log_size, size);
+
+        let mut assignments = HashMap::new();
+        assignments.insert("age".to_string(), DataType::Integer(43));
+        db.update("users", assignments, Some(parse_expr("age = 42").unwrap()))
+            .unwrap();
+        db.delete_from("users", Some(parse_expr("age = 7").unwrap()))
+            .unwrap();
+        drop(db);
+
+        let recovered = WriteAheadLog::recover(&log_path, 0, 0).unwrap();
+        let entries: Vec<LogEntry> = recovered.records.into_iter().map(|r| r.entry).collect();
+        assert!(matches!(entries[0], LogEntry::CreateTable { .. }));
+        assert_eq!(entries[1], insert_entry("Bob", 42));
+        assert_eq!(entries[2], insert_entry("Eve", 7));
+        assert_eq!(
+            entries[3],
+            LogEntry::Update {
+                table: "users".to_string(),
+                old: insert_row("Bob", 42),
+                new: insert_row("Bob", 43),
+            }
+        );
+        assert_eq!(
+            entries[4],
+            LogEntry::Delete {
+                table: "users".to_string(),
+                row: insert_row("Eve", 7),
+            }
+        );
+        assert_eq!(entries.len(), 5);
+
+        // Replaying the row images from an empty database rebuilds the same state
+        let header = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        header.set_len(0).unwrap();
+        drop(header);
+        let db = FerrousDB::open(&path).unwrap();
+        let rows = &db.tables.get("users").unwrap().rows;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].data, insert_row("Bob", 43));
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&log_path);
+    }
+
+    #[test]
+    fn test_rollback_restores_tables_and_indexes() {
+        let path = temp_db_path("ferrous_rollback");
+        let mut db = FerrousDB::open(&path).unwrap();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        for age in 0..50 {
+            db.insert_into("users", insert_row("Bob", age)).unwrap();
+        }
+        db.create_index("users", "age", IndexType::BTree).unwrap();
+        let tables = db.tables.clone();
+        let indexes = db.indexes.clone();
+        let log_size = db.wal_stats().unwrap().log_size;
+
+        db.execute_sql("BEGIN").unwrap();
+        db.insert_into("users", insert_row("Eve", 7)).unwrap();
+        let mut assignments = HashMap::new();
+        assignments.insert("age".to_string(), DataType::Integer(100));
+        db.update("users", assignments, Some(parse_expr("age = 3").unwrap()))
+            .unwrap();
+        db.delete_from("users", Some(parse_expr("age = 10").unwrap()))
+            .unwrap();
+        db.create_index("users", "name", IndexType::Hash).unwrap();
+        db.execute_sql("CREATE TABLE posts (title TEXT)").unwrap();
+        assert_eq!(db.wal_stats().unwrap().log_size, log_size);
+        db.execute_sql("ROLLBACK").unwrap();
+
+        assert!(!db.in_transaction());
+        assert_eq!(db.tables, tables);
+        assert_eq!(db.indexes, indexes);
+        assert_eq!(db.wal_stats().unwrap().log_size, log_size);
+
+        // The rolled back table can be created again and the data file is intact
+        db.execute_sql("CREATE TABLE posts (title TEXT)").unwrap();
+        drop(db);
+        let db = FerrousDB::open(&path).unwrap();
+        assert_eq!(db.tables["users"], tables["users"]);
+        assert_eq!(db.indexes, indexes);
+        assert!(db.tables.contains_key("posts"));
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("log"));
+    }
+
+    #[test]
+    fn test_commit_logs_transaction_at_once() {
+        let path = temp_db_path("ferrous_commit");
+        let log_path = path.with_extension("log");
+        let mut db = FerrousDB::open(&path).unwrap();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+
+        db.execute_sql("BEGIN").unwrap();
+        db.insert_into("users", insert_row("Bob", 42)).unwrap();
+        db.insert_into("users", insert_row("Eve", 7)).unwrap();
+        assert!(db.execute_sql("CHECKPOINT").is_err());
+        assert!(db.execute_sql("BEGIN").is_err());
+        db.execute_sql("COMMIT").unwrap();
+        assert!(db.execute_sql("COMMIT").is_err());
+        assert!(db.execute_sql("ROLLBACK").is_err());
+        drop(db);
+
+        let recovered = WriteAheadLog::recover(&log_path, 0, 0).unwrap();
+        let txn_ids: Vec<u64> = recovered.records.iter().map(|r| r.txn_id).collect();
+        assert_eq!(txn_ids, vec![1, 2, 2]);
+        let db = FerrousDB::open(&path).unwrap();
+        assert_eq!(db.tables["users"].rows.len(), 2);
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&log_path);
+    }
+
+    #[test]
+    fn test_uncommitted_transaction_does_not_survive_crash() {
+        let path = temp_db_path("ferrous_uncommitted");
+        let mut db = FerrousDB::open(&path).unwrap();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        db.insert_into("users", insert_row("Bob", 42)).unwrap();
+
+        db.execute_sql("BEGIN").unwrap();
+        db.execute_sql("CREATE TABLE posts (title TEXT)").unwrap();
+        for age in 0..200 {
+            db.insert_into("users", insert_row("Eve", age)).unwrap();
+        }
+        db.delete_from("users", Some(parse_expr("age = 42").unwrap()))
+            .unwrap();
+        // Dropping the handle without committing stands in for a crash
+        drop(db);
+
+        let db = FerrousDB::open(&path).unwrap();
+        assert!(!db.tables.contains_key("posts"));
+        let rows = &db.tables["users"].rows;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].data, insert_row("Bob", 42));
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("log"));
+    }
+
+    #[test]
+    fn test_failed_log_write_rolls_back_transaction() {
+        let path = temp_db_path("ferrous_failed_log_write");
+        let log_path = path.with_extension("log");
+        let mut db = FerrousDB::open(&path).unwrap();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        db.insert_into("users", insert_row("Bob", 42)).unwrap();
+
+        // With a directory in place of the log every write to it fails
+        let log = fs::read(&log_path).unwrap();
+        fs::remove_file(&log_path).unwrap();
+        fs::create_dir(&log_path).unwrap();
+        assert!(db.insert_into("users", insert_row("Eve", 7)).is_err());
+        db.execute_sql("BEGIN").unwrap();
+        db.insert_into("users", insert_row("Carol", 30)).unwrap();
+        assert!(db.commit().is_err());
+        assert_eq!(select_rows(&db, "SELECT name FROM users"), ["Bob"]);
+
+        fs::remove_dir(&log_path).unwrap();
+        fs::write(&log_path, log).unwrap();
+        db.insert_into("users", insert_row("Alice", 25)).unwrap();
+        drop(db);
+
+        let db = FerrousDB::open(&path).unwrap();
+        assert_eq!(select_rows(&db, "SELECT name FROM users"), ["Bob", "Alice"]);
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&log_path);
+    }
+
+    #[test]
+    fn test_rollback_to_savepoint_keeps_earlier_changes() {
+        let path = temp_db_path("ferrous_savepoint");
+        let mut db = FerrousDB::open(&path).unwrap();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        db.create_index("users", "age", IndexType::BTree).unwrap();
+
+        db.execute_sql("BEGIN").unwrap();
+        db.insert_into("users", insert_row("Bob", 42)).unwrap();
+        db.insert_into("users", insert_row("Eve", 7)).unwrap();
+        let tables = db.tables.clone();
+        let indexes = db.indexes.clone();
+
+        db.execute_sql("SAVEPOINT chunk").unwrap();
+        for age in 100..150 {
+            db.insert_into("users", insert_row("Mallory", age)).unwrap();
+        }
+        db.delete_from("users", Some(parse_expr("age = 42").unwrap()))
+            .unwrap();
+        db.execute_sql("SAVEPOINT inner").unwrap();
+        let mut assignments = HashMap::new();
+        assignments.insert("age".to_string(), DataType::Integer(8));
+        db.update("users", assignments, Some(parse_expr("age = 7").unwrap()))
+            .unwrap();
+        db.create_index("users", "name", IndexType::Hash).unwrap();
+        db.execute_sql("ROLLBACK TO chunk").unwrap();
+
+        assert!(db.in_transaction());
+        assert_eq!(db.tables["users"].rows.len(), 2);
+        for (row, expected) in db.tables["users"].rows.iter().zip(&tables["users"].rows) {
+            assert_eq!(row.data, expected.data);
+        }
+        assert_eq!(db.indexes, indexes);
+        // Savepoints set after the one rolled back to are gone, it stays itself
+        assert!(db.execute_sql("RELEASE inner").is_err());
+        db.execute_sql("ROLLBACK TO SAVEPOINT chunk").unwrap();
+
+        db.insert_into("users", insert_row("Alice", 30)).unwrap();
+        db.execute_sql("RELEASE SAVEPOINT chunk").unwrap();
+        assert!(db.execute_sql("ROLLBACK TO chunk").is_err());
+        db.execute_sql("COMMIT").unwrap();
+        drop(db);
+
+        let db = FerrousDB::open(&path).unwrap();
+        let rows = &db.tables["users"].rows;
+        let data: Vec<_> = rows.iter().map(|row| row.data.clone()).collect();
+        assert_eq!(
+            data,
+            vec![
+                insert_row("Bob", 42),
+                insert_row("Eve", 7),
+                insert_row("Alice", 30)
+            ]
+        );
+        assert_eq!(db.indexes.len(), 1);
+        assert_eq!(
+            db.indexes["users_age"].find(&DataType::Integer(30)),
+            Some(&vec![2])
+        );
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("log"));
+    }
+
+    #[test]
+    fn test_savepoint_requires_transaction() {
+        let mut db = setup();
+        assert!(db.execute_sql("SAVEPOINT a").is_err());
+        assert!(db.execute_sql("RELEASE a").is_err());
+        assert!(db.execute_sql("ROLLBACK TO a").is_err());
+
+        db.execute_sql("BEGIN").unwrap();
+        db.execute_sql("SAVEPOINT a").unwrap();
+        assert!(db.execute_sql("ROLLBACK TO b").is_err());
+        db.execute_sql("ROLLBACK").unwrap();
+        assert!(db.execute_sql("ROLLBACK TO a").is_err());
+    }
+
+    #[test]
+    fn test_committed_snapshot_hides_open_transaction() {
+        let mut db = setup();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        db.insert_into("users", insert_row("Bob", 42)).unwrap();
+        db.insert_into("users", insert_row("Eve", 7)).unwrap();
+
+        fn ages(page: PageResult) -> Vec<DataType> {
+            match page {
+                PageResult::Page(rows) => rows.iter().map(|row| row.data["age"].clone()).collect(),
+                _ => Vec::new(),
+            }
+        }
+
+        db.begin().unwrap();
+        let mut assignments = HashMap::new();
+        assignments.insert("age".to_string(), DataType::Integer(43));
+        db.update("users", assignments, Some(parse_expr("age = 42").unwrap()))
+            .unwrap();
+        db.delete_from("users", Some(parse_expr("age = 7").unwrap()))
+            .unwrap();
+        db.insert_into("users", insert_row("Alice", 30)).unwrap();
+
+        assert_eq!(
+            ages(db.get_page("users", 1, 10, None, None, None)),
+            vec![DataType::Integer(43), DataType::Integer(30)]
+        );
+        assert_eq!(
+            ages(db.get_committed_page("users", 1, 10, None, None, None)),
+            vec![DataType::Integer(42), DataType::Integer(7)]
+        );
+        assert_eq!(db.total_pages("users", 1).unwrap(), 2);
+
+        db.commit().unwrap();
+        assert_eq!(
+            ages(db.get_committed_page("users", 1, 10, None, None, None)),
+            vec![DataType::Integer(43), DataType::Integer(30)]
+        );
+        // Replaced and deleted versions are dropped once committed
+        let rows = &db.tables["users"].rows;
+        assert_eq!(rows.len(), 2);
+        assert!(rows
+            .iter()
+            .all(|row| row.previous.is_none() && row.xmin == 0));
+    }
+
+    #[test]
+    fn test_open_locks_database_file() {
+        let path = temp_db_path("ferrous_lock");
+        let mut writer = FerrousDB::open(&path).unwrap();
+        writer
+            .execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        writer.insert_into("users", insert_row("Bob", 42)).unwrap();
+
+        assert!(matches!(
+            FerrousDB::open(&path),
+            Err(FerrousDBError::DatabaseLocked(_))
+        ));
+        assert!(matches!(
+            FerrousDB::open_read_only(&path),
+            Err(FerrousDBError::DatabaseLocked(_))
+        ));
+        // A refused open leaves the writer's data alone
+        writer.insert_into("users", insert_row("Eve", 7)).unwrap();
+        drop(writer);
+
+        let mut reader = FerrousDB::open_read_only(&path).unwrap();
+        let other_reader = FerrousDB::open_read_only(&path).unwrap();
+        assert!(matches!(
+            FerrousDB::open(&path),
+            Err(FerrousDBError::DatabaseLocked(_))
+        ));
+        assert_eq!(reader.tables["users"].rows.len(), 2);
+        assert!(reader.execute_sql("SELECT * FROM users").is_ok());
+        assert!(matches!(
+            reader.insert_into("users", insert_row("Alice", 30)),
+            Err(FerrousDBError::ReadOnly(_))
+        ));
+        assert!(matches!(
+            reader.execute_sql("CHECKPOINT"),
+            Err(FerrousDBError::ReadOnly(_))
+        ));
+        drop(reader);
+        drop(other_reader);
+
+        let db = FerrousDB::open(&path).unwrap();
+        assert_eq!(db.tables["users"].rows.len(), 2);
+        drop(db);
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("log"));
+    }
+
+    #[test]
+    fn test_read_only_open_replays_log_in_memory() {
+        let path = temp_db_path("ferrous_read_only_recovery");
+        let log_path = path.with_extension("log");
+        let mut db = FerrousDB::open(&path).unwrap();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        drop(db);
+
+        // Committed to the log but never flushed to the database file
+        let mut wal = WriteAheadLog::new(&log_path).unwrap();
+        wal.log(100, &insert_entry("Bob", 42)).unwrap();
+        wal.commit(100).unwrap();
+        drop(wal);
+        let data_file = fs::read(&path).unwrap();
+        let log_file = fs::read(&log_path).unwrap();
+
+        let db = FerrousDB::open_read_only(&path).unwrap();
+        assert_eq!(db.tables["users"].rows.len(), 1);
+        drop(db);
+        assert_eq!(fs::read(&path).unwrap(), data_file);
+        assert_eq!(fs::read(&log_path).unwrap(), log_file);
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&log_path);
+    }
+
+    #[test]
+    fn test_select_where_filters_rows() {
+        let mut db = setup();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER, email TEXT)")
+            .unwrap();
+        db.execute_sql("INSERT INTO users (name, age, email) VALUES ('Alice', 30, 'a@x.io')")
+            .unwrap();
+        db.execute_sql("INSERT INTO users (name, age) VALUES ('Bob', 42)")
+            .unwrap();
+        db.execute_sql("INSERT INTO users (name, age, email) VALUES ('Carol', 25, NULL)")
+            .unwrap();
+
+        let names = |db: &FerrousDB, sql: &str| -> Vec<String> {
+            let SQLCommand::SelectFrom { filter, .. } = parse_sql(sql).unwrap() else {
+                panic!("Expected a SELECT");
+            };
+            match db.get_page("users", 1, 10, filter.as_ref(), None, None) {
+                PageResult::Page(rows) => rows
+                    .iter()
+                    .map(|row| row.data["name"].get_value())
+                    .collect(),
+                _ => Vec::new(),
+            }
+        };
+        assert_eq!(
+            names(&db, "SELECT * FROM users WHERE age > 28"),
+            ["Alice", "Bob"]
+        );
+        assert_eq!(
+            names(
+                &db,
+                "SELECT * FROM users WHERE (age < 28 OR name = 'Bob') AND NOT age = 42"
+            ),
+            ["Carol"]
+        );
+        assert_eq!(
+            names(&db, "SELECT * FROM users WHERE email IS NULL"),
+            ["Bob", "Carol"]
+        );
+        assert_eq!(
+            names(&db, "SELECT * FROM users WHERE users.age IN (25, 42)"),
+            ["Bob", "Carol"]
+        );
+        assert_eq!(
+            names(&db, "SELECT * FROM users WHERE age NOT BETWEEN 26 AND 41"),
+            ["Bob", "Carol"]
+        );
+        assert_eq!(
+            names(&db, "SELECT * FROM users WHERE name LIKE '_a%'"),
+            ["Carol"]
+        );
+        // A comparison with NULL is unknown, so neither side matches
+        assert!(names(&db, "SELECT * FROM users WHERE email <> 'a@x.io'").is_empty());
+
+        assert!(matches!(
+            db.execute_sql("SELECT * FROM users WHERE height > 1"),
+            Err(FerrousDBError::ColumnNotFound(_))
+        ));
+        assert!(matches!(
+            db.execute_sql("SELECT * FROM users WHERE name > 1"),
+            Err(FerrousDBError::InvalidExpression(_))
+        ));
+    }
+
+    #[test]
+    fn test_update_and_delete_where_expressions() {
+        let mut db = setup();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        for (name, age) in [("Alice", 3), ("Bob", 8), ("Carol", 12)] {
+            db.insert_into("users", insert_row(name, age)).unwrap();
+        }
+        db.execute_sql("INSERT INTO users (name) VALUES ('Dave')")
+            .unwrap();
+
+        assert_eq!(
+            db.execute_sql("UPDATE users SET age = 9 WHERE age >= 8 AND name <> 'Carol'")
+                .unwrap(),
+            QueryResult::RowsAffected(1)
+        );
+        // Dave's age is NULL, so neither the condition nor its negation holds
+        assert_eq!(
+            db.execute_sql("DELETE FROM users WHERE NOT age > 5")
+                .unwrap(),
+            QueryResult::RowsAffected(1)
+        );
+        let ages: Vec<_> = db.tables["users"]
+            .rows
+            .iter()
+            .map(|row| row.data.get("age").cloned())
+            .collect();
+        assert_eq!(
+            ages,
+            [
+                Some(DataType::Integer(9)),
+                Some(DataType::Integer(12)),
+                None
+            ]
+        );
+
+        assert!(matches!(
+            db.execute_sql("DELETE FROM users WHERE height > 1"),
+            Err(FerrousDBError::ColumnNotFound(_))
+        ));
+        assert_eq!(
+            db.execute_sql("DELETE FROM users").unwrap(),
+            QueryResult::RowsAffected(3)
+        );
+    }
+
+    #[test]
+    fn test_check_constraint() {
+        let mut db = setup();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER CHECK (age >= 0))")
+            .unwrap();
+        db.execute_sql("INSERT INTO users (name, age) VALUES ('Bob', 42)")
+            .unwrap();
+        // NULL doesn't make the check false
+        db.execute_sql("INSERT INTO users (name) VALUES ('Eve')")
+            .unwrap();
+
+        assert!(matches!(
+            db.execute_sql("INSERT INTO users (name, age) VALUES ('Mallory', -1)"),
+            Err(FerrousDBError::ConstraintViolation(_))
+        ));
+        assert!(matches!(
+            db.execute_sql("UPDATE users SET age = -5 WHERE name = 'Bob'"),
+            Err(FerrousDBError::ConstraintViolation(_))
+        ));
+        assert_eq!(db.tables["users"].rows.len(), 2);
+        assert_eq!(
+            db.tables["users"].rows[0].data["age"],
+            DataType::Integer(42)
+        );
+
+        assert!(matches!(
+            db.execute_sql("CREATE TABLE bad (age INTEGER CHECK (height > 0))"),
+            Err(FerrousDBError::ColumnNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_select_projection() {
+        let mut db = setup();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        db.insert_into("users", insert_row("Alice", 30)).unwrap();
+        db.execute_sql("INSERT INTO users (name) VALUES ('Bob')")
+            .unwrap();
+
+        let result = db.select("SELECT age, users.name FROM users").unwrap();
+        assert_eq!(result.column_names(), ["age", "name"]);
+        assert_eq!(
+            result.rows,
+            [
+                vec![
+                    Some(DataType::Integer(30)),
+                    Some(DataType::Text("Alice".into()))
+                ],
+                vec![None, Some(DataType::Text("Bob".into()))],
+            ]
+        );
+
+        let result = db
+            .select("SELECT upper(name) AS shout, age + 1, *, users.* FROM users WHERE age > 0")
+            .unwrap();
+        assert_eq!(
+            result.column_names(),
+            ["shout", "age + 1", "name", "age", "name", "age"]
+        );
+        assert_eq!(result.columns[0].data_type, "TEXT");
+        assert_eq!(result.columns[1].data_type, "INTEGER");
+        assert_eq!(
+            result.rows[0][..2],
+            [
+                Some(DataType::Text("ALICE".into())),
+                Some(DataType::Integer(31))
+            ]
+        );
+
+        assert!(matches!(
+            db.select("SELECT height FROM users"),
+            Err(FerrousDBError::ColumnNotFound(_))
+        ));
+        assert!(matches!(
+            db.select("SELECT orders.* FROM users"),
+            Err(FerrousDBError::TableNotFound(_))
+        ));
+        assert!(matches!(
+            db.select("SELECT nope(name) FROM users"),
+            Err(FerrousDBError::ParseError(_))
+        ));
+
+        match db.execute_sql("SELECT name FROM users WHERE age = 30").unwrap() {
+            QueryResult::Rows(result) => {
+                assert_eq!(result.rows, [vec![Some(DataType::Text("Alice".into()))]])
+            }
+            other => panic!("Expected rows, got {:?}", other),
+        }
+        assert_eq!(
+            db.execute_sql("INSERT INTO users (name) VALUES ('Eve')")
+                .unwrap(),
+            QueryResult::RowsAffected(1)
+        );
+    }
+
+    #[test]
+    fn test_aggregates_with_group_by_and_having() {
+        let mut db = setup();
+        db.execute_sql("CREATE TABLE sales (region TEXT, product TEXT, amount INTEGER)")
+            .unwrap();
+        for (region, product, amount) in [
+            ("north", "tea", Some(10)),
+            ("south", "tea", Some(4)),
+            ("north", "coffee", Some(7)),
+            ("north", "tea", None),
+            ("south", "coffee", Some(4)),
+            ("east", "tea", Some(1)),
+        ] {
+            let amount = amount.map_or("NULL".to_string(), |a: i64| a.to_string());
+            db.execute_sql(&format!(
+                "INSERT INTO sales (region, product, amount) VALUES ('{}', '{}', {})",
+                region, product, amount
+            ))
+            .unwrap();
+        }
+        let int = |i| Some(DataType::Integer(i));
+        let text = |s: &str| Some(DataType::Text(s.into()));
+
+        let result = db
+            .select(
+                "SELECT count(*), count(amount), count(DISTINCT amount), sum(amount), \
+                 avg(amount), min(amount), max(product) FROM sales",
+            )
+            .unwrap();
+        assert_eq!(
+            result.column_names(),
+            [
+                "count(*)",
+                "count(amount)",
+                "count(DISTINCT amount)",
+                "sum(amount)",
+                "avg(amount)",
+                "min(amount)",
+                "max(product)"
+            ]
+        );
+        assert_eq!(result.columns[6].data_type, "TEXT");
+        assert_eq!(
+            result.rows,
+            [vec![
+                int(6),
+                int(5),
+                int(4),
+                int(26),
+                int(5),
+                int(1),
+                text("tea")
+            ]]
+        );
+
+        // Groups come out in the order of their first row
+        let result = db
+            .select("SELECT region, count(*) AS n, sum(amount) FROM sales GROUP BY region")
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            [
+                vec![text("north"), int(3), int(17)],
+                vec![text("south"), int(2), int(8)],
+                vec![text("east"), int(1), int(1)],
+            ]
+        );
+
+        let result = db
+            .select(
+                "SELECT region, product, max(amount) FROM sales \
+                 WHERE amount > 1 GROUP BY region, product",
+            )
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            [
+                vec![text("north"), text("tea"), int(10)],
+                vec![text("south"), text("tea"), int(4)],
+                vec![text("north"), text("coffee"), int(7)],
+                vec![text("south"), text("coffee"), int(4)],
+            ]
+        );
+
+        let result = db
+            .select(
+                "SELECT upper(region) AS r, sum(amount) AS total FROM sales \
+                 GROUP BY region HAVING count(*) > 1",
+            )
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            [vec![text("NORTH"), int(17)], vec![text("SOUTH"), int(8)]]
+        );
+
+        // Without GROUP BY an empty table still forms one group
+        let result = db
+            .select("SELECT count(*), sum(amount) FROM sales WHERE amount > 100")
+            .unwrap();
+        assert_eq!(result.rows, [vec![int(0), None]]);
+
+        for sql in [
+            "SELECT product, count(*) FROM sales GROUP BY region",
+            "SELECT amount + sum(amount) FROM sales",
+            "SELECT * FROM sales GROUP BY region",
+            "SELECT region FROM sales WHERE count(*) > 1",
+            "SELECT sum(product) FROM sales",
+        ] {
+            assert!(
+                matches!(db.select(sql), Err(FerrousDBError::InvalidExpression(_))),
+                "{} should be rejected",
+                sql
+            );
+        }
+        assert!(matches!(
+            db.select("SELECT region FROM sales GROUP BY height"),
+            Err(FerrousDBError::ColumnNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_order_by_multiple_keys() {
+        let mut db = setup();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        for (name, age) in [("Carol", 9), ("Alice", 10), ("Bob", 9), ("Dave", 100)] {
+            db.insert_into("users", insert_row(name, age)).unwrap();
+        }
+        db.execute_sql("INSERT INTO users (name) VALUES ('Eve')")
+            .unwrap();
+        let names = |db: &FerrousDB, sql: &str| -> Vec<String> {
+            let result = db.select(sql).unwrap();
+            result
+                .rows
+                .iter()
+                .map(|row| row[0].as_ref().unwrap().get_value())
+                .collect()
+        };
+
+        // Integers compare as numbers, and NULL is the smallest value
+        assert_eq!(
+            names(&db, "SELECT name FROM users ORDER BY age"),
+            ["Eve", "Carol", "Bob", "Alice", "Dave"]
+        );
+        assert_eq!(
+            names(&db, "SELECT name FROM users ORDER BY age DESC, name ASC"),
+            ["Dave", "Alice", "Bob", "Carol", "Eve"]
+        );
+        assert_eq!(
+            names(
+                &db,
+                "SELECT name FROM users ORDER BY age ASC NULLS LAST, name DESC"
+            ),
+            ["Carol", "Bob", "Alice", "Dave", "Eve"]
+        );
+        assert_eq!(
+            names(
+                &db,
+                "SELECT name FROM users ORDER BY age DESC NULLS FIRST LIMIT 2"
+            ),
+            ["Eve", "Dave"]
+        );
+
+        // Keys can be expressions, output names or positions
+        assert_eq!(
+            names(
+                &db,
+                "SELECT name, age % 10 AS digit FROM users ORDER BY digit, 1 DESC"
+            ),
+            ["Eve", "Dave", "Alice", "Carol", "Bob"]
+        );
+        assert_eq!(
+            names(
+                &db,
+                "SELECT name FROM users WHERE age > 0 ORDER BY length(name), age * -1"
+            ),
+            ["Bob", "Dave", "Alice", "Carol"]
+        );
+        let result = db
+            .select("SELECT age, count(*) FROM users GROUP BY age ORDER BY count(*) DESC, age")
+            .unwrap();
+        assert_eq!(
+            result.rows[0],
+            [Some(DataType::Integer(9)), Some(DataType::Integer(2))]
+        );
+        assert_eq!(result.rows[1], [None, Some(DataType::Integer(1))]);
+
+        assert!(matches!(
+            db.select("SELECT name FROM users ORDER BY 2"),
+            Err(FerrousDBError::InvalidExpression(_))
+        ));
+        assert!(matches!(
+            db.select("SELECT name FROM users ORDER BY height"),
+            Err(FerrousDBError::ColumnNotFound(_))
+        ));
+        assert!(matches!(
+            db.select("SELECT age FROM users GROUP BY age ORDER BY name"),
+            Err(FerrousDBError::InvalidExpression(_))
+        ));
+
+        assert_eq!(
+            parse_sql("SELECT name FROM users ORDER BY age DESC, name NULLS LAST")
+                .unwrap()
+                .to_string(),
+            "SELECT name FROM users ORDER BY age DESC, name NULLS LAST"
+        );
+    }
+
+    #[test]
+    fn test_select_limit_and_offset_count_rows() {
+        let mut db = setup();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER)")
+            .unwrap();
+        let ages = |db: &FerrousDB, sql: &str| -> Vec<Option<DataType>> {
+            db.select(sql).unwrap().rows.into_iter().flatten().collect()
+        };
+        assert!(ages(&db, "SELECT age FROM users").is_empty());
+        assert_eq!(
+            ages(&db, "SELECT count(*) FROM users LIMIT 5"),
+            [Some(DataType::Integer(0))]
+        );
+
+        for age in 0..20 {
+            db.insert_into("users", insert_row("User", age)).unwrap();
+        }
+        let range = |ages: std::ops::Range<i64>| -> Vec<Option<DataType>> {
+            ages.map(|age| Some(DataType::Integer(age))).collect()
+        };
+        assert_eq!(
+            ages(&db, "SELECT age FROM users LIMIT 10 OFFSET 5"),
+            range(5..15)
+        );
+        assert_eq!(ages(&db, "SELECT age FROM users OFFSET 17"), range(17..20));
+        assert_eq!(ages(&db, "SELECT age FROM users LIMIT 3"), range(0..3));
+        assert!(ages(&db, "SELECT age FROM users LIMIT 0").is_empty());
+        assert!(ages(&db, "SELECT age FROM users OFFSET 20").is_empty());
+        assert_eq!(
+            ages(
+                &db,
+                "SELECT age FROM users ORDER BY age DESC LIMIT 2 OFFSET 1"
+            ),
+            range(17..19).into_iter().rev().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ages(
+                &db,
+                "SELECT age % 3 AS r FROM users GROUP BY age % 3 ORDER BY r LIMIT 5 OFFSET 1"
+            ),
+            range(1..3)
+        );
+        assert!(matches!(
+            db.select("SELECT age FROM users LIMIT 'ten'"),
+            Err(FerrousDBError::ParseError(_))
+        ));
+
+        assert_eq!(
+            parse_sql("SELECT age FROM users LIMIT 10 OFFSET 5")
+                .unwrap()
+                .to_string(),
+            "SELECT age FROM users LIMIT 10 OFFSET 5"
+        );
+        assert_eq!(
+            parse_sql("SELECT age FROM users OFFSET 5")
+                .unwrap()
+                .to_string(),
+            "SELECT age FROM users OFFSET 5"
+        );
+
+        // Pages are a limit and an offset over the same rows
+        match db.get_page("users", 3, 8, None, None, Some(("age".to_string(), false))) {
+            PageResult::Page(rows) => assert_eq!(
+                rows.iter()
+                    .map(|row| Some(row.data["age"].clone()))
+                    .collect::<Vec<_>>(),
+                range(0..4).into_iter().rev().collect::<Vec<_>>()
+            ),
+            _ => panic!("Expected PageResult::Page"),
+        }
+        assert!(matches!(
+            db.get_page("users", 4, 8, None, None, None),
+            PageResult::PageOutOfRange
+        ));
+        assert!(matches!(
+            db.get_page("users", usize::MAX, 8, None, None, None),
+            PageResult::PageOutOfRange
+        ));
+    }
+
+    #[test]
+    fn test_joins() {
+        let mut db = setup();
+        db.execute_sql("CREATE TABLE users (id INTEGER, name TEXT)")
+            .unwrap();
+        db.execute_sql("CREATE TABLE orders (id INTEGER, user_id INTEGER, total INTEGER)")
+            .unwrap();
+        db.execute_sql("INSERT INTO users (id, name) VALUES (1, 'Alice')")
+            .unwrap();
+        db.execute_sql("INSERT INTO users (id, name) VALUES (2, 'Bob')")
+            .unwrap();
+        db.execute_sql("INSERT INTO users (id, name) VALUES (3, 'Carol')")
+            .unwrap();
+        for (id, user_id, total) in [(10, 1, 5), (11, 1, 7), (12, 2, 3), (13, 4, 9)] {
+            db.execute_sql(&format!(
+                "INSERT INTO orders (id, user_id, total) VALUES ({}, {}, {})",
+                id, user_id, total
+            ))
+            .unwrap();
+        }
+
+        let inner = "SELECT u.name, o.total FROM users u JOIN orders o ON o.user_id = u.id \
+                     ORDER BY o.id";
+        assert_eq!(select_rows(&db, inner), ["Alice,5", "Alice,7", "Bob,3"]);
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT name, total FROM users LEFT JOIN orders ON user_id = users.id \
+                 ORDER BY name, total"
+            ),
+            ["Alice,5", "Alice,7", "Bob,3", "Carol,NULL"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT u.name, o.id FROM users u RIGHT JOIN orders o ON o.user_id = u.id \
+                 ORDER BY o.id"
+            ),
+            ["Alice,10", "Alice,11", "Bob,12", "NULL,13"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT u.id, o.id FROM users u FULL JOIN orders o ON o.user_id = u.id \
+                 ORDER BY u.id NULLS LAST, o.id"
+            ),
+            ["1,10", "1,11", "2,12", "3,NULL", "NULL,13"]
+        );
+        assert_eq!(
+            select_rows(&db, "SELECT count(*) FROM users, orders"),
+            ["12"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT count(*) FROM users CROSS JOIN orders WHERE total > 5"
+            ),
+            ["6"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT a.name, b.name FROM users a JOIN users b ON b.id = a.id + 1 ORDER BY a.id"
+            ),
+            ["Alice,Bob", "Bob,Carol"]
+        );
+
+        // Joins combine with filtering, grouping and ordering
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT u.name, count(o.id) FROM users u LEFT JOIN orders o ON o.user_id = u.id \
+                 WHERE u.id < 3 OR o.id IS NULL GROUP BY u.name ORDER BY u.name"
+            ),
+            ["Alice,2", "Bob,1", "Carol,0"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT u.name, sum(o.total) AS spent FROM users u JOIN orders o \
+                 ON o.user_id = u.id AND o.total > 3 GROUP BY u.name ORDER BY spent DESC"
+            ),
+            ["Alice,12"]
+        );
 
-    fn setup() -> FerrousDB {
-        // Remove any existing test database file
-        let _ = fs::remove_file("data.ferrous");
-        FerrousDB::new()
+        // Columns of the same name are told apart by their table
+        let result = db
+            .select("SELECT * FROM users u JOIN orders o ON o.user_id = u.id")
+            .unwrap();
+        assert_eq!(
+            result
+                .columns
+                .iter()
+                .map(|column| column.name.as_str())
+                .collect::<Vec<_>>(),
+            ["u.id", "name", "o.id", "user_id", "total"]
+        );
+        assert!(matches!(
+            db.select("SELECT id FROM users JOIN orders ON user_id = users.id"),
+            Err(FerrousDBError::InvalidExpression(_))
+        ));
+        assert!(matches!(
+            db.select("SELECT name FROM users JOIN users ON id = id"),
+            Err(FerrousDBError::InvalidExpression(_))
+        ));
+        assert!(matches!(
+            db.select("SELECT name FROM users u JOIN orders o ON o.user_id = users.id"),
+            Err(FerrousDBError::ColumnNotFound(_))
+        ));
+        assert_eq!(
+            db.select("SELECT name FROM users JOIN payments ON id = user_id")
+                .unwrap_err()
+                .to_string(),
+            "Table 'payments' not found"
+        );
+        assert!(matches!(
+            db.select("SELECT name FROM users LEFT JOIN orders USING (id)"),
+            Err(FerrousDBError::ParseError(_))
+        ));
+
+        // An index on the joined column gives the same rows
+        db.create_index("orders", "user_id", IndexType::Hash)
+            .unwrap();
+        assert_eq!(select_rows(&db, inner), ["Alice,5", "Alice,7", "Bob,3"]);
+
+        assert_eq!(
+            parse_sql("SELECT u.name FROM users AS u LEFT JOIN orders AS o ON o.user_id = u.id")
+                .unwrap()
+                .to_string(),
+            "SELECT u.name FROM users AS u LEFT JOIN orders AS o ON o.user_id = u.id"
+        );
     }
 
     #[test]
-    fn test_create_table() {
+    fn test_subqueries() {
         let mut db = setup();
-        let result = db.create_table(
-            "users",
-            vec![
-                ColumnSchema::new("name".to_string(), "TEXT".to_string()),
-                ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
-            ],
+        db.execute_sql("CREATE TABLE users (id INTEGER, name TEXT, age INTEGER)")
+            .unwrap();
+        db.execute_sql("CREATE TABLE orders (id INTEGER, user_id INTEGER, total INTEGER)")
+            .unwrap();
+        for (id, name, age) in [(1, "Alice", 30), (2, "Bob", 20), (3, "Carol", 40)] {
+            db.execute_sql(&format!(
+                "INSERT INTO users (id, name, age) VALUES ({}, '{}', {})",
+                id, name, age
+            ))
+            .unwrap();
+        }
+        for (id, user_id, total) in [(10, 1, 5), (11, 1, 7), (12, 2, 3), (13, 4, 9)] {
+            db.execute_sql(&format!(
+                "INSERT INTO orders (id, user_id, total) VALUES ({}, {}, {})",
+                id, user_id, total
+            ))
+            .unwrap();
+        }
+
+        // Scalar subqueries
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT name, (SELECT max(total) FROM orders) FROM users WHERE id = 1"
+            ),
+            ["Alice,9"]
         );
-        assert!(result.is_ok());
-        assert_eq!(db.tables.len(), 1);
         assert_eq!(
-            db.tables.get("users").unwrap().schema,
-            vec![
-                ColumnSchema::new("name".to_string(), "TEXT".to_string()),
-                ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
-            ]
+            select_rows(
+                &db,
+                "SELECT name FROM users WHERE age > (SELECT avg(age) FROM users)"
+            ),
+            ["Carol"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT name FROM users WHERE age = (SELECT age FROM users WHERE id = 5)"
+            ),
+            Vec::<String>::new()
+        );
+
+        // IN and EXISTS
+        let in_orders = "SELECT name FROM users WHERE id IN (SELECT user_id FROM orders) \
+                         ORDER BY name";
+        assert_eq!(select_rows(&db, in_orders), ["Alice", "Bob"]);
+        let not_in_orders = "SELECT name FROM users WHERE id NOT IN (SELECT user_id FROM orders)";
+        assert_eq!(select_rows(&db, not_in_orders), ["Carol"]);
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT name FROM users u WHERE EXISTS \
+                 (SELECT id FROM orders o WHERE o.user_id = u.id AND o.total > 6)"
+            ),
+            ["Alice"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT name FROM users WHERE NOT EXISTS \
+                 (SELECT id FROM orders WHERE user_id = users.id) ORDER BY name"
+            ),
+            ["Carol"]
+        );
+
+        // Correlated subqueries run for every row and see its columns
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT name, (SELECT sum(total) FROM orders WHERE user_id = users.id) \
+                 FROM users ORDER BY id"
+            ),
+            ["Alice,12", "Bob,3", "Carol,NULL"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT name FROM users WHERE \
+                 (SELECT count(*) FROM orders WHERE user_id = users.id) > 1"
+            ),
+            ["Alice"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT name FROM users u WHERE EXISTS (SELECT id FROM orders WHERE \
+                 user_id = u.id AND total IN (SELECT total FROM orders WHERE total > u.age / 5))"
+            ),
+            ["Alice"]
+        );
+
+        // Derived tables
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT t.name, t.spent FROM (SELECT u.name, sum(o.total) AS spent \
+                 FROM users u JOIN orders o ON o.user_id = u.id GROUP BY u.name) AS t \
+                 WHERE t.spent > 5"
+            ),
+            ["Alice,12"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT u.name, t.n FROM users u JOIN \
+                 (SELECT user_id, count(*) AS n FROM orders GROUP BY user_id) AS t \
+                 ON t.user_id = u.id ORDER BY u.name"
+            ),
+            ["Alice,2", "Bob,1"]
         );
+
+        assert!(matches!(
+            db.select("SELECT name FROM users WHERE id = (SELECT user_id FROM orders)"),
+            Err(FerrousDBError::InvalidExpression(_))
+        ));
+        assert!(matches!(
+            db.select("SELECT name FROM users WHERE id IN (SELECT id, user_id FROM orders)"),
+            Err(FerrousDBError::InvalidExpression(_))
+        ));
+        assert!(matches!(
+            db.select("SELECT name FROM users WHERE id IN (SELECT price FROM orders)"),
+            Err(FerrousDBError::ColumnNotFound(_))
+        ));
+        assert!(matches!(
+            db.select("SELECT n FROM (SELECT count(*) AS n FROM orders)"),
+            Err(FerrousDBError::ParseError(_))
+        ));
+
+        // NOT IN is unknown when the subquery gives a NULL
+        db.execute_sql("INSERT INTO orders (id, total) VALUES (14, 1)")
+            .unwrap();
+        assert!(select_rows(&db, not_in_orders).is_empty());
+        match db
+            .execute_sql("DELETE FROM orders WHERE user_id NOT IN (SELECT id FROM users)")
+            .unwrap()
+        {
+            QueryResult::RowsAffected(count) => assert_eq!(count, 1),
+            _ => panic!("Expected QueryResult::RowsAffected"),
+        }
+        assert_eq!(select_rows(&db, "SELECT count(*) FROM orders"), ["4"]);
+
+        assert_eq!(
+            parse_sql(in_orders).unwrap().to_string(),
+            "SELECT name FROM users WHERE id IN (SELECT user_id FROM orders) ORDER BY name"
+        );
+        let derived = "SELECT n FROM (SELECT count(*) AS n FROM orders) AS t \
+                       WHERE NOT EXISTS (SELECT id FROM users)";
+        assert_eq!(parse_sql(derived).unwrap().to_string(), derived);
     }
 
     #[test]
-    fn test_insert_into() {
+    fn test_set_operations() {
         let mut db = setup();
-        let create_result = db.create_table(
-            "users",
-            vec![
-                ColumnSchema::new("name".to_string(), "TEXT".to_string()),
-                ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
-            ],
+        for table in ["archive", "live"] {
+            db.execute_sql(&format!("CREATE TABLE {} (id INTEGER, name TEXT)", table))
+                .unwrap();
+        }
+        for (table, ids) in [("archive", [1, 2, 2, 3, 3]), ("live", [2, 2, 2, 3, 4])] {
+            for id in ids {
+                db.execute_sql(&format!(
+                    "INSERT INTO {} (id, name) VALUES ({}, 'n{}')",
+                    table, id, id
+                ))
+                .unwrap();
+            }
+        }
+        let ids = |db: &FerrousDB, op: &str| -> Vec<String> {
+            select_rows(
+                db,
+                &format!(
+                    "SELECT id FROM archive {} SELECT id FROM live ORDER BY id",
+                    op
+                ),
+            )
+        };
+
+        assert_eq!(ids(&db, "UNION"), ["1", "2", "3", "4"]);
+        assert_eq!(
+            ids(&db, "UNION ALL"),
+            ["1", "2", "2", "2", "2", "2", "3", "3", "3", "4"]
         );
-        assert!(create_result.is_ok());
+        assert_eq!(ids(&db, "INTERSECT"), ["2", "3"]);
+        assert_eq!(ids(&db, "INTERSECT ALL"), ["2", "2", "3"]);
+        assert_eq!(ids(&db, "EXCEPT"), ["1"]);
+        assert_eq!(ids(&db, "EXCEPT ALL"), ["1", "3"]);
 
-        let mut values = HashMap::new();
-        values.insert("name".to_string(), DataType::Text("Alice".to_string()));
-        values.insert("age".to_string(), DataType::Integer(30));
-        let insert_result = db.insert_into("users", values);
-        assert!(insert_result.is_ok());
+        // ORDER BY and LIMIT apply to the combined rows, named after the
+        // left side
+        let result = db
+            .select(
+                "SELECT id AS n, name FROM archive UNION SELECT id, name FROM live \
+                 ORDER BY n DESC LIMIT 2 OFFSET 1",
+            )
+            .unwrap();
+        assert_eq!(
+            result
+                .columns
+                .iter()
+                .map(|column| column.name.as_str())
+                .collect::<Vec<_>>(),
+            ["n", "name"]
+        );
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[0][1], Some(DataType::Text("n3".to_string())));
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT id FROM archive UNION SELECT id FROM live ORDER BY 1 DESC LIMIT 1"
+            ),
+            ["4"]
+        );
 
-        assert_eq!(db.tables.get("users").unwrap().rows.len(), 1);
-        let row = &db.tables.get("users").unwrap().rows[0];
-        assert_eq!(row.data.get("name").unwrap().get_value(), "Alice");
-        assert_eq!(row.data.get("age").unwrap().get_value(), "30");
+        // INTERSECT binds tighter than UNION and EXCEPT
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT id FROM archive EXCEPT SELECT id FROM live \
+                 UNION SELECT id FROM live WHERE id > 3 ORDER BY id"
+            ),
+            ["1", "4"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT id FROM live UNION SELECT id FROM live \
+                 INTERSECT SELECT id FROM archive WHERE id = 1 ORDER BY id"
+            ),
+            ["2", "3", "4"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT count(*) FROM \
+                 (SELECT id FROM archive UNION ALL SELECT id FROM live) AS t"
+            ),
+            ["10"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT label FROM (SELECT name AS label FROM live \
+                 WHERE id IN (SELECT id FROM live EXCEPT SELECT id FROM archive)) AS t"
+            ),
+            ["n4"]
+        );
+
+        // NULLs are equal to each other
+        db.execute_sql("INSERT INTO archive (id) VALUES (5)")
+            .unwrap();
+        db.execute_sql("INSERT INTO live (id) VALUES (5)").unwrap();
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT id, name FROM archive INTERSECT SELECT id, name FROM live ORDER BY id"
+            ),
+            ["2,n2", "3,n3", "5,NULL"]
+        );
+
+        for sql in [
+            "SELECT id FROM archive UNION SELECT id, name FROM live",
+            "SELECT id FROM archive UNION SELECT name FROM live",
+            "SELECT id FROM archive UNION SELECT id FROM live ORDER BY id + 1",
+        ] {
+            assert!(matches!(
+                db.select(sql),
+                Err(FerrousDBError::InvalidExpression(_))
+            ));
+        }
+        assert!(matches!(
+            db.select("SELECT id FROM archive UNION SELECT id FROM live ORDER BY name"),
+            Err(FerrousDBError::ColumnNotFound(_))
+        ));
+
+        for sql in [
+            "SELECT id FROM archive UNION ALL SELECT id FROM live ORDER BY id DESC LIMIT 3",
+            "(SELECT id FROM archive LIMIT 1) EXCEPT SELECT id FROM live",
+            "SELECT id FROM archive INTERSECT SELECT id FROM live UNION SELECT id FROM archive",
+            "SELECT id FROM archive UNION (SELECT id FROM live UNION SELECT id FROM archive)",
+        ] {
+            assert_eq!(parse_sql(sql).unwrap().to_string(), sql);
+        }
     }
 
     #[test]
-    fn test_select_from_with_limit_and_offset() {
+    fn test_distinct() {
         let mut db = setup();
-        let create_result = db.create_table(
-            "users",
-            vec![
-                ColumnSchema::new("name".to_string(), "TEXT".to_string()),
-                ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
-            ],
+        db.execute_sql("CREATE TABLE people (name TEXT, country TEXT, age INTEGER)")
+            .unwrap();
+        for (name, country, age) in [
+            ("ann", "fr", 30),
+            ("bob", "de", 25),
+            ("cid", "fr", 41),
+            ("dan", "it", 25),
+            ("eve", "de", 35),
+            ("fay", "fr", 30),
+        ] {
+            db.execute_sql(&format!(
+                "INSERT INTO people (name, country, age) VALUES ('{}', '{}', {})",
+                name, country, age
+            ))
+            .unwrap();
+        }
+        db.execute_sql("INSERT INTO people (name) VALUES ('gus')")
+            .unwrap();
+
+        // Duplicates are left out in the order rows come, NULLs being equal
+        assert_eq!(
+            select_rows(&db, "SELECT DISTINCT country FROM people"),
+            ["fr", "de", "it", "NULL"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT DISTINCT country, age FROM people WHERE age IS NOT NULL \
+                 ORDER BY country, age"
+            ),
+            ["de,25", "de,35", "fr,30", "fr,41", "it,25"]
+        );
+        // LIMIT and OFFSET count distinct rows
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT DISTINCT age FROM people ORDER BY age DESC NULLS LAST LIMIT 2 OFFSET 1"
+            ),
+            ["35", "30"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT DISTINCT count(*) FROM people WHERE age IS NOT NULL \
+                 GROUP BY country ORDER BY 1"
+            ),
+            ["1", "2", "3"]
         );
-        assert!(create_result.is_ok());
 
-        // Insert 5 users
-        for i in 1..=5 {
-            let mut values = HashMap::new();
-            values.insert("name".to_string(), DataType::Text(format!("User{}", i)));
-            values.insert("age".to_string(), DataType::Integer(20 + i));
-            let insert_result = db.insert_into("users", values);
-            assert!(insert_result.is_ok());
+        // DISTINCT ON keeps the first row of each country in ORDER BY order
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT DISTINCT ON (country) country, name FROM people \
+                 WHERE age IS NOT NULL ORDER BY country, age DESC"
+            ),
+            ["de,eve", "fr,cid", "it,dan"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT DISTINCT ON (age, country) name FROM people \
+                 WHERE age IS NOT NULL ORDER BY country, age, name LIMIT 3"
+            ),
+            ["bob", "eve", "ann"]
+        );
+        assert_eq!(
+            select_rows(&db, "SELECT DISTINCT ON (1) age FROM people ORDER BY age"),
+            ["NULL", "25", "30", "35", "41"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT DISTINCT ON (country) country, max(age) FROM people \
+                 GROUP BY country, age ORDER BY country, max(age)"
+            ),
+            ["NULL,NULL", "de,25", "fr,30", "it,25"]
+        );
+
+        for sql in [
+            "SELECT DISTINCT country FROM people ORDER BY age",
+            "SELECT DISTINCT ON (country) name FROM people ORDER BY name",
+            "SELECT DISTINCT ON (country, age) name FROM people ORDER BY country, name",
+            "SELECT DISTINCT ON (age) country, count(*) FROM people GROUP BY country",
+            "SELECT DISTINCT ON (3) name FROM people",
+        ] {
+            assert!(matches!(
+                db.select(sql),
+                Err(FerrousDBError::InvalidExpression(_))
+            ));
         }
 
-        // Test with limit 2 and offset 1
-        let table = db.get_page("users", 2, 2, None, None);
-        match table {
-            PageResult::Page(rows) => {
-                assert_eq!(rows.len(), 2);
-                assert_eq!(rows[0].data.get("name").unwrap().get_value(), "User3");
-                assert_eq!(rows[1].data.get("name").unwrap().get_value(), "User4");
-            }
-            _ => panic!("Expected PageResult::Page"),
-        };
+        for sql in [
+            "SELECT DISTINCT country FROM people ORDER BY country LIMIT 2",
+            "SELECT DISTINCT ON (country, age) name FROM people ORDER BY country, age",
+        ] {
+            assert_eq!(parse_sql(sql).unwrap().to_string(), sql);
+        }
+    }
 
-        // Test with limit 3 and offset 3
-        let table = db.get_page("users", 3, 2, None, None);
-        match table {
-            PageResult::Page(rows) => {
-                assert_eq!(rows.len(), 1); // Only 1 row left
-                assert_eq!(rows[0].data.get("name").unwrap().get_value(), "User5");
-            }
-            _ => panic!("Expected PageResult::Page"),
-        };
+    #[test]
+    fn test_with_queries() {
+        let mut db = setup();
+        db.execute_sql("CREATE TABLE staff (id INTEGER, name TEXT, manager INTEGER)")
+            .unwrap();
+        for (id, name, manager) in [
+            (1, "ceo", "NULL"),
+            (2, "cto", "1"),
+            (3, "cfo", "1"),
+            (4, "dev", "2"),
+            (5, "intern", "4"),
+        ] {
+            db.execute_sql(&format!(
+                "INSERT INTO staff (id, name, manager) VALUES ({}, '{}', {})",
+                id, name, manager
+            ))
+            .unwrap();
+        }
+
+        // Each CTE reads those before it and hides tables of its name
+        assert_eq!(
+            select_rows(
+                &db,
+                "WITH bosses AS (SELECT DISTINCT manager AS id FROM staff), \
+                 named (boss) AS (SELECT s.name FROM staff s JOIN bosses b ON b.id = s.id) \
+                 SELECT boss FROM named ORDER BY boss"
+            ),
+            ["ceo", "cto", "dev"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "WITH staff AS (SELECT id FROM staff WHERE id > 3) SELECT count(*) FROM staff"
+            ),
+            ["2"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "WITH top AS (SELECT id FROM staff WHERE manager IS NULL) \
+                 SELECT name FROM staff WHERE manager IN (SELECT id FROM top) \
+                 UNION SELECT name FROM staff WHERE id = (SELECT id FROM top) ORDER BY name"
+            ),
+            ["ceo", "cfo", "cto"]
+        );
+
+        // A recursive CTE runs until its recursive term finds no new rows
+        assert_eq!(
+            select_rows(
+                &db,
+                "WITH RECURSIVE chain (id, name, depth) AS ( \
+                 SELECT id, name, 0 FROM staff WHERE manager IS NULL \
+                 UNION ALL \
+                 SELECT s.id, s.name, c.depth + 1 FROM staff s JOIN chain c ON s.manager = c.id) \
+                 SELECT name, depth FROM chain ORDER BY depth, name"
+            ),
+            ["ceo,0", "cfo,1", "cto,1", "dev,2", "intern,3"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "WITH RECURSIVE above (id) AS ( \
+                 SELECT manager FROM staff WHERE name = 'intern' \
+                 UNION SELECT manager FROM staff WHERE id IN (SELECT id FROM above)) \
+                 SELECT name FROM staff JOIN above ON above.id = staff.id ORDER BY staff.id"
+            ),
+            ["ceo", "cto", "dev"]
+        );
+        // Without ALL, rows found before end the recursion even in a cycle
+        db.execute_sql("UPDATE staff SET manager = 5 WHERE id = 1")
+            .unwrap();
+        assert_eq!(
+            select_rows(
+                &db,
+                "WITH RECURSIVE reach (id) AS (SELECT 1 FROM staff WHERE id = 1 \
+                 UNION SELECT s.id FROM staff s JOIN reach r ON s.manager = r.id) \
+                 SELECT count(*) FROM reach"
+            ),
+            ["5"]
+        );
+        assert!(matches!(
+            db.select(
+                "WITH RECURSIVE reach (id) AS (SELECT 1 FROM staff WHERE id = 1 \
+                 UNION ALL SELECT s.id FROM staff s JOIN reach r ON s.manager = r.id) \
+                 SELECT count(*) FROM reach"
+            ),
+            Err(FerrousDBError::RecursionLimit(_))
+        ));
+
+        for sql in [
+            "WITH c (a, b) AS (SELECT id FROM staff) SELECT a FROM c",
+            "WITH RECURSIVE c (n) AS (SELECT 1 FROM staff \
+             UNION SELECT name FROM c JOIN staff ON id = n) SELECT n FROM c",
+        ] {
+            assert!(matches!(
+                db.select(sql),
+                Err(FerrousDBError::InvalidExpression(_))
+            ));
+        }
+        // Only a recursive WITH lets a CTE read itself
+        assert!(matches!(
+            db.select(
+                "WITH c (n) AS (SELECT 1 FROM staff UNION SELECT n + 1 FROM c) SELECT n FROM c"
+            ),
+            Err(FerrousDBError::TableNotFound(_))
+        ));
+
+        let sql = "WITH RECURSIVE c (n) AS (SELECT id FROM staff UNION ALL \
+                   SELECT n + 1 FROM c WHERE n < 3) SELECT n FROM c ORDER BY n";
+        assert_eq!(parse_sql(sql).unwrap().to_string(), sql);
     }
 
     #[test]
-    fn test_update() {
+    fn test_window_functions() {
         let mut db = setup();
-        let create_result = db.create_table(
-            "users",
-            vec![
-                ColumnSchema::new("name".to_string(), "TEXT".to_string()),
-                ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
-            ],
+        db.execute_sql("CREATE TABLE events (id INTEGER, kind TEXT, score INTEGER)")
+            .unwrap();
+        for (id, kind, score) in [
+            (1, "a", 10),
+            (2, "b", 30),
+            (3, "a", 20),
+            (4, "a", 20),
+            (5, "b", 5),
+            (6, "a", 40),
+        ] {
+            db.execute_sql(&format!(
+                "INSERT INTO events (id, kind, score) VALUES ({}, '{}', {})",
+                id, kind, score
+            ))
+            .unwrap();
+        }
+
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT id, row_number() OVER (PARTITION BY kind ORDER BY score, id), \
+                 rank() OVER (PARTITION BY kind ORDER BY score), \
+                 dense_rank() OVER (PARTITION BY kind ORDER BY score) \
+                 FROM events WHERE kind = 'a' ORDER BY id"
+            ),
+            ["1,1,1,1", "3,2,2,2", "4,3,2,2", "6,4,4,3"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT id, lag(score) OVER (ORDER BY id), lead(score, 2, 0) OVER (ORDER BY id) \
+                 FROM events ORDER BY id"
+            ),
+            [
+                "1,NULL,20",
+                "2,10,20",
+                "3,30,5",
+                "4,20,40",
+                "5,20,0",
+                "6,5,0"
+            ]
+        );
+        // Without a frame, aggregates run up to the last peer of the row,
+        // over the whole partition when it has no ORDER BY
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT id, sum(score) OVER (ORDER BY score), count(*) OVER (PARTITION BY kind), \
+                 sum(score) OVER (ORDER BY id ROWS BETWEEN 1 PRECEDING AND 1 FOLLOWING) \
+                 FROM events ORDER BY id"
+            ),
+            [
+                "1,15,4,40",
+                "2,85,2,60",
+                "3,55,4,70",
+                "4,55,4,45",
+                "5,5,2,65",
+                "6,125,4,45"
+            ]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT DISTINCT kind, first_value(id) OVER (PARTITION BY kind ORDER BY score), \
+                 last_value(id) OVER (PARTITION BY kind ORDER BY score \
+                 ROWS BETWEEN UNBOUNDED PRECEDING AND UNBOUNDED FOLLOWING) \
+                 FROM events ORDER BY kind"
+            ),
+            ["a,1,6", "b,5,2"]
         );
-        assert!(create_result.is_ok());
 
-        // Insert initial data
-        let mut values = HashMap::new();
-        values.insert("name".to_string(), DataType::Text("Alice".to_string()));
-        values.insert("age".to_string(), DataType::Integer(30));
-        let insert_result = db.insert_into("users", values);
-        assert!(insert_result.is_ok());
+        // Windows run before ORDER BY and LIMIT, over groups when grouping
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT id, rank() OVER (ORDER BY score DESC) AS r FROM events \
+                 ORDER BY r, id LIMIT 3"
+            ),
+            ["6,1", "2,2", "3,3"]
+        );
+        assert_eq!(
+            select_rows(
+                &db,
+                "SELECT kind, sum(score), sum(sum(score)) OVER (), \
+                 rank() OVER (ORDER BY count(*) DESC) FROM events GROUP BY kind ORDER BY kind"
+            ),
+            ["a,90,125,1", "b,35,125,2"]
+        );
 
-        // Verify initial data
-        match db.get_page("users", 1, 10, None, None) {
-            PageResult::Page(rows) => {
-                assert_eq!(rows.len(), 1);
-                assert_eq!(rows[0].data.get("name").unwrap().get_value(), "Alice");
-                assert_eq!(rows[0].data.get("age").unwrap().get_value(), "30");
-            }
-            _ => panic!("Expected to find inserted row"),
+        for sql in [
+            "SELECT id FROM events WHERE row_number() OVER () > 1",
+            "SELECT kind FROM events GROUP BY kind HAVING rank() OVER () = 1",
+            "SELECT sum(row_number() OVER ()) OVER () FROM events",
+            "SELECT kind, row_number() OVER (ORDER BY score) FROM events GROUP BY kind",
+            "SELECT lag(id, 'one') OVER () FROM events",
+        ] {
+            assert!(matches!(
+                db.select(sql),
+                Err(FerrousDBError::InvalidExpression(_))
+            ));
+        }
+        for sql in [
+            "SELECT rank(id) OVER () FROM events",
+            "SELECT ntile(2) OVER () FROM events",
+            "SELECT sum(DISTINCT score) OVER () FROM events",
+            "SELECT sum(score) OVER (ORDER BY id ROWS BETWEEN CURRENT ROW AND 1 PRECEDING) FROM events",
+            "SELECT sum(score) OVER (ORDER BY id RANGE BETWEEN 1 PRECEDING AND CURRENT ROW) FROM events",
+        ] {
+            assert!(matches!(
+                db.select(sql),
+                Err(FerrousDBError::ParseError(_))
+            ));
         }
 
-        // Perform update
-        let mut assignments = HashMap::new();
-        assignments.insert("age".to_string(), DataType::Integer(31));
-        let update_result = db.update("users", assignments, Some("name = 'Alice'".to_string()));
-        assert!(update_result.is_ok());
-        assert_eq!(update_result.unwrap(), 1); // Should update exactly 1 row
+        let sql = "SELECT count(*) OVER (PARTITION BY kind ORDER BY score DESC \
+                   ROWS BETWEEN 2 PRECEDING AND CURRENT ROW) FROM events";
+        assert_eq!(parse_sql(sql).unwrap().to_string(), sql);
+    }
 
-        // Verify the update was successful
-        match db.get_page("users", 1, 10, None, None) {
-            PageResult::Page(rows) => {
-                assert_eq!(rows.len(), 1);
-                assert_eq!(rows[0].data.get("name").unwrap().get_value(), "Alice");
-                assert_eq!(rows[0].data.get("age").unwrap().get_value(), "31");
-            }
-            _ => panic!("Expected to find the updated row"),
+    #[test]
+    fn test_insert_rows() {
+        let path = temp_db_path("ferrous_insert_rows");
+        let log_path = path.with_extension("log");
+        let mut db = FerrousDB::open(&path).unwrap();
+        db.execute_sql("CREATE TABLE users (name TEXT, age INTEGER CHECK (age >= 0))")
+            .unwrap();
+        db.execute_sql("CREATE TABLE adults (name TEXT, age INTEGER)")
+            .unwrap();
+
+        let sql = "INSERT INTO users (name, age) VALUES ('Bob', 42), ('Eve', 7), ('Carol', NULL)";
+        assert_eq!(db.execute_sql(sql).unwrap(), QueryResult::RowsAffected(3));
+        assert_eq!(parse_sql(sql).unwrap().to_string(), sql);
+        let sql = "INSERT INTO adults (age, name) SELECT age, name FROM users WHERE age >= 18 OR age IS NULL";
+        assert_eq!(db.execute_sql(sql).unwrap(), QueryResult::RowsAffected(2));
+        assert_eq!(parse_sql(sql).unwrap().to_string(), sql);
+        // The query sees the table it inserts into as it was before
+        assert_eq!(
+            db.execute_sql("INSERT INTO adults (name, age) SELECT name, age + 1 FROM adults")
+                .unwrap(),
+            QueryResult::RowsAffected(2)
+        );
+        let text = |value: &str| Some(DataType::Text(value.to_string()));
+        assert_eq!(
+            db.select("SELECT name, age FROM adults ORDER BY name, age")
+                .unwrap()
+                .rows,
+            vec![
+                vec![text("Bob"), Some(DataType::Integer(42))],
+                vec![text("Bob"), Some(DataType::Integer(43))],
+                vec![text("Carol"), None],
+                vec![text("Carol"), None],
+            ]
+        );
+
+        // A failing row leaves none of the others behind
+        assert!(matches!(
+            db.execute_sql("INSERT INTO users (name, age) VALUES ('Dan', 1), ('Mallory', -1)"),
+            Err(FerrousDBError::ConstraintViolation(_))
+        ));
+        assert!(matches!(
+            db.execute_sql("INSERT INTO users (name, age) SELECT name, age - 10 FROM users"),
+            Err(FerrousDBError::ConstraintViolation(_))
+        ));
+        assert!(matches!(
+            db.execute_sql("INSERT INTO missing (name) SELECT name FROM users WHERE age > 100"),
+            Err(FerrousDBError::TableNotFound(_))
+        ));
+        assert_eq!(db.tables["users"].rows.len(), 3);
+        drop(db);
+
+        // Every statement is logged as one transaction
+        let recovered = WriteAheadLog::recover(&log_path, 0, 0).unwrap();
+        let txn_ids: Vec<u64> = recovered.records.iter().map(|r| r.txn_id).collect();
+        assert_eq!(txn_ids, vec![1, 2, 3, 3, 3, 4, 4, 5, 5]);
+        let db = FerrousDB::open(&path).unwrap();
+        assert_eq!(db.tables["users"].rows.len(), 3);
+        assert_eq!(db.tables["adults"].rows.len(), 4);
+
+        // UNIQUE values are checked against the table and the rest of the rows
+        let mut db = setup();
+        db.create_table("tags", vec!["name TEXT UNIQUE".parse().unwrap()])
+            .unwrap();
+        db.execute_sql("INSERT INTO tags (name) VALUES ('a')")
+            .unwrap();
+        for sql in [
+            "INSERT INTO tags (name) VALUES ('b'), ('a')",
+            "INSERT INTO tags (name) VALUES ('c'), ('c')",
+            "INSERT INTO tags (name) SELECT name FROM tags",
+        ] {
+            assert!(matches!(
+                db.execute_sql(sql),
+                Err(FerrousDBError::ConstraintViolation(_))
+            ));
         }
+        assert_eq!(
+            db.execute_sql("INSERT INTO tags (name) VALUES ('b'), ('c')")
+                .unwrap(),
+            QueryResult::RowsAffected(2)
+        );
+        assert_eq!(select_rows(&db, "SELECT name FROM tags"), ["a", "b", "c"]);
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&log_path);
     }
 
     #[test]
-    fn test_delete_from() {
+    fn test_insert_positional_and_default_values() {
         let mut db = setup();
-        let create_result = db.create_table(
-            "users",
-            vec![
-                ColumnSchema::new("name".to_string(), "TEXT".to_string()),
-                ColumnSchema::new("age".to_string(), "INTEGER".to_string()),
-            ],
+        db.execute_sql(
+            "CREATE TABLE users (name TEXT, age INTEGER DEFAULT 18, active BOOLEAN DEFAULT true)",
+        )
+        .unwrap();
+        assert_eq!(
+            db.tables["users"].schema[1].default_value(),
+            Some(&DataType::Integer(18))
         );
-        assert!(create_result.is_ok());
 
-        let mut values = HashMap::new();
-        values.insert("name".to_string(), DataType::Text("Alice".to_string()));
-        values.insert("age".to_string(), DataType::Integer(30));
-        let insert_result = db.insert_into("users", values);
-        assert!(insert_result.is_ok());
+        db.execute_sql("INSERT INTO users VALUES ('Ana', 30, false)")
+            .unwrap();
+        db.execute_sql("INSERT INTO users (name) VALUES ('Bob')")
+            .unwrap();
+        let sql = "INSERT INTO users VALUES ('Carol', DEFAULT, NULL)";
+        db.execute_sql(sql).unwrap();
+        assert_eq!(parse_sql(sql).unwrap().to_string(), sql);
+        let sql = "INSERT INTO users DEFAULT VALUES";
+        assert_eq!(db.execute_sql(sql).unwrap(), QueryResult::RowsAffected(1));
+        assert_eq!(parse_sql(sql).unwrap().to_string(), sql);
+        db.execute_sql("INSERT INTO users SELECT name, age + 1, active FROM users WHERE age = 30")
+            .unwrap();
 
-        let delete_result = db.delete_from("users", Some("name = 'Alice'".to_string()));
-        assert!(delete_result.is_ok());
+        let text = |value: &str| Some(DataType::Text(value.to_string()));
+        let age = |value| Some(DataType::Integer(value));
+        let active = |value| Some(DataType::Boolean(value));
+        assert_eq!(
+            db.select("SELECT * FROM users").unwrap().rows,
+            vec![
+                vec![text("Ana"), age(30), active(false)],
+                vec![text("Bob"), age(18), active(true)],
+                vec![text("Carol"), age(18), None],
+                vec![None, age(18), active(true)],
+                vec![text("Ana"), age(31), active(false)],
+            ]
+        );
 
-        assert_eq!(db.tables.get("users").unwrap().rows.len(), 0);
+        for sql in [
+            "INSERT INTO users VALUES ('Dan', 40)",
+            "INSERT INTO users VALUES ('Dan', 40, true, 1)",
+            "INSERT INTO users (name, age) VALUES ('Dan')",
+            "INSERT INTO users (name) VALUES ('Dan'), ('Eve', 40)",
+            "INSERT INTO users (name) SELECT name, age FROM users",
+            "INSERT INTO users (name, name) VALUES ('Dan', 'Eve')",
+        ] {
+            assert!(matches!(
+                db.execute_sql(sql),
+                Err(FerrousDBError::ParseError(_))
+            ));
+        }
+        assert!(matches!(
+            db.execute_sql("INSERT INTO users (height) VALUES (DEFAULT)"),
+            Err(FerrousDBError::ColumnNotFound(_))
+        ));
+        assert_eq!(db.tables["users"].rows.len(), 5);
+
+        assert!(matches!(
+            db.execute_sql("CREATE TABLE bad (age INTEGER DEFAULT 'old')"),
+            Err(FerrousDBError::TypeMismatch(_))
+        ));
+        assert!(matches!(
+            db.execute_sql("CREATE TABLE bad (age INTEGER DEFAULT 1 + 1)"),
+            Err(FerrousDBError::ParseError(_))
+        ));
     }
 }
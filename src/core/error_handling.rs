@@ -28,4 +28,19 @@ pub enum FerrousDBError {
 
     #[error("Constraint violation: {0}")]
     ConstraintViolation(String),
+
+    #[error("Transaction error: {0}")]
+    TransactionError(String),
+
+    #[error("Database '{0}' is locked by another process")]
+    DatabaseLocked(String),
+
+    #[error("Database '{0}' is open read-only")]
+    ReadOnly(String),
+
+    #[error("Invalid expression: {0}")]
+    InvalidExpression(String),
+
+    #[error("Recursion limit exceeded: {0}")]
+    RecursionLimit(String),
 }
@@ -0,0 +1,653 @@
+//! Evaluates [`Expr`] against rows.
+//!
+//! Rows map column names to values. The rows of a join qualify every name
+//! with its table, and the columns of expressions evaluated against them are
+//! qualified too, see [`Scope::bind`](super::query::Scope::bind).
+//!
+//! Evaluation follows SQL's three-valued logic. A column missing from a row
+//! is NULL, comparing anything with NULL gives NULL (unknown), and `AND`,
+//! `OR` and `NOT` treat NULL as unknown rather than false. A `WHERE` clause
+//! keeps a row only if its condition is true. Arithmetic and functions on
+//! NULL give NULL, except for `coalesce`. Aggregates skip NULL values.
+//!
+//! Subqueries run through the [`Subqueries`] of the query evaluating the
+//! expression, which knows the tables they read.
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use super::{
+    error_handling::FerrousDBError,
+    expr::{AggregateFunction, BinaryOperator, Expr, Function},
+    parser::command::SQLCommand,
+    result_set::ResultSet,
+};
+use crate::DataType;
+
+/// Runs the subqueries of the expressions a query evaluates.
+pub(crate) trait Subqueries {
+    /// Runs `query` for `row`, the row of the enclosing query it is
+    /// evaluated for.
+    fn run(
+        &self,
+        query: &SQLCommand,
+        row: &HashMap<String, DataType>,
+    ) -> Result<Rc<ResultSet>, FerrousDBError>;
+}
+
+/// For expressions evaluated outside of a query, where subqueries can't run.
+pub(crate) struct NoSubqueries;
+
+impl Subqueries for NoSubqueries {
+    fn run(
+        &self,
+        query: &SQLCommand,
+        _row: &HashMap<String, DataType>,
+    ) -> Result<Rc<ResultSet>, FerrousDBError> {
+        Err(FerrousDBError::InvalidExpression(format!(
+            "subquery '{}' is not allowed here",
+            query.to_string()
+        )))
+    }
+}
+
+/// Evaluates `expr` for a row. `None` stands for NULL.
+pub fn evaluate(
+    expr: &Expr,
+    row: &HashMap<String, DataType>,
+) -> Result<Option<DataType>, FerrousDBError> {
+    evaluate_with(expr, row, &NoSubqueries)
+}
+
+/// Evaluates `expr` for a row, running its subqueries with `subqueries`.
+pub(crate) fn evaluate_with(
+    expr: &Expr,
+    row: &HashMap<String, DataType>,
+    subqueries: &dyn Subqueries,
+) -> Result<Option<DataType>, FerrousDBError> {
+    let evaluate = |expr: &Expr| evaluate_with(expr, row, subqueries);
+    Ok(match expr {
+        Expr::Column { table: None, name } => row.get(name).cloned(),
+        Expr::Column {
+            table: Some(table),
+            name,
+        } => row.get(&format!("{}.{}", table, name)).cloned(),
+        Expr::Literal(value) => Some(value.clone()),
+        Expr::Null => None,
+        Expr::Binary { left, op, right } => {
+            let left = evaluate(left)?;
+            let right = evaluate(right)?;
+            match op {
+                BinaryOperator::And => and(truth(&left)?, truth(&right)?).map(DataType::Boolean),
+                BinaryOperator::Or => or(truth(&left)?, truth(&right)?).map(DataType::Boolean),
+                op if op.is_arithmetic() => arithmetic(&left, *op, &right)?,
+                _ => compare(&left, *op, &right)?.map(DataType::Boolean),
+            }
+        }
+        Expr::Not(expr) => truth(&evaluate(expr)?)?.map(|b| DataType::Boolean(!b)),
+        Expr::IsNull { expr, negated } => {
+            Some(DataType::Boolean(evaluate(expr)?.is_none() != *negated))
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let value = evaluate(expr)?;
+            negate(contains(&value, list.iter().map(evaluate))?, *negated)
+        }
+        Expr::Between {
+            expr,
+            low,
+            high,
+            negated,
+        } => {
+            let value = evaluate(expr)?;
+            let low = evaluate(low)?;
+            let high = evaluate(high)?;
+            let within = and(
+                compare(&value, BinaryOperator::GtEq, &low)?,
+                compare(&value, BinaryOperator::LtEq, &high)?,
+            );
+            negate(within, *negated)
+        }
+        Expr::Like {
+            expr,
+            pattern,
+            negated,
+        } => match (evaluate(expr)?, evaluate(pattern)?) {
+            (Some(DataType::Text(text)), Some(DataType::Text(pattern))) => {
+                negate(Some(like(&text, &pattern)), *negated)
+            }
+            (None, _) | (_, None) => None,
+            (value, pattern) => {
+                return Err(FerrousDBError::InvalidExpression(format!(
+                    "LIKE needs text operands, got {} and {}",
+                    type_name(&value),
+                    type_name(&pattern)
+                )))
+            }
+        },
+        Expr::Function { func, args } => {
+            let mut values = Vec::with_capacity(args.len());
+            for arg in args {
+                values.push(evaluate(arg)?);
+            }
+            call(*func, values)?
+        }
+        Expr::Aggregate { .. } => {
+            return Err(FerrousDBError::InvalidExpression(format!(
+                "{} is only allowed in the select list and HAVING",
+                expr
+            )))
+        }
+        Expr::Window { .. } => {
+            return Err(FerrousDBError::InvalidExpression(format!(
+                "{} is only allowed in the select list and ORDER BY",
+                expr
+            )))
+        }
+        Expr::Subquery(query) => {
+            let result = subqueries.run(query, row)?;
+            single_column(&result, query)?;
+            match result.rows.as_slice() {
+                [] => None,
+                [values] => values[0].clone(),
+                _ => {
+                    return Err(FerrousDBError::InvalidExpression(format!(
+                        "subquery '{}' returned more than one row",
+                        query.to_string()
+                    )))
+                }
+            }
+        }
+        Expr::InSubquery {
+            expr,
+            subquery,
+            negated,
+        } => {
+            let value = evaluate(expr)?;
+            let result = subqueries.run(subquery, row)?;
+            single_column(&result, subquery)?;
+            let values = result.rows.iter().map(|values| Ok(values[0].clone()));
+            negate(contains(&value, values)?, *negated)
+        }
+        Expr::Exists { subquery, negated } => {
+            let result = subqueries.run(subquery, row)?;
+            negate(Some(!result.rows.is_empty()), *negated)
+        }
+    })
+}
+
+/// Computes an aggregate over the rows of a group.
+pub fn aggregate(
+    func: AggregateFunction,
+    arg: Option<&Expr>,
+    distinct: bool,
+    rows: &[&HashMap<String, DataType>],
+) -> Result<Option<DataType>, FerrousDBError> {
+    let Some(arg) = arg else {
+        // count(*)
+        return Ok(Some(DataType::Integer(rows.len() as i64)));
+    };
+    let mut values = Vec::new();
+    let mut seen = HashSet::new();
+    for row in rows {
+        if let Some(value) = evaluate(arg, row)? {
+            if !distinct || seen.insert(value.clone()) {
+                values.push(value);
+            }
+        }
+    }
+    fold(func, arg, values)
+}
+
+/// Computes an aggregate from the values its argument `arg` gives, NULLs
+/// left out.
+pub(crate) fn fold(
+    func: AggregateFunction,
+    arg: &Expr,
+    values: Vec<DataType>,
+) -> Result<Option<DataType>, FerrousDBError> {
+    if func == AggregateFunction::Count {
+        return Ok(Some(DataType::Integer(values.len() as i64)));
+    }
+    if values.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(match func {
+        AggregateFunction::Sum | AggregateFunction::Avg => {
+            let mut sum: i64 = 0;
+            for value in &values {
+                let DataType::Integer(n) = value else {
+                    return Err(FerrousDBError::InvalidExpression(format!(
+                        "{}() needs INTEGER values, got {}",
+                        func.name(),
+                        value.get_type()
+                    )));
+                };
+                sum = sum.checked_add(*n).ok_or_else(|| {
+                    FerrousDBError::InvalidExpression(format!("{}({}) overflows", func.name(), arg))
+                })?;
+            }
+            if func == AggregateFunction::Avg {
+                sum /= values.len() as i64;
+            }
+            DataType::Integer(sum)
+        }
+        _ => {
+            let wanted = if func == AggregateFunction::Min {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            };
+            let mut best = values[0].clone();
+            for value in &values[1..] {
+                if compare_values(value, &best)? == wanted {
+                    best = value.clone();
+                }
+            }
+            best
+        }
+    }))
+}
+
+/// Replaces the aggregates in `expr` by their values over the rows of a
+/// group, leaving an expression [`evaluate`] can handle.
+pub fn resolve_aggregates(
+    expr: &Expr,
+    rows: &[&HashMap<String, DataType>],
+) -> Result<Expr, FerrousDBError> {
+    if !expr.contains_aggregate() {
+        return Ok(expr.clone());
+    }
+    let resolve = |expr: &Expr| resolve_aggregates(expr, rows).map(Box::new);
+    Ok(match expr {
+        Expr::Aggregate {
+            func,
+            arg,
+            distinct,
+        } => match aggregate(*func, arg.as_deref(), *distinct, rows)? {
+            Some(value) => Expr::Literal(value),
+            None => Expr::Null,
+        },
+        Expr::Binary { left, op, right } => Expr::Binary {
+            left: resolve(left)?,
+            op: *op,
+            right: resolve(right)?,
+        },
+        Expr::Not(expr) => Expr::Not(resolve(expr)?),
+        Expr::IsNull { expr, negated } => Expr::IsNull {
+            expr: resolve(expr)?,
+            negated: *negated,
+        },
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => Expr::InList {
+            expr: resolve(expr)?,
+            list: list
+                .iter()
+                .map(|item| resolve_aggregates(item, rows))
+                .collect::<Result<_, _>>()?,
+            negated: *negated,
+        },
+        Expr::Between {
+            expr,
+            low,
+            high,
+            negated,
+        } => Expr::Between {
+            expr: resolve(expr)?,
+            low: resolve(low)?,
+            high: resolve(high)?,
+            negated: *negated,
+        },
+        Expr::Like {
+            expr,
+            pattern,
+            negated,
+        } => Expr::Like {
+            expr: resolve(expr)?,
+            pattern: resolve(pattern)?,
+            negated: *negated,
+        },
+        Expr::Function { func, args } => Expr::Function {
+            func: *func,
+            args: args
+                .iter()
+                .map(|arg| resolve_aggregates(arg, rows))
+                .collect::<Result<_, _>>()?,
+        },
+        Expr::InSubquery {
+            expr,
+            subquery,
+            negated,
+        } => Expr::InSubquery {
+            expr: resolve(expr)?,
+            subquery: subquery.clone(),
+            negated: *negated,
+        },
+        Expr::Window { .. } => {
+            let mut expr = expr.clone();
+            for child in expr.children_mut() {
+                *child = resolve_aggregates(child, rows)?;
+            }
+            expr
+        }
+        Expr::Column { .. }
+        | Expr::Literal(_)
+        | Expr::Null
+        | Expr::Subquery(_)
+        | Expr::Exists { .. } => expr.clone(),
+    })
+}
+
+/// Whether `expr` is true for a row. NULL and false both count as no match.
+pub fn matches(expr: &Expr, row: &HashMap<String, DataType>) -> Result<bool, FerrousDBError> {
+    matches_with(expr, row, &NoSubqueries)
+}
+
+/// [`matches`] for a condition that may contain subqueries.
+pub(crate) fn matches_with(
+    expr: &Expr,
+    row: &HashMap<String, DataType>,
+    subqueries: &dyn Subqueries,
+) -> Result<bool, FerrousDBError> {
+    Ok(truth(&evaluate_with(expr, row, subqueries)?)? == Some(true))
+}
+
+/// Orders two non-NULL values of the same type.
+pub fn compare_values(left: &DataType, right: &DataType) -> Result<Ordering, FerrousDBError> {
+    if left.get_type() != right.get_type() {
+        return Err(FerrousDBError::InvalidExpression(format!(
+            "cannot compare {} with {}",
+            left.get_type(),
+            right.get_type()
+        )));
+    }
+    Ok(left.cmp(right))
+}
+
+fn compare(
+    left: &Option<DataType>,
+    op: BinaryOperator,
+    right: &Option<DataType>,
+) -> Result<Option<bool>, FerrousDBError> {
+    let (Some(left), Some(right)) = (left, right) else {
+        return Ok(None);
+    };
+    let ordering = compare_values(left, right)?;
+    Ok(Some(match op {
+        BinaryOperator::Eq => ordering == Ordering::Equal,
+        BinaryOperator::NotEq => ordering != Ordering::Equal,
+        BinaryOperator::Lt => ordering == Ordering::Less,
+        BinaryOperator::LtEq => ordering != Ordering::Greater,
+        BinaryOperator::Gt => ordering == Ordering::Greater,
+        BinaryOperator::GtEq => ordering != Ordering::Less,
+        _ => unreachable!("not a comparison"),
+    }))
+}
+
+fn arithmetic(
+    left: &Option<DataType>,
+    op: BinaryOperator,
+    right: &Option<DataType>,
+) -> Result<Option<DataType>, FerrousDBError> {
+    let (a, b) = match (left, right) {
+        (Some(DataType::Integer(a)), Some(DataType::Integer(b))) => (*a, *b),
+        (None, _) | (_, None) => return Ok(None),
+        (left, right) => {
+            return Err(FerrousDBError::InvalidExpression(format!(
+                "'{}' needs INTEGER operands, got {} and {}",
+                op,
+                type_name(left),
+                type_name(right)
+            )))
+        }
+    };
+    if b == 0 && matches!(op, BinaryOperator::Divide | BinaryOperator::Modulo) {
+        return Err(FerrousDBError::InvalidExpression(
+            "division by zero".to_string(),
+        ));
+    }
+    let result = match op {
+        BinaryOperator::Plus => a.checked_add(b),
+        BinaryOperator::Minus => a.checked_sub(b),
+        BinaryOperator::Multiply => a.checked_mul(b),
+        BinaryOperator::Divide => a.checked_div(b),
+        BinaryOperator::Modulo => a.checked_rem(b),
+        _ => unreachable!("not an arithmetic operator"),
+    };
+    result
+        .map(|n| Some(DataType::Integer(n)))
+        .ok_or_else(|| FerrousDBError::InvalidExpression(format!("{} {} {} overflows", a, op, b)))
+}
+
+fn call(func: Function, args: Vec<Option<DataType>>) -> Result<Option<DataType>, FerrousDBError> {
+    if func == Function::Coalesce {
+        return Ok(args.into_iter().flatten().next());
+    }
+    let Some(arg) = args.into_iter().next().flatten() else {
+        return Ok(None);
+    };
+    Ok(Some(match (func, arg) {
+        (Function::Upper, DataType::Text(text)) => DataType::Text(text.to_uppercase()),
+        (Function::Lower, DataType::Text(text)) => DataType::Text(text.to_lowercase()),
+        (Function::Length, DataType::Text(text)) => DataType::Integer(text.chars().count() as i64),
+        (Function::Abs, DataType::Integer(n)) => {
+            DataType::Integer(n.checked_abs().ok_or_else(|| {
+                FerrousDBError::InvalidExpression(format!("abs({}) overflows", n))
+            })?)
+        }
+        (func, arg) => {
+            return Err(FerrousDBError::InvalidExpression(format!(
+                "{}() can't take {}",
+                func.name(),
+                arg.get_type()
+            )))
+        }
+    }))
+}
+
+/// Reads a value as a truth value, where NULL is unknown.
+fn truth(value: &Option<DataType>) -> Result<Option<bool>, FerrousDBError> {
+    match value {
+        Some(DataType::Boolean(b)) => Ok(Some(*b)),
+        None => Ok(None),
+        Some(other) => Err(FerrousDBError::InvalidExpression(format!(
+            "expected a BOOLEAN, got {}",
+            other.get_type()
+        ))),
+    }
+}
+
+fn and(left: Option<bool>, right: Option<bool>) -> Option<bool> {
+    match (left, right) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+    }
+}
+
+fn or(left: Option<bool>, right: Option<bool>) -> Option<bool> {
+    match (left, right) {
+        (Some(true), _) | (_, Some(true)) => Some(true),
+        (Some(false), Some(false)) => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether `value` equals one of `items`: NULL rather than false when some
+/// comparison was unknown.
+fn contains(
+    value: &Option<DataType>,
+    items: impl IntoIterator<Item = Result<Option<DataType>, FerrousDBError>>,
+) -> Result<Option<bool>, FerrousDBError> {
+    let mut found = Some(false);
+    for item in items {
+        found = or(found, compare(value, BinaryOperator::Eq, &item?)?);
+        if found == Some(true) {
+            break;
+        }
+    }
+    Ok(found)
+}
+
+/// Checks that a subquery used as a value gives a single column.
+fn single_column(result: &ResultSet, query: &SQLCommand) -> Result<(), FerrousDBError> {
+    if result.columns.len() != 1 {
+        return Err(FerrousDBError::InvalidExpression(format!(
+            "subquery '{}' must return one column, not {}",
+            query.to_string(),
+            result.columns.len()
+        )));
+    }
+    Ok(())
+}
+
+fn negate(value: Option<bool>, negated: bool) -> Option<DataType> {
+    value.map(|b| DataType::Boolean(b != negated))
+}
+
+fn type_name(value: &Option<DataType>) -> &'static str {
+    value.as_ref().map_or("NULL", |value| value.get_type())
+}
+
+/// Matches `text` against a `LIKE` pattern.
+fn like(text: &str, pattern: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let (mut t, mut p) = (0, 0);
+    // Where the last `%` was seen, and how much text it swallowed so far
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some('_') => {
+                t += 1;
+                p += 1;
+            }
+            Some(&c) if c == text[t] => {
+                t += 1;
+                p += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    t = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '%')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row() -> HashMap<String, DataType> {
+        let mut row = HashMap::new();
+        row.insert("name".to_string(), DataType::Text("Bob".to_string()));
+        row.insert("age".to_string(), DataType::Integer(42));
+        row
+    }
+
+    fn int(value: i64) -> Expr {
+        Expr::Literal(DataType::Integer(value))
+    }
+
+    #[test]
+    fn test_null_comparisons_are_unknown() {
+        let row = row();
+        let missing = Expr::binary(Expr::column("email"), BinaryOperator::Eq, int(1));
+        assert_eq!(evaluate(&missing, &row).unwrap(), None);
+        assert!(!matches(&missing, &row).unwrap());
+        assert!(!matches(&Expr::Not(Box::new(missing.clone())), &row).unwrap());
+
+        // Unknown OR true is true, unknown AND false is false
+        let adult = Expr::binary(Expr::column("age"), BinaryOperator::Gt, int(18));
+        let either = Expr::binary(missing.clone(), BinaryOperator::Or, adult.clone());
+        assert!(matches(&either, &row).unwrap());
+        let minor = Expr::Not(Box::new(adult));
+        let both = Expr::binary(missing, BinaryOperator::And, minor);
+        assert_eq!(
+            evaluate(&both, &row).unwrap(),
+            Some(DataType::Boolean(false))
+        );
+
+        let in_list = Expr::InList {
+            expr: Box::new(Expr::column("age")),
+            list: vec![int(1), Expr::Null],
+            negated: true,
+        };
+        assert_eq!(evaluate(&in_list, &row).unwrap(), None);
+    }
+
+    #[test]
+    fn test_like_patterns() {
+        assert!(like("Bob", "B%"));
+        assert!(like("Bob", "%o%"));
+        assert!(like("Bob", "_o_"));
+        assert!(like("", "%"));
+        assert!(like("abcabd", "%abd"));
+        assert!(!like("Bob", "b%"));
+        assert!(!like("Bob", "_o"));
+    }
+
+    #[test]
+    fn test_mismatched_types_are_errors() {
+        let expr = Expr::binary(
+            Expr::column("age"),
+            BinaryOperator::Gt,
+            Expr::Literal(DataType::Text("x".to_string())),
+        );
+        assert!(matches!(
+            evaluate(&expr, &row()),
+            Err(FerrousDBError::InvalidExpression(_))
+        ));
+    }
+
+    #[test]
+    fn test_arithmetic_and_functions() {
+        let row = row();
+        let next = Expr::binary(Expr::column("age"), BinaryOperator::Plus, int(1));
+        assert_eq!(evaluate(&next, &row).unwrap(), Some(DataType::Integer(43)));
+        let upper = Expr::Function {
+            func: Function::Upper,
+            args: vec![Expr::column("name")],
+        };
+        assert_eq!(
+            evaluate(&upper, &row).unwrap(),
+            Some(DataType::Text("BOB".to_string()))
+        );
+
+        // NULL propagates, except through coalesce
+        let missing = Expr::binary(Expr::column("email"), BinaryOperator::Multiply, int(2));
+        assert_eq!(evaluate(&missing, &row).unwrap(), None);
+        let coalesce = Expr::Function {
+            func: Function::Coalesce,
+            args: vec![Expr::column("email"), int(0)],
+        };
+        assert_eq!(
+            evaluate(&coalesce, &row).unwrap(),
+            Some(DataType::Integer(0))
+        );
+
+        let divide = Expr::binary(Expr::column("age"), BinaryOperator::Divide, int(0));
+        assert!(matches!(
+            evaluate(&divide, &row),
+            Err(FerrousDBError::InvalidExpression(_))
+        ));
+    }
+}
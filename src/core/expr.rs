@@ -0,0 +1,889 @@
+//! Typed SQL expressions, as found in `WHERE` clauses, `CHECK` constraints and
+//! the select list.
+//!
+//! [`parse_sql`](super::parser::sql_parser::parse_sql) converts the
+//! expressions of the SQL parser into [`Expr`], and the
+//! [`evaluator`](super::evaluator) evaluates them against rows.
+
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::parser::command::SQLCommand;
+use crate::DataType;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// A column, optionally qualified with its table name.
+    Column {
+        table: Option<String>,
+        name: String,
+    },
+    Literal(DataType),
+    Null,
+    Binary {
+        left: Box<Expr>,
+        op: BinaryOperator,
+        right: Box<Expr>,
+    },
+    Not(Box<Expr>),
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    /// `expr LIKE pattern`, where `%` matches any run of characters and `_`
+    /// any single character.
+    Like {
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        negated: bool,
+    },
+    /// A call to a scalar function, such as `upper(name)`.
+    Function {
+        func: Function,
+        args: Vec<Expr>,
+    },
+    /// An aggregate over the rows of a group, such as `count(DISTINCT name)`.
+    /// `arg` is `None` for `count(*)`.
+    Aggregate {
+        func: AggregateFunction,
+        arg: Option<Box<Expr>>,
+        distinct: bool,
+    },
+    /// A subquery giving a single value, NULL when it returns no rows.
+    Subquery(Box<SQLCommand>),
+    /// `expr IN (SELECT …)`, against the values of the subquery's only
+    /// column.
+    InSubquery {
+        expr: Box<Expr>,
+        subquery: Box<SQLCommand>,
+        negated: bool,
+    },
+    /// `EXISTS (SELECT …)`: whether the subquery returns any row.
+    Exists {
+        subquery: Box<SQLCommand>,
+        negated: bool,
+    },
+    /// A window function, computed for each row of the result from the rows
+    /// of its partition, such as `rank() OVER (ORDER BY score DESC)`.
+    Window {
+        func: WindowFunction,
+        args: Vec<Expr>,
+        over: Box<WindowSpec>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+/// The scalar functions expressions can call.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Upper,
+    Lower,
+    Length,
+    Abs,
+    /// The first of its arguments that isn't NULL.
+    Coalesce,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    /// The average, rounded towards zero as values are integers.
+    Avg,
+    Min,
+    Max,
+}
+
+/// The functions that can be called with `OVER (…)`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowFunction {
+    RowNumber,
+    /// The row number of the first row of the current row's peers, leaving
+    /// gaps after ties.
+    Rank,
+    /// The number of distinct peer groups up to the current row.
+    DenseRank,
+    /// `lag(value [, offset [, default]])`: `value` for the row `offset`
+    /// rows before in the partition, or `default`.
+    Lag,
+    /// `lead(value [, offset [, default]])`: like `lag`, for a later row.
+    Lead,
+    FirstValue,
+    LastValue,
+    /// An aggregate over the rows of the frame. `count()` without argument
+    /// stands for `count(*)`.
+    Aggregate(AggregateFunction),
+}
+
+/// The rows a window function reads for each row: those of its partition
+/// and, for aggregates and `first_value` or `last_value`, of its frame.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WindowSpec {
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderByItem>,
+    /// `None` for the default frame: the partition up to the last peer of
+    /// the current row, rows being peers when `order_by` doesn't tell them
+    /// apart.
+    pub frame: Option<WindowFrame>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WindowFrame {
+    pub units: FrameUnits,
+    pub start: FrameBound,
+    pub end: FrameBound,
+}
+
+/// What the bounds of a frame count: rows, or groups of peers for `RANGE`,
+/// which only supports unbounded bounds and `CURRENT ROW`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameUnits {
+    Rows,
+    Range,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(usize),
+    CurrentRow,
+    Following(usize),
+    UnboundedFollowing,
+}
+
+/// An entry of the select list.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SelectItem {
+    /// `*`, or `table.*` when qualified, for every column of the table.
+    Wildcard(Option<String>),
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+    },
+}
+
+/// A table read by a query, named in `FROM` or `JOIN`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TableRef {
+    /// The name of the table, or the alias of a derived table.
+    pub name: String,
+    pub alias: Option<String>,
+    /// The query of a derived table, `(SELECT …) AS name`, whose result
+    /// gives the rows.
+    pub subquery: Option<Box<SQLCommand>>,
+}
+
+/// A table joined to the ones before it in `FROM`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub table: TableRef,
+    /// The `ON` condition, `None` for a `CROSS JOIN`.
+    pub condition: Option<Expr>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    /// Keeps the rows of the left side that match nothing, with NULLs for
+    /// the right side.
+    Left,
+    /// Keeps the rows of the right side that match nothing, with NULLs for
+    /// the left side.
+    Right,
+    /// Keeps the rows of both sides that match nothing.
+    Full,
+    /// Pairs every row of the left side with every row of the right side.
+    Cross,
+}
+
+/// How a set operation combines the rows of two queries. Duplicate rows are
+/// removed unless it is `ALL`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperator {
+    /// The rows of both queries.
+    Union,
+    /// The rows of the left query also returned by the right one.
+    Intersect,
+    /// The rows of the left query not returned by the right one.
+    Except,
+}
+
+/// Which rows `SELECT DISTINCT` takes as duplicates, of which it keeps the
+/// first.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Distinct {
+    /// Rows equal in every column of the result.
+    All,
+    /// `DISTINCT ON (…)`: rows with equal values of the expressions. The
+    /// one kept is the first in the order of `ORDER BY`.
+    On(Vec<Expr>),
+}
+
+/// A key of `ORDER BY`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OrderByItem {
+    pub expr: Expr,
+    pub ascending: bool,
+    /// Whether NULLs come before all values. By default NULL is smaller
+    /// than any value: first when ascending, last when descending.
+    pub nulls_first: bool,
+}
+
+impl Expr {
+    pub fn column(name: &str) -> Self {
+        Expr::Column {
+            table: None,
+            name: name.to_string(),
+        }
+    }
+
+    pub fn binary(left: Expr, op: BinaryOperator, right: Expr) -> Self {
+        Expr::Binary {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }
+    }
+
+    /// The expressions this one is made of. Those of subqueries are left
+    /// out, as subqueries are bound and evaluated on their own.
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Column { .. }
+            | Expr::Literal(_)
+            | Expr::Null
+            | Expr::Subquery(_)
+            | Expr::Exists { .. } => Vec::new(),
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Not(expr) | Expr::IsNull { expr, .. } | Expr::InSubquery { expr, .. } => {
+                vec![expr]
+            }
+            Expr::InList { expr, list, .. } => {
+                let mut children = vec![expr.as_ref()];
+                children.extend(list);
+                children
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+            Expr::Like { expr, pattern, .. } => vec![expr, pattern],
+            Expr::Function { args, .. } => args.iter().collect(),
+            Expr::Aggregate { arg, .. } => arg.iter().map(|arg| arg.as_ref()).collect(),
+            Expr::Window { args, over, .. } => {
+                let mut children: Vec<&Expr> = args.iter().collect();
+                children.extend(&over.partition_by);
+                children.extend(over.order_by.iter().map(|order| &order.expr));
+                children
+            }
+        }
+    }
+
+    /// The expressions this one is made of, for changing them in place.
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Column { .. }
+            | Expr::Literal(_)
+            | Expr::Null
+            | Expr::Subquery(_)
+            | Expr::Exists { .. } => Vec::new(),
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Not(expr) | Expr::IsNull { expr, .. } | Expr::InSubquery { expr, .. } => {
+                vec![expr]
+            }
+            Expr::InList { expr, list, .. } => {
+                let mut children = vec![expr.as_mut()];
+                children.extend(list);
+                children
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+            Expr::Like { expr, pattern, .. } => vec![expr, pattern],
+            Expr::Function { args, .. } => args.iter_mut().collect(),
+            Expr::Aggregate { arg, .. } => arg.iter_mut().map(|arg| arg.as_mut()).collect(),
+            Expr::Window { args, over, .. } => {
+                let mut children: Vec<&mut Expr> = args.iter_mut().collect();
+                children.extend(&mut over.partition_by);
+                children.extend(over.order_by.iter_mut().map(|order| &mut order.expr));
+                children
+            }
+        }
+    }
+
+    /// Calls `f` for every column the expression refers to.
+    pub fn visit_columns<'a>(&'a self, f: &mut impl FnMut(Option<&'a str>, &'a str)) {
+        match self {
+            Expr::Column { table, name } => f(table.as_deref(), name),
+            _ => {
+                for child in self.children() {
+                    child.visit_columns(f);
+                }
+            }
+        }
+    }
+
+    /// The subqueries of the expression, leaving out those nested in other
+    /// subqueries.
+    pub fn subqueries(&self) -> Vec<&SQLCommand> {
+        match self {
+            Expr::Subquery(subquery) | Expr::Exists { subquery, .. } => vec![subquery],
+            Expr::InSubquery { expr, subquery, .. } => {
+                let mut subqueries = expr.subqueries();
+                subqueries.push(subquery);
+                subqueries
+            }
+            _ => self
+                .children()
+                .into_iter()
+                .flat_map(|child| child.subqueries())
+                .collect(),
+        }
+    }
+
+    /// Whether the expression computes an aggregate anywhere.
+    pub fn contains_aggregate(&self) -> bool {
+        matches!(self, Expr::Aggregate { .. })
+            || self
+                .children()
+                .iter()
+                .any(|child| child.contains_aggregate())
+    }
+
+    /// Whether the expression calls a window function anywhere.
+    pub fn contains_window(&self) -> bool {
+        matches!(self, Expr::Window { .. })
+            || self.children().iter().any(|child| child.contains_window())
+    }
+}
+
+impl BinaryOperator {
+    /// Whether the operator computes an INTEGER rather than a BOOLEAN.
+    pub fn is_arithmetic(self) -> bool {
+        matches!(
+            self,
+            BinaryOperator::Plus
+                | BinaryOperator::Minus
+                | BinaryOperator::Multiply
+                | BinaryOperator::Divide
+                | BinaryOperator::Modulo
+        )
+    }
+
+    /// How tightly the operator binds, higher binding tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOperator::Or => 1,
+            BinaryOperator::And => 2,
+            BinaryOperator::Eq
+            | BinaryOperator::NotEq
+            | BinaryOperator::Lt
+            | BinaryOperator::LtEq
+            | BinaryOperator::Gt
+            | BinaryOperator::GtEq => 3,
+            BinaryOperator::Plus | BinaryOperator::Minus => 4,
+            BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Modulo => 5,
+        }
+    }
+}
+
+impl Function {
+    /// Looks a function up by its case-insensitive SQL name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_lowercase().as_str() {
+            "upper" => Function::Upper,
+            "lower" => Function::Lower,
+            "length" => Function::Length,
+            "abs" => Function::Abs,
+            "coalesce" => Function::Coalesce,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Function::Upper => "upper",
+            Function::Lower => "lower",
+            Function::Length => "length",
+            Function::Abs => "abs",
+            Function::Coalesce => "coalesce",
+        }
+    }
+
+    /// Whether the function can be called with `count` arguments.
+    pub fn accepts(self, count: usize) -> bool {
+        match self {
+            Function::Coalesce => count > 0,
+            _ => count == 1,
+        }
+    }
+}
+
+impl AggregateFunction {
+    /// Looks an aggregate up by its case-insensitive SQL name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_lowercase().as_str() {
+            "count" => AggregateFunction::Count,
+            "sum" => AggregateFunction::Sum,
+            "avg" => AggregateFunction::Avg,
+            "min" => AggregateFunction::Min,
+            "max" => AggregateFunction::Max,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AggregateFunction::Count => "count",
+            AggregateFunction::Sum => "sum",
+            AggregateFunction::Avg => "avg",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+        }
+    }
+}
+
+impl WindowFunction {
+    /// Looks a window function up by its case-insensitive SQL name,
+    /// aggregates included.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_lowercase().as_str() {
+            "row_number" => WindowFunction::RowNumber,
+            "rank" => WindowFunction::Rank,
+            "dense_rank" => WindowFunction::DenseRank,
+            "lag" => WindowFunction::Lag,
+            "lead" => WindowFunction::Lead,
+            "first_value" => WindowFunction::FirstValue,
+            "last_value" => WindowFunction::LastValue,
+            name => WindowFunction::Aggregate(AggregateFunction::from_name(name)?),
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WindowFunction::RowNumber => "row_number",
+            WindowFunction::Rank => "rank",
+            WindowFunction::DenseRank => "dense_rank",
+            WindowFunction::Lag => "lag",
+            WindowFunction::Lead => "lead",
+            WindowFunction::FirstValue => "first_value",
+            WindowFunction::LastValue => "last_value",
+            WindowFunction::Aggregate(func) => func.name(),
+        }
+    }
+
+    /// Whether the function can be called with `count` arguments.
+    pub fn accepts(self, count: usize) -> bool {
+        match self {
+            WindowFunction::RowNumber | WindowFunction::Rank | WindowFunction::DenseRank => {
+                count == 0
+            }
+            WindowFunction::Lag | WindowFunction::Lead => (1..=3).contains(&count),
+            WindowFunction::Aggregate(AggregateFunction::Count) => count <= 1,
+            _ => count == 1,
+        }
+    }
+}
+
+impl SelectItem {
+    /// The name of the column the item produces, unless it's a wildcard.
+    pub fn output_name(&self) -> Option<String> {
+        match self {
+            SelectItem::Wildcard(_) => None,
+            SelectItem::Expr {
+                alias: Some(alias), ..
+            } => Some(alias.clone()),
+            SelectItem::Expr {
+                expr: Expr::Column { name, .. },
+                ..
+            } => Some(name.clone()),
+            SelectItem::Expr { expr, .. } => Some(expr.to_string()),
+        }
+    }
+}
+
+impl Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            BinaryOperator::Eq => "=",
+            BinaryOperator::NotEq => "<>",
+            BinaryOperator::Lt => "<",
+            BinaryOperator::LtEq => "<=",
+            BinaryOperator::Gt => ">",
+            BinaryOperator::GtEq => ">=",
+            BinaryOperator::And => "AND",
+            BinaryOperator::Or => "OR",
+            BinaryOperator::Plus => "+",
+            BinaryOperator::Minus => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Modulo => "%",
+        };
+        f.write_str(op)
+    }
+}
+
+/// Writes `expr` as the operand of another operator, in parentheses unless it
+/// binds at least as tightly as `precedence`.
+fn write_operand(f: &mut fmt::Formatter<'_>, expr: &Expr, precedence: u8) -> fmt::Result {
+    match expr {
+        Expr::Binary { op, .. } if op.precedence() < precedence => write!(f, "({})", expr),
+        Expr::Not(_)
+        | Expr::IsNull { .. }
+        | Expr::InList { .. }
+        | Expr::InSubquery { .. }
+        | Expr::Exists { .. }
+        | Expr::Between { .. }
+        | Expr::Like { .. }
+            if precedence > BinaryOperator::And.precedence() =>
+        {
+            write!(f, "({})", expr)
+        }
+        _ => write!(f, "{}", expr),
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let not = |negated: &bool| if *negated { "NOT " } else { "" };
+        // Operands of the postfix operators bind tighter than comparisons
+        let operand = BinaryOperator::Plus.precedence();
+        match self {
+            Expr::Column {
+                table: Some(table),
+                name,
+            } => write!(f, "{}.{}", table, name),
+            Expr::Column { table: None, name } => f.write_str(name),
+            Expr::Literal(DataType::Text(text)) => write!(f, "'{}'", text.replace('\'', "''")),
+            Expr::Literal(value) => write!(f, "{}", value),
+            Expr::Null => f.write_str("NULL"),
+            Expr::Binary { left, op, right } => {
+                // Operators associate to the left, so an equally binding
+                // right operand needs parentheses
+                write_operand(f, left, op.precedence())?;
+                write!(f, " {} ", op)?;
+                write_operand(f, right, op.precedence() + 1)
+            }
+            Expr::Not(expr) => {
+                f.write_str("NOT ")?;
+                write_operand(f, expr, BinaryOperator::Eq.precedence())
+            }
+            Expr::IsNull { expr, negated } => {
+                write_operand(f, expr, operand)?;
+                write!(f, " IS {}NULL", not(negated))
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let list: Vec<String> = list.iter().map(|item| item.to_string()).collect();
+                write_operand(f, expr, operand)?;
+                write!(f, " {}IN ({})", not(negated), list.join(", "))
+            }
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                write_operand(f, expr, operand)?;
+                write!(f, " {}BETWEEN ", not(negated))?;
+                write_operand(f, low, operand)?;
+                f.write_str(" AND ")?;
+                write_operand(f, high, operand)
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                negated,
+            } => {
+                write_operand(f, expr, operand)?;
+                write!(f, " {}LIKE ", not(negated))?;
+                write_operand(f, pattern, operand)
+            }
+            Expr::Function { func, args } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", func.name(), args.join(", "))
+            }
+            Expr::Aggregate {
+                func,
+                arg,
+                distinct,
+            } => {
+                write!(f, "{}(", func.name())?;
+                if *distinct {
+                    f.write_str("DISTINCT ")?;
+                }
+                match arg {
+                    Some(arg) => write!(f, "{})", arg),
+                    None => f.write_str("*)"),
+                }
+            }
+            Expr::Subquery(query) => write!(f, "({})", query.to_string()),
+            Expr::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => {
+                write_operand(f, expr, operand)?;
+                write!(f, " {}IN ({})", not(negated), subquery.to_string())
+            }
+            Expr::Exists { subquery, negated } => {
+                write!(f, "{}EXISTS ({})", not(negated), subquery.to_string())
+            }
+            Expr::Window { func, args, over } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                let args = match (func, args.is_empty()) {
+                    (WindowFunction::Aggregate(AggregateFunction::Count), true) => "*".to_string(),
+                    _ => args.join(", "),
+                };
+                write!(f, "{}({}) OVER ({})", func.name(), args, over)
+            }
+        }
+    }
+}
+
+impl TableRef {
+    pub fn new(name: &str) -> Self {
+        TableRef {
+            name: name.to_string(),
+            alias: None,
+            subquery: None,
+        }
+    }
+
+    /// The name its columns are qualified with: the alias if there is one.
+    pub fn qualifier(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+impl JoinKind {
+    /// Whether rows of the left side are kept when they match nothing.
+    pub fn keeps_left(self) -> bool {
+        matches!(self, JoinKind::Left | JoinKind::Full)
+    }
+
+    /// Whether rows of the right side are kept when they match nothing.
+    pub fn keeps_right(self) -> bool {
+        matches!(self, JoinKind::Right | JoinKind::Full)
+    }
+}
+
+impl SetOperator {
+    /// How tightly the operator binds: `INTERSECT` before the others.
+    pub(crate) fn precedence(self) -> u8 {
+        match self {
+            SetOperator::Union | SetOperator::Except => 1,
+            SetOperator::Intersect => 2,
+        }
+    }
+}
+
+impl OrderByItem {
+    pub fn new(expr: Expr, ascending: bool) -> Self {
+        OrderByItem {
+            expr,
+            ascending,
+            nulls_first: ascending,
+        }
+    }
+
+    /// Orders two values of the key, NULL standing for `None`.
+    pub fn compare(&self, left: Option<&DataType>, right: Option<&DataType>) -> Ordering {
+        match (left, right) {
+            (Some(left), Some(right)) if self.ascending => left.cmp(right),
+            (Some(left), Some(right)) => right.cmp(left),
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) if self.nulls_first => Ordering::Less,
+            (Some(_), None) if !self.nulls_first => Ordering::Less,
+            _ => Ordering::Greater,
+        }
+    }
+}
+
+impl Display for SelectItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectItem::Wildcard(Some(table)) => write!(f, "{}.*", table),
+            SelectItem::Wildcard(None) => f.write_str("*"),
+            SelectItem::Expr { expr, alias } => {
+                write!(f, "{}", expr)?;
+                if let Some(alias) = alias {
+                    write!(f, " AS {}", alias)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for TableRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(query) = &self.subquery {
+            return write!(f, "({}) AS {}", query.to_string(), self.name);
+        }
+        f.write_str(&self.name)?;
+        if let Some(alias) = &self.alias {
+            write!(f, " AS {}", alias)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Join {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            JoinKind::Inner => "JOIN",
+            JoinKind::Left => "LEFT JOIN",
+            JoinKind::Right => "RIGHT JOIN",
+            JoinKind::Full => "FULL JOIN",
+            JoinKind::Cross => "CROSS JOIN",
+        };
+        write!(f, "{} {}", kind, self.table)?;
+        if let Some(condition) = &self.condition {
+            write!(f, " ON {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for SetOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SetOperator::Union => "UNION",
+            SetOperator::Intersect => "INTERSECT",
+            SetOperator::Except => "EXCEPT",
+        })
+    }
+}
+
+impl Display for Distinct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Distinct::All => f.write_str("DISTINCT"),
+            Distinct::On(exprs) => {
+                let exprs: Vec<String> = exprs.iter().map(|e| e.to_string()).collect();
+                write!(f, "DISTINCT ON ({})", exprs.join(", "))
+            }
+        }
+    }
+}
+
+impl Display for WindowSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut clauses = Vec::new();
+        if !self.partition_by.is_empty() {
+            let exprs: Vec<String> = self.partition_by.iter().map(|e| e.to_string()).collect();
+            clauses.push(format!("PARTITION BY {}", exprs.join(", ")));
+        }
+        if !self.order_by.is_empty() {
+            let items: Vec<String> = self.order_by.iter().map(|o| o.to_string()).collect();
+            clauses.push(format!("ORDER BY {}", items.join(", ")));
+        }
+        if let Some(frame) = &self.frame {
+            clauses.push(frame.to_string());
+        }
+        f.write_str(&clauses.join(" "))
+    }
+}
+
+impl Display for WindowFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let units = match self.units {
+            FrameUnits::Rows => "ROWS",
+            FrameUnits::Range => "RANGE",
+        };
+        write!(f, "{} BETWEEN {} AND {}", units, self.start, self.end)
+    }
+}
+
+impl Display for FrameBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameBound::UnboundedPreceding => f.write_str("UNBOUNDED PRECEDING"),
+            FrameBound::Preceding(offset) => write!(f, "{} PRECEDING", offset),
+            FrameBound::CurrentRow => f.write_str("CURRENT ROW"),
+            FrameBound::Following(offset) => write!(f, "{} FOLLOWING", offset),
+            FrameBound::UnboundedFollowing => f.write_str("UNBOUNDED FOLLOWING"),
+        }
+    }
+}
+
+impl Display for OrderByItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)?;
+        if !self.ascending {
+            f.write_str(" DESC")?;
+        }
+        if self.nulls_first != self.ascending {
+            f.write_str(if self.nulls_first {
+                " NULLS FIRST"
+            } else {
+                " NULLS LAST"
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(value: i64) -> Expr {
+        Expr::Literal(DataType::Integer(value))
+    }
+
+    #[test]
+    fn test_display_adds_only_needed_parentheses() {
+        let sum = Expr::binary(Expr::column("age"), BinaryOperator::Plus, int(1));
+        assert_eq!(sum.to_string(), "age + 1");
+
+        let product = Expr::binary(sum.clone(), BinaryOperator::Multiply, int(2));
+        assert_eq!(product.to_string(), "(age + 1) * 2");
+        let difference = Expr::binary(int(10), BinaryOperator::Minus, sum.clone());
+        assert_eq!(difference.to_string(), "10 - (age + 1)");
+
+        let adult = Expr::binary(Expr::column("age"), BinaryOperator::GtEq, int(18));
+        let either = Expr::binary(adult.clone(), BinaryOperator::Or, adult.clone());
+        let both = Expr::binary(either, BinaryOperator::And, adult);
+        assert_eq!(both.to_string(), "(age >= 18 OR age >= 18) AND age >= 18");
+        assert_eq!(
+            Expr::Not(Box::new(both)).to_string(),
+            "NOT ((age >= 18 OR age >= 18) AND age >= 18)"
+        );
+    }
+}
@@ -26,10 +26,25 @@ impl Index {
         }
     }
 
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    pub fn column_name(&self) -> &str {
+        &self.column_name
+    }
+
+    pub fn index_type(&self) -> &IndexType {
+        &self.index_type
+    }
+
+    /// Keeps the row indices of every value sorted, so an index holds the same
+    /// entries whether it was built at once or updated row by row.
     pub fn insert(&mut self, value: DataType, row_index: usize) {
-        self.entries.entry(value)
-            .or_insert_with(Vec::new)
-            .push(row_index);
+        let indices = self.entries.entry(value).or_default();
+        if let Err(pos) = indices.binary_search(&row_index) {
+            indices.insert(pos, row_index);
+        }
     }
 
     pub fn find(&self, value: &DataType) -> Option<&Vec<usize>> {
@@ -0,0 +1,370 @@
+//! Joins the rows of tables.
+//!
+//! Every join pairs the rows joined so far, on its left, with the rows of one
+//! more table, on its right. Depending on its `ON` condition it finds the
+//! matching rows of the right side in one of three ways:
+//!
+//! - An index nested loop looks each left row up in an index of the right
+//!   table, when the condition compares an indexed column with the left side.
+//! - A hash join hashes the right rows on the right side of an equality
+//!   between both sides.
+//! - A nested loop tries every pair of rows, for any other condition.
+
+use std::{borrow::Cow, collections::HashMap};
+
+use super::{
+    error_handling::FerrousDBError,
+    evaluator,
+    expr::{BinaryOperator, Expr, JoinKind},
+    index::Index,
+    query::Scope,
+    row::Row,
+    table::Table,
+    transaction::Snapshot,
+};
+use crate::DataType;
+
+/// The rows joined so far, keyed as `qualifier.name`.
+pub(crate) struct Joined {
+    pub scope: Scope,
+    pub rows: Vec<HashMap<String, DataType>>,
+}
+
+/// The table on the right side of a join.
+pub(crate) struct JoinTable<'a> {
+    pub table: &'a Table,
+    pub qualifier: &'a str,
+    pub snapshot: Snapshot,
+    /// Indexes of the table by column name. They must index the rows
+    /// `snapshot` sees.
+    pub indexes: HashMap<&'a str, &'a Index>,
+}
+
+/// A right row matching a left row, with its position in the right rows
+/// read up front if it is one of them.
+type Candidate<'a> = (Option<usize>, Cow<'a, HashMap<String, DataType>>);
+
+/// How a join finds the right rows matching a left row.
+#[derive(Debug, PartialEq)]
+enum Strategy<'a> {
+    NestedLoop,
+    /// Hashes the right rows on `right` and looks `left` up.
+    Hash {
+        left: Expr,
+        right: Expr,
+    },
+    /// Looks `left` up in an index of the right table.
+    IndexLookup {
+        left: Expr,
+        index: &'a Index,
+    },
+}
+
+impl Joined {
+    /// The rows of a single table.
+    pub(crate) fn table(table: &Table, qualifier: &str, snapshot: Snapshot) -> Self {
+        let scope = Scope::table(table, qualifier).qualified();
+        let rows = table
+            .visible_rows(snapshot)
+            .map(|(_, row)| qualify(&scope, row))
+            .collect();
+        Joined { scope, rows }
+    }
+}
+
+/// Joins `left` with the rows of `right` for which `condition` holds.
+pub(crate) fn join(
+    left: Joined,
+    right: JoinTable<'_>,
+    kind: JoinKind,
+    condition: Option<&Expr>,
+) -> Result<Joined, FerrousDBError> {
+    let right_scope = Scope::table(right.table, right.qualifier).qualified();
+    let scope = left.scope.join(right_scope.clone())?;
+    let condition = condition
+        .map(|condition| scope.bind_condition(condition))
+        .transpose()?;
+    let strategy = plan(&scope, &right, kind, condition.as_ref());
+
+    let right_rows: Vec<HashMap<String, DataType>> = match strategy {
+        // Only the rows found in the index are read
+        Strategy::IndexLookup { .. } => Vec::new(),
+        _ => right
+            .table
+            .visible_rows(right.snapshot)
+            .map(|(_, row)| qualify(&right_scope, row))
+            .collect(),
+    };
+    let mut buckets: HashMap<DataType, Vec<usize>> = HashMap::new();
+    if let Strategy::Hash { right: key, .. } = &strategy {
+        for (position, row) in right_rows.iter().enumerate() {
+            // NULL equals nothing
+            if let Some(value) = evaluator::evaluate(key, row)? {
+                buckets.entry(value).or_default().push(position);
+            }
+        }
+    }
+
+    let mut rows = Vec::new();
+    let mut right_matched = vec![false; right_rows.len()];
+    for left_row in left.rows {
+        // The matching right rows, with their positions in `right_rows`
+        let candidates: Vec<Candidate> = match &strategy {
+            Strategy::NestedLoop => right_rows
+                .iter()
+                .enumerate()
+                .map(|(position, row)| (Some(position), Cow::Borrowed(row)))
+                .collect(),
+            Strategy::Hash { left: key, .. } => match evaluator::evaluate(key, &left_row)? {
+                Some(value) => buckets
+                    .get(&value)
+                    .into_iter()
+                    .flatten()
+                    .map(|&position| (Some(position), Cow::Borrowed(&right_rows[position])))
+                    .collect(),
+                None => Vec::new(),
+            },
+            Strategy::IndexLookup { left: key, index } => {
+                match evaluator::evaluate(key, &left_row)? {
+                    Some(value) => index
+                        .find(&value)
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|&position| {
+                            right.table.rows.get(position)?.visible(right.snapshot)
+                        })
+                        .map(|row| (None, Cow::Owned(qualify(&right_scope, row))))
+                        .collect(),
+                    None => Vec::new(),
+                }
+            }
+        };
+
+        let mut left_matched = false;
+        for (position, right_row) in candidates {
+            let mut row = left_row.clone();
+            row.extend(right_row.into_owned());
+            if let Some(condition) = &condition {
+                if !evaluator::matches(condition, &row)? {
+                    continue;
+                }
+            }
+            left_matched = true;
+            if let Some(position) = position {
+                right_matched[position] = true;
+            }
+            rows.push(row);
+        }
+        // The columns of the right side are missing, hence NULL
+        if !left_matched && kind.keeps_left() {
+            rows.push(left_row);
+        }
+    }
+    if kind.keeps_right() {
+        rows.extend(
+            right_rows
+                .into_iter()
+                .zip(right_matched)
+                .filter(|(_, matched)| !matched)
+                .map(|(row, _)| row),
+        );
+    }
+    Ok(Joined { scope, rows })
+}
+
+/// Picks how to find matching rows, looking for an equality between the
+/// left and the right side among the conditions `AND` combines. The whole
+/// condition is still checked for every pair found.
+fn plan<'a>(
+    scope: &Scope,
+    right: &JoinTable<'a>,
+    kind: JoinKind,
+    condition: Option<&Expr>,
+) -> Strategy<'a> {
+    let mut conjuncts = Vec::new();
+    if let Some(condition) = condition {
+        split_and(condition, &mut conjuncts);
+    }
+    let mut keys = Vec::new();
+    for conjunct in conjuncts {
+        let Expr::Binary {
+            left,
+            op: BinaryOperator::Eq,
+            right: other,
+        } = conjunct
+        else {
+            continue;
+        };
+        let pair = match (side(left, right.qualifier), side(other, right.qualifier)) {
+            (Some(Side::Left), Some(Side::Right)) => (left.as_ref(), other.as_ref()),
+            (Some(Side::Right), Some(Side::Left)) => (other.as_ref(), left.as_ref()),
+            _ => continue,
+        };
+        // Hashing would skip the type errors comparing the values raises
+        if scope.expr_type(pair.0) == scope.expr_type(pair.1) {
+            keys.push(pair);
+        }
+    }
+
+    // The index only finds rows matching a left row, so it can't tell which
+    // right rows match nothing
+    if !kind.keeps_right() {
+        for (left, key) in &keys {
+            if let Expr::Column { name, .. } = key {
+                if let Some(index) = right.indexes.get(name.as_str()) {
+                    return Strategy::IndexLookup {
+                        left: (*left).clone(),
+                        index,
+                    };
+                }
+            }
+        }
+    }
+    match keys.first() {
+        Some((left, right)) => Strategy::Hash {
+            left: (*left).clone(),
+            right: (*right).clone(),
+        },
+        None => Strategy::NestedLoop,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Side {
+    Left,
+    Right,
+}
+
+/// Which side of the join a bound expression reads columns from, if only
+/// one.
+fn side(expr: &Expr, right_qualifier: &str) -> Option<Side> {
+    let mut right = false;
+    let mut left = false;
+    expr.visit_columns(&mut |qualifier, _| {
+        if qualifier == Some(right_qualifier) {
+            right = true;
+        } else {
+            left = true;
+        }
+    });
+    match (left, right) {
+        (true, false) => Some(Side::Left),
+        (false, true) => Some(Side::Right),
+        _ => None,
+    }
+}
+
+fn split_and<'a>(expr: &'a Expr, conjuncts: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Binary {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            split_and(left, conjuncts);
+            split_and(right, conjuncts);
+        }
+        _ => conjuncts.push(expr),
+    }
+}
+
+/// The values of `row` keyed as the qualified `scope` of its table names
+/// them.
+fn qualify(scope: &Scope, row: &Row) -> HashMap<String, DataType> {
+    scope
+        .columns()
+        .iter()
+        .filter_map(|column| {
+            let value = row.data.get(&column.name)?;
+            Some((scope.key(column), value.clone()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{index::IndexType, table::ColumnSchema};
+
+    fn table(name: &str, columns: &[&str]) -> Table {
+        Table::new(
+            name.to_string(),
+            columns
+                .iter()
+                .map(|column| ColumnSchema::new(column.to_string(), "INTEGER".to_string()))
+                .collect(),
+        )
+    }
+
+    fn column(table: &str, name: &str) -> Expr {
+        Expr::Column {
+            table: Some(table.to_string()),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_plan_picks_strategy() {
+        let users = table("users", &["id", "age"]);
+        let orders = table("orders", &["user_id", "total"]);
+        let scope = Scope::table(&users, "u")
+            .join(Scope::table(&orders, "o"))
+            .unwrap();
+        let index = Index::new("orders".to_string(), "user_id".to_string(), IndexType::Hash);
+        let mut right = JoinTable {
+            table: &orders,
+            qualifier: "o",
+            snapshot: Snapshot::new(0),
+            indexes: HashMap::new(),
+        };
+        let equal = Expr::binary(
+            column("o", "user_id"),
+            BinaryOperator::Eq,
+            column("u", "id"),
+        );
+        let condition = Expr::binary(
+            Expr::binary(column("o", "total"), BinaryOperator::Gt, column("u", "age")),
+            BinaryOperator::And,
+            equal.clone(),
+        );
+
+        assert_eq!(
+            plan(&scope, &right, JoinKind::Inner, Some(&condition)),
+            Strategy::Hash {
+                left: column("u", "id"),
+                right: column("o", "user_id"),
+            }
+        );
+        right.indexes.insert("user_id", &index);
+        assert_eq!(
+            plan(&scope, &right, JoinKind::Left, Some(&condition)),
+            Strategy::IndexLookup {
+                left: column("u", "id"),
+                index: &index,
+            }
+        );
+        assert!(matches!(
+            plan(&scope, &right, JoinKind::Full, Some(&equal)),
+            Strategy::Hash { .. }
+        ));
+
+        let inequal = Expr::binary(column("o", "total"), BinaryOperator::Gt, column("u", "age"));
+        assert_eq!(
+            plan(&scope, &right, JoinKind::Inner, Some(&inequal)),
+            Strategy::NestedLoop
+        );
+        let one_sided = Expr::binary(
+            column("o", "total"),
+            BinaryOperator::Eq,
+            column("o", "user_id"),
+        );
+        assert_eq!(
+            plan(&scope, &right, JoinKind::Inner, Some(&one_sided)),
+            Strategy::NestedLoop
+        );
+        assert_eq!(
+            plan(&scope, &right, JoinKind::Cross, None),
+            Strategy::NestedLoop
+        );
+    }
+}
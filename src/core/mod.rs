@@ -1,9 +1,18 @@
 pub mod bptree;
+pub mod database;
 pub mod db;
 pub mod error_handling;
+pub mod evaluator;
+pub mod expr;
 pub mod index;
+pub mod join;
 pub mod parser;
+pub mod query;
+pub mod result_set;
 pub mod row;
+pub mod storage;
 pub mod table;
+pub mod transaction;
 pub mod view;
+pub mod window;
 pub mod write_ahead_log;
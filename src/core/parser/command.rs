@@ -1,9 +1,13 @@
 use crate::core::error_handling::FerrousDBError;
+use crate::core::expr::{Distinct, Expr, Join, OrderByItem, SelectItem, SetOperator, TableRef};
+use crate::core::parser::sql_parser::parse_expr;
 use crate::{core::table::ColumnSchema, DataType};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt::{self, Display};
 use std::str::FromStr;
 
-#[derive(Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum SQLCommand {
     CreateTable {
         name: String,
@@ -14,29 +18,188 @@ pub enum SQLCommand {
         query: String,
         columns: Vec<String>,
     },
+    /// Inserts the rows of `source`, giving `columns` the values of each
+    /// row in order, or all the columns of the table when there are none.
+    /// Columns left out take their default values.
     InsertInto {
         table: String,
-        values: HashMap<String, DataType>,
+        columns: Vec<String>,
+        source: InsertSource,
     },
     SelectFrom {
-        table: String,
-        page_size: usize,
-        page: usize,
-        group_by: Option<String>,
-        order_by: Option<(String, bool)>, // (column_name, is_ascending)
+        table: TableRef,
+        joins: Vec<Join>,
+        projection: Vec<SelectItem>,
+        /// Whether to leave out duplicate rows, and which.
+        distinct: Option<Distinct>,
+        /// The most rows to return, all of them when `None`.
+        limit: Option<usize>,
+        /// How many rows to skip before the first one returned.
+        offset: usize,
+        filter: Option<Expr>,
+        group_by: Vec<Expr>,
+        having: Option<Expr>,
+        order_by: Vec<OrderByItem>,
+    },
+    /// Combines the rows of two queries, which must give as many columns
+    /// of the same types. The result takes its column names from `left`.
+    SetOperation {
+        op: SetOperator,
+        /// Whether duplicate rows are kept, as in `UNION ALL`.
+        all: bool,
+        left: Box<SQLCommand>,
+        right: Box<SQLCommand>,
+        /// Sorts the combined rows, by the names or positions of their
+        /// columns.
+        order_by: Vec<OrderByItem>,
+        limit: Option<usize>,
+        offset: usize,
+    },
+    /// Runs `query` reading the results of `ctes` like tables, each of
+    /// which may read those before it. With `recursive`, the query of a CTE
+    /// may also read its own result, in the right side of a `UNION`.
+    With {
+        recursive: bool,
+        ctes: Vec<Cte>,
+        query: Box<SQLCommand>,
     },
     DeleteFrom {
         table: String,
-        condition: Option<String>,
+        condition: Option<Expr>,
     },
     Update {
         table: String,
         assignments: HashMap<String, DataType>,
-        condition: Option<String>,
+        condition: Option<Expr>,
+    },
+    Checkpoint,
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint {
+        name: String,
+    },
+    Release {
+        name: String,
+    },
+    RollbackTo {
+        name: String,
     },
 }
 
+/// A common table expression of a `WITH` clause: the query whose result
+/// the statement reads as the table `name`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cte {
+    pub name: String,
+    /// Names for the first columns of the result, the others keeping their
+    /// own.
+    pub columns: Vec<String>,
+    pub query: SQLCommand,
+}
+
+/// The rows an `INSERT` adds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum InsertSource {
+    Values(Vec<Vec<InsertValue>>),
+    /// The rows a query returns, as in `INSERT INTO t SELECT ...`.
+    Query(Box<SQLCommand>),
+    /// A single row of default values.
+    DefaultValues,
+}
+
+/// A value of a row of `INSERT ... VALUES`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum InsertValue {
+    Value(DataType),
+    Null,
+    /// The default value of the column, `NULL` when it has none.
+    Default,
+}
+
+impl Display for InsertValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InsertValue::Value(value) => write!(f, "{}", Expr::Literal(value.clone())),
+            InsertValue::Null => write!(f, "NULL"),
+            InsertValue::Default => write!(f, "DEFAULT"),
+        }
+    }
+}
+
 impl SQLCommand {
+    /// Whether the statement only reads data.
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            SQLCommand::SelectFrom { .. }
+                | SQLCommand::SetOperation { .. }
+                | SQLCommand::With { .. }
+        )
+    }
+
+    /// Whether the query reads the table `name`, in its `FROM` clause, its
+    /// joins or its subqueries.
+    pub(crate) fn reads_table(&self, name: &str) -> bool {
+        match self {
+            SQLCommand::SelectFrom {
+                table,
+                joins,
+                projection,
+                distinct,
+                filter,
+                group_by,
+                having,
+                order_by,
+                ..
+            } => {
+                let tables = std::iter::once(table).chain(joins.iter().map(|join| &join.table));
+                let mut exprs: Vec<&Expr> = projection
+                    .iter()
+                    .filter_map(|item| match item {
+                        SelectItem::Expr { expr, .. } => Some(expr),
+                        SelectItem::Wildcard(_) => None,
+                    })
+                    .collect();
+                if let Some(Distinct::On(on)) = distinct {
+                    exprs.extend(on);
+                }
+                exprs.extend(joins.iter().filter_map(|join| join.condition.as_ref()));
+                exprs.extend(filter.iter().chain(group_by).chain(having));
+                exprs.extend(order_by.iter().map(|order| &order.expr));
+                tables
+                    .map(|table| match &table.subquery {
+                        Some(query) => query.reads_table(name),
+                        None => table.name == name,
+                    })
+                    .chain(
+                        exprs
+                            .iter()
+                            .flat_map(|expr| expr.subqueries())
+                            .map(|query| query.reads_table(name)),
+                    )
+                    .any(|reads| reads)
+            }
+            SQLCommand::SetOperation { left, right, .. } => {
+                left.reads_table(name) || right.reads_table(name)
+            }
+            // A CTE of the same name hides the table from the query
+            SQLCommand::With { ctes, query, .. } => {
+                ctes.iter().any(|cte| cte.query.reads_table(name))
+                    || (ctes.iter().all(|cte| cte.name != name) && query.reads_table(name))
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the statement starts or ends a transaction.
+    pub fn is_transaction_control(&self) -> bool {
+        matches!(
+            self,
+            SQLCommand::Begin | SQLCommand::Commit | SQLCommand::Rollback
+        )
+    }
+
     pub fn to_string(&self) -> String {
         match self {
             SQLCommand::CreateTable { name, columns } => {
@@ -57,39 +220,100 @@ impl SQLCommand {
             } => {
                 format!("CREATE VIEW {} ({}) AS {}", name, columns.join(", "), query)
             }
-            SQLCommand::InsertInto { table, values } => {
-                let columns: Vec<String> = values.keys().map(|s| s.to_string()).collect();
-                let values: Vec<String> = values.values().map(|s| s.to_string()).collect();
-                format!(
-                    "INSERT INTO {} ({}) VALUES ({})",
-                    table,
-                    columns.join(", "),
-                    values.join(", ")
-                )
+            SQLCommand::InsertInto {
+                table,
+                columns,
+                source,
+            } => {
+                let mut insert = format!("INSERT INTO {}", table);
+                if !columns.is_empty() {
+                    insert.push_str(&format!(" ({})", columns.join(", ")));
+                }
+                match source {
+                    InsertSource::Values(rows) => {
+                        let rows: Vec<String> = rows
+                            .iter()
+                            .map(|row| {
+                                let values: Vec<String> =
+                                    row.iter().map(|value| value.to_string()).collect();
+                                format!("({})", values.join(", "))
+                            })
+                            .collect();
+                        insert.push_str(&format!(" VALUES {}", rows.join(", ")));
+                    }
+                    InsertSource::Query(query) => {
+                        insert.push_str(&format!(" {}", query.to_string()))
+                    }
+                    InsertSource::DefaultValues => insert.push_str(" DEFAULT VALUES"),
+                }
+                insert
             }
             SQLCommand::SelectFrom {
                 table,
-                page_size,
-                page,
+                joins,
+                projection,
+                distinct,
+                limit,
+                offset,
+                filter,
                 group_by,
+                having,
                 order_by,
             } => {
-                let mut query = format!(
-                    "SELECT * FROM {} LIMIT {} OFFSET {}",
-                    table, page_size, page
-                );
-                if let Some(group_by) = group_by {
-                    query.push_str(&format!(" GROUP BY {}", group_by));
+                let projection: Vec<String> = projection.iter().map(|i| i.to_string()).collect();
+                let mut query = String::from("SELECT ");
+                if let Some(distinct) = distinct {
+                    query.push_str(&format!("{} ", distinct));
                 }
-                if let Some((column, is_ascending)) = order_by {
-                    query.push_str(&format!(
-                        " ORDER BY {} {}",
-                        column,
-                        if *is_ascending { "ASC" } else { "DESC" }
-                    ));
+                query.push_str(&format!("{} FROM {}", projection.join(", "), table));
+                for join in joins {
+                    query.push_str(&format!(" {}", join));
+                }
+                if let Some(filter) = filter {
+                    query.push_str(&format!(" WHERE {}", filter));
                 }
+                if !group_by.is_empty() {
+                    let group_by: Vec<String> = group_by.iter().map(|e| e.to_string()).collect();
+                    query.push_str(&format!(" GROUP BY {}", group_by.join(", ")));
+                }
+                if let Some(having) = having {
+                    query.push_str(&format!(" HAVING {}", having));
+                }
+                push_order_by_and_limit(&mut query, order_by, *limit, *offset);
                 query
             }
+            SQLCommand::SetOperation {
+                op,
+                all,
+                left,
+                right,
+                order_by,
+                limit,
+                offset,
+            } => {
+                let mut query = format!(
+                    "{} {}{} {}",
+                    left.set_operand(*op, false),
+                    op,
+                    if *all { " ALL" } else { "" },
+                    right.set_operand(*op, true)
+                );
+                push_order_by_and_limit(&mut query, order_by, *limit, *offset);
+                query
+            }
+            SQLCommand::With {
+                recursive,
+                ctes,
+                query,
+            } => {
+                let ctes: Vec<String> = ctes.iter().map(|cte| cte.to_string()).collect();
+                format!(
+                    "WITH {}{} {}",
+                    if *recursive { "RECURSIVE " } else { "" },
+                    ctes.join(", "),
+                    query.to_string()
+                )
+            }
             SQLCommand::DeleteFrom { table, condition } => {
                 let condition_str = condition
                     .as_ref()
@@ -113,10 +337,80 @@ impl SQLCommand {
                     .unwrap_or_else(|| String::new());
                 format!("UPDATE {} SET {}{}", table, assignments_str, condition_str)
             }
+            SQLCommand::Checkpoint => "CHECKPOINT".to_string(),
+            SQLCommand::Begin => "BEGIN".to_string(),
+            SQLCommand::Commit => "COMMIT".to_string(),
+            SQLCommand::Rollback => "ROLLBACK".to_string(),
+            SQLCommand::Savepoint { name } => format!("SAVEPOINT {}", name),
+            SQLCommand::Release { name } => format!("RELEASE {}", name),
+            SQLCommand::RollbackTo { name } => format!("ROLLBACK TO {}", name),
+        }
+    }
+
+    /// The query as an operand of a set operation, in parentheses when it
+    /// sorts or limits its own rows, or when it is a set operation binding
+    /// less tightly than `op`. Set operations associate to the left.
+    fn set_operand(&self, op: SetOperator, right: bool) -> String {
+        let parenthesized = match self {
+            SQLCommand::SelectFrom {
+                order_by,
+                limit,
+                offset,
+                ..
+            } => !order_by.is_empty() || limit.is_some() || *offset > 0,
+            SQLCommand::SetOperation {
+                op: inner,
+                order_by,
+                limit,
+                offset,
+                ..
+            } => {
+                !order_by.is_empty()
+                    || limit.is_some()
+                    || *offset > 0
+                    || inner.precedence() < op.precedence()
+                    || (right && inner.precedence() == op.precedence())
+            }
+            SQLCommand::With { .. } => true,
+            _ => false,
+        };
+        if parenthesized {
+            format!("({})", self.to_string())
+        } else {
+            self.to_string()
         }
     }
 }
 
+impl Display for Cte {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if !self.columns.is_empty() {
+            write!(f, " ({})", self.columns.join(", "))?;
+        }
+        write!(f, " AS ({})", self.query.to_string())
+    }
+}
+
+/// Appends the `ORDER BY`, `LIMIT` and `OFFSET` clauses of a query.
+fn push_order_by_and_limit(
+    query: &mut String,
+    order_by: &[OrderByItem],
+    limit: Option<usize>,
+    offset: usize,
+) {
+    if !order_by.is_empty() {
+        let order_by: Vec<String> = order_by.iter().map(|o| o.to_string()).collect();
+        query.push_str(&format!(" ORDER BY {}", order_by.join(", ")));
+    }
+    if let Some(limit) = limit {
+        query.push_str(&format!(" LIMIT {}", limit));
+    }
+    if offset > 0 {
+        query.push_str(&format!(" OFFSET {}", offset));
+    }
+}
+
 impl FromIterator<String> for SQLCommand {
     fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
         let mut iter = iter.into_iter();
@@ -141,46 +435,54 @@ impl FromIterator<String> for SQLCommand {
             }
             "INSERT INTO" => {
                 let table = iter.next().unwrap();
-                let values: HashMap<String, DataType> = iter
+                let (columns, values) = iter
                     .map(|s| {
                         let mut parts = s.splitn(2, '=');
                         let key = parts.next().unwrap().to_string();
                         let value = parts.next().unwrap().to_string();
-                        (key, value.parse::<DataType>().unwrap())
+                        (key, InsertValue::Value(value.parse::<DataType>().unwrap()))
                     })
-                    .collect();
-                SQLCommand::InsertInto { table, values }
+                    .unzip();
+                SQLCommand::InsertInto {
+                    table,
+                    columns,
+                    source: InsertSource::Values(vec![values]),
+                }
             }
             "SELECT * FROM" => {
                 let table = iter.next().unwrap();
-                let page_size = iter
+                let limit = iter.next().map(|s| s.parse::<usize>().unwrap());
+                let offset = iter
                     .next()
-                    .unwrap_or_else(|| "1000".to_string())
+                    .unwrap_or_else(|| "0".to_string())
                     .parse::<usize>()
                     .unwrap();
-                let page = iter
+                let group_by = iter.next().map(|s| Expr::column(&s)).into_iter().collect();
+                let order_by = iter
                     .next()
-                    .unwrap_or_else(|| "1".to_string())
-                    .parse::<usize>()
-                    .unwrap();
-                let group_by = iter.next();
-                let order_by = iter.next().map(|s| {
-                    let mut parts = s.splitn(2, ' ');
-                    let column = parts.next().unwrap().to_string();
-                    let is_ascending = parts.next().unwrap_or("ASC") == "ASC";
-                    (column, is_ascending)
-                });
+                    .map(|s| {
+                        let mut parts = s.splitn(2, ' ');
+                        let column = Expr::column(parts.next().unwrap());
+                        OrderByItem::new(column, parts.next().unwrap_or("ASC") == "ASC")
+                    })
+                    .into_iter()
+                    .collect();
                 SQLCommand::SelectFrom {
-                    table,
-                    page_size,
-                    page,
+                    table: TableRef::new(&table),
+                    joins: Vec::new(),
+                    projection: vec![SelectItem::Wildcard(None)],
+                    distinct: None,
+                    limit,
+                    offset,
+                    filter: None,
                     group_by,
+                    having: None,
                     order_by,
                 }
             }
             "DELETE FROM" => {
                 let table = iter.next().unwrap();
-                let condition = iter.next().map(|s| s.to_string());
+                let condition = iter.next().map(|s| parse_expr(&s).unwrap());
                 SQLCommand::DeleteFrom { table, condition }
             }
             "UPDATE" => {
@@ -194,12 +496,7 @@ impl FromIterator<String> for SQLCommand {
                     .position(|x| x.eq_ignore_ascii_case("WHERE"));
 
                 let assignments: HashMap<String, DataType> = if let Some(pos) = where_pos {
-                    let (assignments, where_clause) = remaining.split_at(pos);
-                    let condition = where_clause[1..]
-                        .iter()
-                        .map(|s| s.as_str())
-                        .collect::<Vec<&str>>()
-                        .join(" ");
+                    let (assignments, _) = remaining.split_at(pos);
                     assignments
                         .iter()
                         .filter(|s| !s.is_empty())
@@ -223,7 +520,8 @@ impl FromIterator<String> for SQLCommand {
                         .collect()
                 };
 
-                let condition = where_pos.map(|pos| remaining[pos + 1..].join(" "));
+                let condition =
+                    where_pos.map(|pos| parse_expr(&remaining[pos + 1..].join(" ")).unwrap());
 
                 SQLCommand::Update {
                     table,
@@ -257,11 +555,16 @@ impl FromStr for SQLCommand {
                 let table = parts.remove(0);
 
                 Ok(SQLCommand::SelectFrom {
-                    table,
-                    page_size: 1000,
-                    page: 1,
-                    group_by: None,
-                    order_by: None,
+                    table: TableRef::new(&table),
+                    joins: Vec::new(),
+                    projection: vec![SelectItem::Wildcard(None)],
+                    distinct: None,
+                    limit: None,
+                    offset: 0,
+                    filter: None,
+                    group_by: Vec::new(),
+                    having: None,
+                    order_by: Vec::new(),
                 })
             }
             "INSERT" => {
@@ -274,7 +577,8 @@ impl FromStr for SQLCommand {
                 parts.remove(0); // remove "INTO"
                 let table = parts.remove(0);
 
-                let mut values = HashMap::new();
+                let mut columns = Vec::new();
+                let mut values = Vec::new();
                 for part in parts {
                     let mut pair = part.splitn(2, '=');
                     let key = pair
@@ -292,10 +596,15 @@ impl FromStr for SQLCommand {
                         .map_err(|_| {
                             FerrousDBError::ParseError("Invalid value type".to_string())
                         })?;
-                    values.insert(key, value);
+                    columns.push(key);
+                    values.push(InsertValue::Value(value));
                 }
 
-                Ok(SQLCommand::InsertInto { table, values })
+                Ok(SQLCommand::InsertInto {
+                    table,
+                    columns,
+                    source: InsertSource::Values(vec![values]),
+                })
             }
             "UPDATE" => {
                 if parts.len() < 4 || parts[1].to_uppercase() != "SET" {
@@ -310,7 +619,7 @@ impl FromStr for SQLCommand {
 
                 let (assignments_part, condition) = if let Some(pos) = where_pos {
                     let (left, right) = parts.split_at(pos);
-                    (left.to_vec(), Some(right[1..].join(" ")))
+                    (left.to_vec(), Some(parse_expr(&right[1..].join(" "))?))
                 } else {
                     (parts, None)
                 };
@@ -358,7 +667,9 @@ impl FromStr for SQLCommand {
 
                 let where_pos = parts.iter().position(|x| x.to_uppercase() == "WHERE");
 
-                let condition = where_pos.map(|pos| parts[pos + 1..].join(" "));
+                let condition = where_pos
+                    .map(|pos| parse_expr(&parts[pos + 1..].join(" ")))
+                    .transpose()?;
 
                 Ok(SQLCommand::DeleteFrom { table, condition })
             }
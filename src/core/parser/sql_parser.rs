@@ -1,12 +1,29 @@
-use crate::{core::error_handling::FerrousDBError, core::parser::command::SQLCommand, DataType};
-use sqlparser::ast::{Expr, GroupByExpr, Offset, Statement};
+use crate::core::expr::{
+    AggregateFunction, BinaryOperator, Distinct, Expr as TypedExpr, FrameBound, FrameUnits,
+    Function, Join, JoinKind, OrderByItem, SelectItem, SetOperator, TableRef, WindowFrame,
+    WindowFunction, WindowSpec,
+};
+use crate::core::parser::command::{Cte, InsertSource, InsertValue, SQLCommand};
+use crate::{core::error_handling::FerrousDBError, DataType};
+use sqlparser::ast::{
+    ColumnOption, DuplicateTreatment, Expr, FromTable, FunctionArg, FunctionArgExpr,
+    FunctionArgumentList, FunctionArguments, GroupByExpr, JoinConstraint, JoinOperator, Offset,
+    OrderByExpr, Query, Select, SetExpr, SetQuantifier, Statement, TableFactor, TableWithJoins,
+    UnaryOperator, Value, WindowFrameBound, WindowFrameUnits, WindowType,
+};
 use std::collections::HashMap;
 
-use crate::core::table::ColumnSchema;
+use crate::core::table::{ColumnSchema, Constraint};
 use sqlparser::dialect::GenericDialect;
 use sqlparser::parser::Parser;
+use sqlparser::tokenizer::Token;
 
 pub fn parse_sql(sql: &str) -> Result<SQLCommand, FerrousDBError> {
+    // CHECKPOINT isn't part of the dialects understood by sqlparser
+    if sql.trim().trim_end_matches(';').trim().eq_ignore_ascii_case("CHECKPOINT") {
+        return Ok(SQLCommand::Checkpoint);
+    }
+
     let dialect = GenericDialect {}; // or a more specific dialect if needed
     let ast =
         Parser::parse_sql(&dialect, sql).map_err(|e| FerrousDBError::ParseError(e.to_string()))?;
@@ -20,127 +37,53 @@ pub fn parse_sql(sql: &str) -> Result<SQLCommand, FerrousDBError> {
     match &ast[0] {
         Statement::CreateTable(create_table) => {
             let table_name = create_table.name.to_string();
-            let column_names: Vec<ColumnSchema> = create_table
-                .columns
-                .iter()
-                .map(|c| ColumnSchema::new(c.name.value.clone(), c.data_type.to_string()))
-                .collect();
+            let mut column_names = Vec::new();
+            for c in &create_table.columns {
+                let mut column = ColumnSchema::new(c.name.value.clone(), c.data_type.to_string());
+                for option in &c.options {
+                    match &option.option {
+                        ColumnOption::Check(expr) => {
+                            column
+                                .constraints
+                                .push(Constraint::Check(convert_expr(expr)?));
+                        }
+                        // A NULL default is the same as none
+                        ColumnOption::Default(expr) => match convert_expr(expr)? {
+                            TypedExpr::Literal(value) => {
+                                column.constraints.push(Constraint::Default(value));
+                            }
+                            TypedExpr::Null => {}
+                            _ => {
+                                return Err(FerrousDBError::ParseError(format!(
+                                    "Unsupported DEFAULT value '{}'",
+                                    expr
+                                )))
+                            }
+                        },
+                        _ => {}
+                    }
+                }
+                column_names.push(column);
+            }
             Ok(SQLCommand::CreateTable {
                 name: table_name,
                 columns: column_names,
             })
         }
         Statement::Insert(insert) => {
-            // For simplicity, we'll assume a single row of values
-            if let Some(query) = &insert.source {
-                if let sqlparser::ast::SetExpr::Values(values) = &*query.body {
-                    if let Some(row) = values.rows.first() {
-                        let mut values: HashMap<String, DataType> =
-                            std::collections::HashMap::new();
-                        for (col, val) in insert.columns.iter().zip(row.iter()) {
-                            values.insert(
-                                col.value.clone(),
-                                val.to_string().parse::<DataType>().unwrap(),
-                            );
-                        }
-                        Ok(SQLCommand::InsertInto {
-                            table: insert.table_name.to_string(),
-                            values,
-                        })
-                    } else {
-                        Err(FerrousDBError::ParseError(
-                            "No values provided for insert".to_string(),
-                        ))
-                    }
-                } else {
-                    Err(FerrousDBError::ParseError(
-                        "Unsupported INSERT format".to_string(),
-                    ))
-                }
-            } else {
-                Err(FerrousDBError::ParseError(
-                    "No values provided for insert".to_string(),
-                ))
-            }
+            let source = match &insert.source {
+                // INSERT ... DEFAULT VALUES
+                None => InsertSource::DefaultValues,
+                Some(query) => convert_insert_source(query)?,
+            };
+            Ok(SQLCommand::InsertInto {
+                table: insert.table_name.to_string(),
+                columns: insert.columns.iter().map(|c| c.value.clone()).collect(),
+                source,
+            })
         }
-        Statement::Query(query) => {
-            if let sqlparser::ast::SetExpr::Select(select) = &*query.body {
-                if let Some(table) = select.from.first() {
-                    if let sqlparser::ast::TableFactor::Table { name, .. } = &table.relation {
-                        let mut page = 1;
-                        let mut page_size = 1000;
-
-                        // Check for LIMIT clause
-                        if let Some(limit) = &query.limit {
-                            if let Expr::Value(sqlparser::ast::Value::Number(n, _)) = limit {
-                                if let Ok(parsed_limit) = n.parse::<usize>() {
-                                    page_size = parsed_limit;
-                                }
-                            }
-                        }
-
-                        // Check for OFFSET clause
-                        if let Some(Offset { value, .. }) = &query.offset {
-                            if let Expr::Value(sqlparser::ast::Value::Number(n, _)) = value {
-                                if let Ok(parsed_offset) = n.parse::<usize>() {
-                                    page = (parsed_offset / page_size) + 1;
-                                    if parsed_offset % page_size != 0 {
-                                        page += 1;
-                                    }
-                                }
-                            }
-                        }
 
-                        // Parse GROUP BY
-                        let group_by = match &select.group_by {
-                            GroupByExpr::Expressions(exprs, _modifiers) => {
-                                exprs.first().and_then(|expr| {
-                                    if let Expr::Identifier(ident) = expr {
-                                        Some(ident.value.clone())
-                                    } else {
-                                        None
-                                    }
-                                })
-                            }
-                            _ => None,
-                        };
-
-                        // Parse ORDER BY
-                        let order_by = query
-                            .order_by
-                            .as_ref()
-                            .and_then(|orders| orders.exprs.first())
-                            .and_then(|order| {
-                                if let Expr::Identifier(ident) = &order.expr {
-                                    Some((ident.value.clone(), !order.asc.unwrap_or(true)))
-                                } else {
-                                    None
-                                }
-                            });
-
-                        Ok(SQLCommand::SelectFrom {
-                            table: name.to_string(),
-                            page_size,
-                            page,
-                            group_by,
-                            order_by,
-                        })
-                    } else {
-                        Err(FerrousDBError::ParseError(
-                            "Unsupported FROM clause".to_string(),
-                        ))
-                    }
-                } else {
-                    Err(FerrousDBError::ParseError(
-                        "No FROM clause in SELECT statement".to_string(),
-                    ))
-                }
-            } else {
-                Err(FerrousDBError::ParseError(
-                    "Unsupported query type".to_string(),
-                ))
-            }
-        }
+        Statement::Query(query) => convert_query(query),
         Statement::Update {
             table,
             assignments,
@@ -151,45 +94,21 @@ pub fn parse_sql(sql: &str) -> Result<SQLCommand, FerrousDBError> {
             let mut update_assignments = HashMap::new();
 
             for assignment in assignments {
-                match &assignment.target {
-                    target => {
-                        if let Expr::Value(value) = &assignment.value {
-                            let column_name = target.to_string();
-                            match value {
-                                sqlparser::ast::Value::Number(n, _) => {
-                                    update_assignments
-                                        .insert(column_name, DataType::Integer(n.parse().unwrap()));
-                                }
-                                sqlparser::ast::Value::SingleQuotedString(s) => {
-                                    update_assignments
-                                        .insert(column_name, DataType::Text(s.clone()));
-                                }
-                                _ => {
-                                    return Err(FerrousDBError::ParseError(
-                                        "Unsupported value type".to_string(),
-                                    ))
-                                }
-                            }
-                        }
+                let column_name = assignment.target.to_string();
+                match convert_expr(&assignment.value)? {
+                    TypedExpr::Literal(value) => {
+                        update_assignments.insert(column_name, value);
+                    }
+                    _ => {
+                        return Err(FerrousDBError::ParseError(format!(
+                            "Unsupported UPDATE value '{}'",
+                            assignment.value
+                        )))
                     }
                 }
             }
 
-            let condition = selection.as_ref().and_then(|expr| {
-                if let Expr::BinaryOp { left, op, right } = expr {
-                    if let Expr::Identifier(id) = left.as_ref() {
-                        if let Expr::Value(value) = right.as_ref() {
-                            Some(format!("{}={}", id.value, value))
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            });
+            let condition = selection.as_ref().map(convert_expr).transpose()?;
 
             Ok(SQLCommand::Update {
                 table: table_name,
@@ -198,31 +117,19 @@ pub fn parse_sql(sql: &str) -> Result<SQLCommand, FerrousDBError> {
             })
         }
         Statement::Delete(delete) => {
-            let table_name = if let Some(using) = &delete.using {
-                if let Some(table) = using.first() {
+            let (FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from)) = &delete.from;
+            let table_name = match from.as_slice() {
+                [table] if delete.tables.is_empty() && delete.using.is_none() => {
                     table.relation.to_string()
-                } else {
-                    delete.tables[0].to_string()
                 }
-            } else {
-                delete.tables[0].to_string()
+                _ => {
+                    return Err(FerrousDBError::ParseError(
+                        "DELETE supports a single table".to_string(),
+                    ))
+                }
             };
 
-            let condition = delete.selection.as_ref().and_then(|expr| {
-                if let Expr::BinaryOp { left, op, right } = expr {
-                    if let Expr::Identifier(id) = left.as_ref() {
-                        if let Expr::Value(value) = right.as_ref() {
-                            Some(format!("{}={}", id.value, value))
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            });
+            let condition = delete.selection.as_ref().map(convert_expr).transpose()?;
 
             Ok(SQLCommand::DeleteFrom {
                 table: table_name,
@@ -245,8 +152,726 @@ pub fn parse_sql(sql: &str) -> Result<SQLCommand, FerrousDBError> {
                 columns: column_names,
             })
         }
+        Statement::StartTransaction { .. } => Ok(SQLCommand::Begin),
+        Statement::Commit { chain: false } => Ok(SQLCommand::Commit),
+        Statement::Rollback {
+            chain: false,
+            savepoint,
+        } => Ok(match savepoint {
+            Some(name) => SQLCommand::RollbackTo {
+                name: name.value.clone(),
+            },
+            None => SQLCommand::Rollback,
+        }),
+        Statement::Savepoint { name } => Ok(SQLCommand::Savepoint {
+            name: name.value.clone(),
+        }),
+        Statement::ReleaseSavepoint { name } => Ok(SQLCommand::Release {
+            name: name.value.clone(),
+        }),
         _ => Err(FerrousDBError::ParseError(
             "Unsupported SQL command".to_string(),
         )),
     }
 }
+
+/// Parses a standalone expression such as `age > 30 AND name = 'Bob'`.
+pub fn parse_expr(sql: &str) -> Result<TypedExpr, FerrousDBError> {
+    let expr = Parser::new(&GenericDialect {})
+        .try_with_sql(sql)
+        .and_then(|mut parser| {
+            let expr = parser.parse_expr()?;
+            parser.expect_token(&Token::EOF)?;
+            Ok(expr)
+        })
+        .map_err(|e| FerrousDBError::ParseError(e.to_string()))?;
+    convert_expr(&expr)
+}
+
+/// Converts an expression from the SQL parser into the typed [`TypedExpr`]
+/// the database evaluates.
+pub fn convert_expr(expr: &Expr) -> Result<TypedExpr, FerrousDBError> {
+    use sqlparser::ast::BinaryOperator as Op;
+
+    let boxed = |expr: &Expr| convert_expr(expr).map(Box::new);
+    Ok(match expr {
+        Expr::Identifier(ident) => TypedExpr::Column {
+            table: None,
+            name: ident.value.clone(),
+        },
+        Expr::CompoundIdentifier(idents) => match idents.as_slice() {
+            [table, column] => TypedExpr::Column {
+                table: Some(table.value.clone()),
+                name: column.value.clone(),
+            },
+            _ => return Err(unsupported_expr(expr)),
+        },
+        Expr::Value(Value::Null) => TypedExpr::Null,
+        Expr::Value(value) => TypedExpr::Literal(convert_value(value)?),
+        Expr::Nested(inner) => convert_expr(inner)?,
+        Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => TypedExpr::Not(boxed(expr)?),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr: inner,
+        } => match convert_expr(inner)? {
+            TypedExpr::Literal(DataType::Integer(n)) => TypedExpr::Literal(DataType::Integer(-n)),
+            _ => return Err(unsupported_expr(expr)),
+        },
+        Expr::BinaryOp { left, op, right } => {
+            let op = match op {
+                Op::Eq => BinaryOperator::Eq,
+                Op::NotEq => BinaryOperator::NotEq,
+                Op::Lt => BinaryOperator::Lt,
+                Op::LtEq => BinaryOperator::LtEq,
+                Op::Gt => BinaryOperator::Gt,
+                Op::GtEq => BinaryOperator::GtEq,
+                Op::And => BinaryOperator::And,
+                Op::Or => BinaryOperator::Or,
+                Op::Plus => BinaryOperator::Plus,
+                Op::Minus => BinaryOperator::Minus,
+                Op::Multiply => BinaryOperator::Multiply,
+                Op::Divide => BinaryOperator::Divide,
+                Op::Modulo => BinaryOperator::Modulo,
+                _ => return Err(unsupported_expr(expr)),
+            };
+            TypedExpr::binary(convert_expr(left)?, op, convert_expr(right)?)
+        }
+        Expr::IsNull(inner) => TypedExpr::IsNull {
+            expr: boxed(inner)?,
+            negated: false,
+        },
+        Expr::IsNotNull(inner) => TypedExpr::IsNull {
+            expr: boxed(inner)?,
+            negated: true,
+        },
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => TypedExpr::InList {
+            expr: boxed(expr)?,
+            list: list.iter().map(convert_expr).collect::<Result<_, _>>()?,
+            negated: *negated,
+        },
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => TypedExpr::Between {
+            expr: boxed(expr)?,
+            low: boxed(low)?,
+            high: boxed(high)?,
+            negated: *negated,
+        },
+        Expr::Like {
+            negated,
+            expr,
+            pattern,
+            escape_char: None,
+        } => TypedExpr::Like {
+            expr: boxed(expr)?,
+            pattern: boxed(pattern)?,
+            negated: *negated,
+        },
+        Expr::Function(function) => convert_function(expr, function)?,
+        Expr::Subquery(query) => TypedExpr::Subquery(Box::new(convert_query(query)?)),
+        Expr::InSubquery {
+            expr,
+            subquery,
+            negated,
+        } => TypedExpr::InSubquery {
+            expr: boxed(expr)?,
+            subquery: Box::new(convert_query(subquery)?),
+            negated: *negated,
+        },
+        Expr::Exists { subquery, negated } => TypedExpr::Exists {
+            subquery: Box::new(convert_query(subquery)?),
+            negated: *negated,
+        },
+        _ => return Err(unsupported_expr(expr)),
+    })
+}
+
+fn convert_function(
+    expr: &Expr,
+    function: &sqlparser::ast::Function,
+) -> Result<TypedExpr, FerrousDBError> {
+    let name = function.name.to_string();
+    let FunctionArguments::List(list) = &function.args else {
+        return Err(unsupported_expr(expr));
+    };
+    if !list.clauses.is_empty() || function.filter.is_some() {
+        return Err(unsupported_expr(expr));
+    }
+    if let Some(over) = &function.over {
+        return convert_window(expr, function, list, over);
+    }
+    let distinct = matches!(list.duplicate_treatment, Some(DuplicateTreatment::Distinct));
+
+    if let Some(func) = AggregateFunction::from_name(&name) {
+        let arg = match list.args.as_slice() {
+            [FunctionArg::Unnamed(FunctionArgExpr::Wildcard)]
+                if func == AggregateFunction::Count && !distinct =>
+            {
+                None
+            }
+            [FunctionArg::Unnamed(FunctionArgExpr::Expr(arg))] => {
+                let arg = convert_expr(arg)?;
+                if arg.contains_aggregate() {
+                    return Err(FerrousDBError::ParseError(format!(
+                        "Aggregate functions can't be nested in '{}'",
+                        expr
+                    )));
+                }
+                Some(Box::new(arg))
+            }
+            _ => {
+                return Err(FerrousDBError::ParseError(format!(
+                    "Wrong arguments for {}()",
+                    func.name()
+                )))
+            }
+        };
+        return Ok(TypedExpr::Aggregate {
+            func,
+            arg,
+            distinct,
+        });
+    }
+
+    let func = Function::from_name(&name)
+        .ok_or_else(|| FerrousDBError::ParseError(format!("Unknown function '{}'", name)))?;
+    if list.duplicate_treatment.is_some() {
+        return Err(unsupported_expr(expr));
+    }
+    let mut args = Vec::new();
+    for arg in &list.args {
+        match arg {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(arg)) => args.push(convert_expr(arg)?),
+            _ => return Err(unsupported_expr(expr)),
+        }
+    }
+    if !func.accepts(args.len()) {
+        return Err(FerrousDBError::ParseError(format!(
+            "Wrong number of arguments for {}()",
+            func.name()
+        )));
+    }
+    Ok(TypedExpr::Function { func, args })
+}
+
+/// Converts a call to a window function, `func(args) OVER (…)`.
+fn convert_window(
+    expr: &Expr,
+    function: &sqlparser::ast::Function,
+    list: &FunctionArgumentList,
+    over: &WindowType,
+) -> Result<TypedExpr, FerrousDBError> {
+    let name = function.name.to_string();
+    let func = WindowFunction::from_name(&name)
+        .ok_or_else(|| FerrousDBError::ParseError(format!("Unknown window function '{}'", name)))?;
+    if list.duplicate_treatment.is_some() || function.null_treatment.is_some() {
+        return Err(unsupported_expr(expr));
+    }
+    let mut args = Vec::new();
+    for arg in &list.args {
+        match arg {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(arg)) => args.push(convert_expr(arg)?),
+            // count(*)
+            FunctionArg::Unnamed(FunctionArgExpr::Wildcard)
+                if func == WindowFunction::Aggregate(AggregateFunction::Count)
+                    && list.args.len() == 1 => {}
+            _ => return Err(unsupported_expr(expr)),
+        }
+    }
+    if !func.accepts(args.len()) {
+        return Err(FerrousDBError::ParseError(format!(
+            "Wrong number of arguments for {}()",
+            func.name()
+        )));
+    }
+
+    let WindowType::WindowSpec(spec) = over else {
+        return Err(FerrousDBError::ParseError(format!(
+            "Unsupported window '{}'",
+            over
+        )));
+    };
+    if spec.window_name.is_some() {
+        return Err(FerrousDBError::ParseError(format!(
+            "Unsupported window '{}'",
+            over
+        )));
+    }
+    Ok(TypedExpr::Window {
+        func,
+        args,
+        over: Box::new(WindowSpec {
+            partition_by: spec
+                .partition_by
+                .iter()
+                .map(convert_expr)
+                .collect::<Result<_, _>>()?,
+            order_by: spec
+                .order_by
+                .iter()
+                .map(convert_order_by)
+                .collect::<Result<_, _>>()?,
+            frame: convert_frame(spec)?,
+        }),
+    })
+}
+
+/// Converts the frame of a window, if it has one. Its start mustn't come
+/// after its end, and `RANGE` frames only take unbounded bounds and
+/// `CURRENT ROW`.
+fn convert_frame(spec: &sqlparser::ast::WindowSpec) -> Result<Option<WindowFrame>, FerrousDBError> {
+    let Some(frame) = &spec.window_frame else {
+        return Ok(None);
+    };
+    let unsupported =
+        || FerrousDBError::ParseError(format!("Unsupported window frame in '({})'", spec));
+    let units = match frame.units {
+        WindowFrameUnits::Rows => FrameUnits::Rows,
+        WindowFrameUnits::Range => FrameUnits::Range,
+        WindowFrameUnits::Groups => return Err(unsupported()),
+    };
+    let start = convert_frame_bound(&frame.start_bound)?;
+    let end = match &frame.end_bound {
+        Some(bound) => convert_frame_bound(bound)?,
+        None => FrameBound::CurrentRow,
+    };
+
+    // Where a bound lies, relative to the current row
+    let order = |bound: FrameBound| match bound {
+        FrameBound::UnboundedPreceding => 0,
+        FrameBound::Preceding(_) => 1,
+        FrameBound::CurrentRow => 2,
+        FrameBound::Following(_) => 3,
+        FrameBound::UnboundedFollowing => 4,
+    };
+    let offset = |bound: FrameBound| matches!(order(bound), 1 | 3);
+    if order(start) > order(end)
+        || start == FrameBound::UnboundedFollowing
+        || end == FrameBound::UnboundedPreceding
+        || (units == FrameUnits::Range && (offset(start) || offset(end)))
+    {
+        return Err(unsupported());
+    }
+    Ok(Some(WindowFrame { units, start, end }))
+}
+
+fn convert_frame_bound(bound: &WindowFrameBound) -> Result<FrameBound, FerrousDBError> {
+    Ok(match bound {
+        WindowFrameBound::CurrentRow => FrameBound::CurrentRow,
+        WindowFrameBound::Preceding(None) => FrameBound::UnboundedPreceding,
+        WindowFrameBound::Preceding(Some(offset)) => {
+            FrameBound::Preceding(convert_count(offset, "PRECEDING")?)
+        }
+        WindowFrameBound::Following(None) => FrameBound::UnboundedFollowing,
+        WindowFrameBound::Following(Some(offset)) => {
+            FrameBound::Following(convert_count(offset, "FOLLOWING")?)
+        }
+    })
+}
+
+/// Converts a query, at the top level or nested in an expression or `FROM`.
+fn convert_query(query: &Query) -> Result<SQLCommand, FerrousDBError> {
+    if let Some(with) = &query.with {
+        let ctes = with
+            .cte_tables
+            .iter()
+            .map(convert_cte)
+            .collect::<Result<_, _>>()?;
+        let body = Query {
+            with: None,
+            ..query.clone()
+        };
+        return Ok(SQLCommand::With {
+            recursive: with.recursive,
+            ctes,
+            query: Box::new(convert_query(&body)?),
+        });
+    }
+    let mut command = convert_set_expr(&query.body)?;
+    let limit = query
+        .limit
+        .as_ref()
+        .map(|limit| convert_count(limit, "LIMIT"))
+        .transpose()?;
+    let offset = match &query.offset {
+        Some(Offset { value, .. }) => convert_count(value, "OFFSET")?,
+        None => 0,
+    };
+
+    // Parse ORDER BY
+    let order_by = match &query.order_by {
+        Some(order_by) if order_by.interpolate.is_none() => order_by
+            .exprs
+            .iter()
+            .map(convert_order_by)
+            .collect::<Result<_, _>>()?,
+        Some(_) => {
+            return Err(FerrousDBError::ParseError(
+                "Unsupported ORDER BY clause".to_string(),
+            ))
+        }
+        None => Vec::new(),
+    };
+    if order_by.is_empty() && limit.is_none() && offset == 0 {
+        return Ok(command);
+    }
+
+    // They apply to the whole body, unless it is a parenthesized query
+    // sorting or limiting its rows itself
+    match &mut command {
+        SQLCommand::SelectFrom {
+            order_by: body_order_by,
+            limit: body_limit,
+            offset: body_offset,
+            ..
+        }
+        | SQLCommand::SetOperation {
+            order_by: body_order_by,
+            limit: body_limit,
+            offset: body_offset,
+            ..
+        } if body_order_by.is_empty() && body_limit.is_none() && *body_offset == 0 => {
+            *body_order_by = order_by;
+            *body_limit = limit;
+            *body_offset = offset;
+            Ok(command)
+        }
+        _ => Err(FerrousDBError::ParseError(format!(
+            "Unsupported query '{}'",
+            query
+        ))),
+    }
+}
+
+fn convert_cte(cte: &sqlparser::ast::Cte) -> Result<Cte, FerrousDBError> {
+    if cte.from.is_some() || cte.materialized.is_some() {
+        return Err(FerrousDBError::ParseError(format!(
+            "Unsupported WITH query '{}'",
+            cte
+        )));
+    }
+    Ok(Cte {
+        name: cte.alias.name.value.clone(),
+        columns: cte
+            .alias
+            .columns
+            .iter()
+            .map(|column| column.value.clone())
+            .collect(),
+        query: convert_query(&cte.query)?,
+    })
+}
+
+/// Converts the rows of an `INSERT`: those of `VALUES`, or of any other query.
+fn convert_insert_source(query: &Query) -> Result<InsertSource, FerrousDBError> {
+    let values = match &*query.body {
+        SetExpr::Values(values)
+            if query.with.is_none()
+                && query.order_by.is_none()
+                && query.limit.is_none()
+                && query.offset.is_none() =>
+        {
+            values
+        }
+        _ => return Ok(InsertSource::Query(Box::new(convert_query(query)?))),
+    };
+    if values.rows.is_empty() {
+        return Err(FerrousDBError::ParseError(
+            "No values provided for insert".to_string(),
+        ));
+    }
+    let mut rows = Vec::with_capacity(values.rows.len());
+    for row in &values.rows {
+        let mut values = Vec::with_capacity(row.len());
+        for val in row {
+            values.push(match val {
+                Expr::Identifier(ident)
+                    if ident.quote_style.is_none()
+                        && ident.value.eq_ignore_ascii_case("DEFAULT") =>
+                {
+                    InsertValue::Default
+                }
+                _ => match convert_expr(val)? {
+                    TypedExpr::Literal(value) => InsertValue::Value(value),
+                    TypedExpr::Null => InsertValue::Null,
+                    _ => {
+                        return Err(FerrousDBError::ParseError(format!(
+                            "Unsupported INSERT value '{}'",
+                            val
+                        )))
+                    }
+                },
+            });
+        }
+        rows.push(values);
+    }
+    Ok(InsertSource::Values(rows))
+}
+
+/// Converts the body of a query: a `SELECT`, or a set operation combining
+/// the rows of two queries.
+fn convert_set_expr(body: &SetExpr) -> Result<SQLCommand, FerrousDBError> {
+    match body {
+        SetExpr::Select(select) => convert_select(select),
+        SetExpr::Query(query) => convert_query(query),
+        SetExpr::SetOperation {
+            op,
+            set_quantifier,
+            left,
+            right,
+        } => {
+            let all = match set_quantifier {
+                SetQuantifier::All => true,
+                SetQuantifier::Distinct | SetQuantifier::None => false,
+                _ => {
+                    return Err(FerrousDBError::ParseError(format!(
+                        "Unsupported set operation '{} {}'",
+                        op, set_quantifier
+                    )))
+                }
+            };
+            Ok(SQLCommand::SetOperation {
+                op: match op {
+                    sqlparser::ast::SetOperator::Union => SetOperator::Union,
+                    sqlparser::ast::SetOperator::Intersect => SetOperator::Intersect,
+                    sqlparser::ast::SetOperator::Except => SetOperator::Except,
+                },
+                all,
+                left: Box::new(convert_set_expr(left)?),
+                right: Box::new(convert_set_expr(right)?),
+                order_by: Vec::new(),
+                limit: None,
+                offset: 0,
+            })
+        }
+        _ => Err(FerrousDBError::ParseError(
+            "Unsupported query type".to_string(),
+        )),
+    }
+}
+
+/// Converts a `SELECT`, leaving the `ORDER BY` and `LIMIT` of its query to
+/// [`convert_query`].
+fn convert_select(select: &Select) -> Result<SQLCommand, FerrousDBError> {
+    if select.from.is_empty() {
+        return Err(FerrousDBError::ParseError(
+            "No FROM clause in SELECT statement".to_string(),
+        ));
+    }
+    let (table, joins) = convert_from(&select.from)?;
+
+    // Parse GROUP BY and HAVING
+    let group_by = match &select.group_by {
+        GroupByExpr::Expressions(exprs, modifiers) if modifiers.is_empty() => {
+            exprs.iter().map(convert_expr).collect::<Result<_, _>>()?
+        }
+        _ => {
+            return Err(FerrousDBError::ParseError(
+                "Unsupported GROUP BY clause".to_string(),
+            ))
+        }
+    };
+    let having = select.having.as_ref().map(convert_expr).transpose()?;
+
+    let projection = select
+        .projection
+        .iter()
+        .map(convert_select_item)
+        .collect::<Result<_, _>>()?;
+    let filter = select.selection.as_ref().map(convert_expr).transpose()?;
+    let distinct = match &select.distinct {
+        None => None,
+        Some(sqlparser::ast::Distinct::Distinct) => Some(Distinct::All),
+        Some(sqlparser::ast::Distinct::On(exprs)) => Some(Distinct::On(
+            exprs.iter().map(convert_expr).collect::<Result<_, _>>()?,
+        )),
+    };
+
+    Ok(SQLCommand::SelectFrom {
+        table,
+        joins,
+        projection,
+        distinct,
+        limit: None,
+        offset: 0,
+        filter,
+        group_by,
+        having,
+        order_by: Vec::new(),
+    })
+}
+
+fn convert_select_item(item: &sqlparser::ast::SelectItem) -> Result<SelectItem, FerrousDBError> {
+    use sqlparser::ast::SelectItem as Item;
+
+    Ok(match item {
+        Item::Wildcard(_) => SelectItem::Wildcard(None),
+        Item::QualifiedWildcard(name, _) => SelectItem::Wildcard(Some(name.to_string())),
+        Item::UnnamedExpr(expr) => SelectItem::Expr {
+            expr: convert_expr(expr)?,
+            alias: None,
+        },
+        Item::ExprWithAlias { expr, alias } => SelectItem::Expr {
+            expr: convert_expr(expr)?,
+            alias: Some(alias.value.clone()),
+        },
+    })
+}
+
+/// Converts the tables of `FROM`, the first one and the ones joined to it.
+/// Tables separated by commas are cross joined.
+fn convert_from(from: &[TableWithJoins]) -> Result<(TableRef, Vec<Join>), FerrousDBError> {
+    let Some(first) = from.first() else {
+        return Err(FerrousDBError::ParseError(
+            "No FROM clause in SELECT statement".to_string(),
+        ));
+    };
+    let table = convert_table(&first.relation)?;
+    let mut joins = Vec::new();
+    for (position, item) in from.iter().enumerate() {
+        if position > 0 {
+            joins.push(Join {
+                kind: JoinKind::Cross,
+                table: convert_table(&item.relation)?,
+                condition: None,
+            });
+        }
+        for join in &item.joins {
+            let (kind, constraint) = match &join.join_operator {
+                JoinOperator::Inner(constraint) => (JoinKind::Inner, Some(constraint)),
+                JoinOperator::LeftOuter(constraint) => (JoinKind::Left, Some(constraint)),
+                JoinOperator::RightOuter(constraint) => (JoinKind::Right, Some(constraint)),
+                JoinOperator::FullOuter(constraint) => (JoinKind::Full, Some(constraint)),
+                JoinOperator::CrossJoin => (JoinKind::Cross, None),
+                _ => {
+                    return Err(FerrousDBError::ParseError(format!(
+                        "Unsupported join '{}'",
+                        join
+                    )))
+                }
+            };
+            let condition = match constraint {
+                Some(JoinConstraint::On(expr)) => Some(convert_expr(expr)?),
+                Some(JoinConstraint::None) | None => None,
+                Some(_) => {
+                    return Err(FerrousDBError::ParseError(format!(
+                        "Unsupported join condition in '{}', use ON",
+                        join
+                    )))
+                }
+            };
+            // A plain JOIN without ON pairs every row, like a cross join
+            let kind = match (kind, &condition) {
+                (JoinKind::Inner, None) => JoinKind::Cross,
+                (JoinKind::Cross, _) | (_, Some(_)) => kind,
+                (_, None) => {
+                    return Err(FerrousDBError::ParseError(format!(
+                        "Missing ON condition in '{}'",
+                        join
+                    )))
+                }
+            };
+            joins.push(Join {
+                kind,
+                table: convert_table(&join.relation)?,
+                condition,
+            });
+        }
+    }
+    Ok((table, joins))
+}
+
+fn convert_table(relation: &TableFactor) -> Result<TableRef, FerrousDBError> {
+    match relation {
+        TableFactor::Table {
+            name, alias, args, ..
+        } if args.is_none() => {
+            if let Some(alias) = alias.as_ref().filter(|alias| !alias.columns.is_empty()) {
+                return Err(FerrousDBError::ParseError(format!(
+                    "Unsupported table alias '{}'",
+                    alias
+                )));
+            }
+            Ok(TableRef {
+                name: name.to_string(),
+                alias: alias.as_ref().map(|alias| alias.name.value.clone()),
+                subquery: None,
+            })
+        }
+        TableFactor::Derived {
+            lateral: false,
+            subquery,
+            alias,
+        } => match alias {
+            Some(alias) if alias.columns.is_empty() => Ok(TableRef {
+                name: alias.name.value.clone(),
+                alias: None,
+                subquery: Some(Box::new(convert_query(subquery)?)),
+            }),
+            Some(alias) => Err(FerrousDBError::ParseError(format!(
+                "Unsupported table alias '{}'",
+                alias
+            ))),
+            None => Err(FerrousDBError::ParseError(format!(
+                "Subquery '{}' in FROM must have an alias",
+                subquery
+            ))),
+        },
+        _ => Err(FerrousDBError::ParseError(format!(
+            "Unsupported FROM clause '{}'",
+            relation
+        ))),
+    }
+}
+
+fn convert_order_by(order: &OrderByExpr) -> Result<OrderByItem, FerrousDBError> {
+    if order.with_fill.is_some() {
+        return Err(FerrousDBError::ParseError(format!(
+            "Unsupported ORDER BY key '{}'",
+            order
+        )));
+    }
+    let mut item = OrderByItem::new(convert_expr(&order.expr)?, order.asc.unwrap_or(true));
+    if let Some(nulls_first) = order.nulls_first {
+        item.nulls_first = nulls_first;
+    }
+    Ok(item)
+}
+
+/// Converts the row count of a `LIMIT` or `OFFSET` clause.
+fn convert_count(expr: &Expr, clause: &str) -> Result<usize, FerrousDBError> {
+    match expr {
+        Expr::Value(Value::Number(n, _)) => n.parse::<usize>().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| FerrousDBError::ParseError(format!("Unsupported {} '{}'", clause, expr)))
+}
+
+fn convert_value(value: &Value) -> Result<DataType, FerrousDBError> {
+    match value {
+        Value::Number(n, _) => n
+            .parse::<i64>()
+            .map(DataType::Integer)
+            .map_err(|_| FerrousDBError::ParseError(format!("Unsupported number '{}'", n))),
+        Value::SingleQuotedString(s) => Ok(DataType::Text(s.clone())),
+        Value::Boolean(b) => Ok(DataType::Boolean(*b)),
+        _ => Err(FerrousDBError::ParseError(format!(
+            "Unsupported value '{}'",
+            value
+        ))),
+    }
+}
+
+fn unsupported_expr(expr: &Expr) -> FerrousDBError {
+    FerrousDBError::ParseError(format!("Unsupported expression '{}'", expr))
+}
@@ -0,0 +1,862 @@
+//! Evaluates the select list, grouping and aggregates of a `SELECT` over the
+//! rows it reads.
+//!
+//! A [`Scope`] describes the columns of those rows and binds the column
+//! references of expressions to them.
+
+use std::{
+    cell::Cell,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use super::{
+    error_handling::FerrousDBError,
+    evaluator::{self, Subqueries},
+    expr::{
+        AggregateFunction, Distinct, Expr, Function, OrderByItem, SelectItem, SetOperator,
+        WindowFunction,
+    },
+    result_set::{ResultColumn, ResultSet},
+    table::Table,
+    window,
+};
+use crate::DataType;
+
+/// A column of the rows a query reads.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SourceColumn {
+    /// The name or alias of the column's table.
+    pub qualifier: String,
+    pub name: String,
+    pub data_type: String,
+}
+
+/// The columns of the rows a query reads, from a single table or from
+/// several joined ones.
+///
+/// Rows of a single table map column names to values. Joined rows map
+/// `qualifier.name` instead, so that columns of the same name in different
+/// tables don't collide.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Scope {
+    columns: Vec<SourceColumn>,
+    qualified: bool,
+    /// The row of the enclosing query, for the scope of a correlated
+    /// subquery.
+    outer: Option<Rc<Outer>>,
+}
+
+/// A row of an enclosing query, which a subquery runs for. The subquery's
+/// columns hide those of the row, and the ones it reads become constants.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Outer {
+    scope: Scope,
+    row: HashMap<String, DataType>,
+    /// Whether the subquery read a column of the row.
+    read: Cell<bool>,
+}
+
+/// A column of the result and the bound expression computing it.
+struct Output {
+    name: String,
+    expr: Expr,
+}
+
+impl Scope {
+    /// The columns of `table`, qualified with `qualifier`.
+    pub(crate) fn table(table: &Table, qualifier: &str) -> Self {
+        Scope {
+            columns: table
+                .schema
+                .iter()
+                .map(|column| SourceColumn {
+                    qualifier: qualifier.to_string(),
+                    name: column.name.clone(),
+                    data_type: column.data_type.clone(),
+                })
+                .collect(),
+            qualified: false,
+            outer: None,
+        }
+    }
+
+    /// The same columns, in a subquery running for `outer`.
+    pub(crate) fn within(mut self, outer: Option<Rc<Outer>>) -> Self {
+        self.outer = outer;
+        self
+    }
+
+    /// The same columns, for rows that qualify their names.
+    pub(crate) fn qualified(mut self) -> Self {
+        self.qualified = true;
+        self
+    }
+
+    /// The columns of both scopes, for joined rows.
+    pub(crate) fn join(mut self, other: Scope) -> Result<Self, FerrousDBError> {
+        if let Some(column) = other
+            .columns
+            .iter()
+            .find(|column| self.columns.iter().any(|c| c.qualifier == column.qualifier))
+        {
+            return Err(FerrousDBError::InvalidExpression(format!(
+                "table name '{}' specified more than once",
+                column.qualifier
+            )));
+        }
+        self.columns.extend(other.columns);
+        self.qualified = true;
+        Ok(self)
+    }
+
+    pub(crate) fn columns(&self) -> &[SourceColumn] {
+        &self.columns
+    }
+
+    /// The name `column` has in rows.
+    pub(crate) fn key(&self, column: &SourceColumn) -> String {
+        if self.qualified {
+            format!("{}.{}", column.qualifier, column.name)
+        } else {
+            column.name.clone()
+        }
+    }
+
+    /// Resolves the column references of `expr` to columns of the scope,
+    /// qualified exactly as rows name them.
+    pub(crate) fn bind(&self, expr: &Expr) -> Result<Expr, FerrousDBError> {
+        let mut expr = expr.clone();
+        self.bind_in_place(&mut expr)?;
+        Ok(expr)
+    }
+
+    /// Binds a condition of `WHERE`, `ON` or `CHECK`, where aggregates are
+    /// not allowed.
+    pub(crate) fn bind_condition(&self, expr: &Expr) -> Result<Expr, FerrousDBError> {
+        if expr.contains_aggregate() {
+            return Err(FerrousDBError::InvalidExpression(format!(
+                "aggregate functions are not allowed in '{}'",
+                expr
+            )));
+        }
+        self.bind(expr)
+    }
+
+    fn bind_in_place(&self, expr: &mut Expr) -> Result<(), FerrousDBError> {
+        if let Expr::Column { table, name } = expr {
+            let column = match (self.resolve(table.as_deref(), name), &self.outer) {
+                (Err(FerrousDBError::ColumnNotFound(missing)), Some(outer)) => {
+                    let value =
+                        outer
+                            .value(table.as_deref(), name)
+                            .map_err(|error| match error {
+                                FerrousDBError::ColumnNotFound(_) => {
+                                    FerrousDBError::ColumnNotFound(missing)
+                                }
+                                error => error,
+                            })?;
+                    *expr = value;
+                    return Ok(());
+                }
+                (column, _) => column?,
+            };
+            *table = self.qualified.then(|| column.qualifier.clone());
+            return Ok(());
+        }
+        expr.children_mut()
+            .into_iter()
+            .try_for_each(|child| self.bind_in_place(child))
+    }
+
+    fn resolve(
+        &self,
+        qualifier: Option<&str>,
+        name: &str,
+    ) -> Result<&SourceColumn, FerrousDBError> {
+        let mut matching = self.columns.iter().filter(|column| {
+            column.name == name && qualifier.is_none_or(|q| column.qualifier == q)
+        });
+        let column = matching.next().ok_or_else(|| {
+            FerrousDBError::ColumnNotFound(match qualifier {
+                Some(q) => format!("{}.{}", q, name),
+                None => name.to_string(),
+            })
+        })?;
+        if matching.next().is_some() {
+            return Err(FerrousDBError::InvalidExpression(format!(
+                "column reference '{}' is ambiguous",
+                name
+            )));
+        }
+        Ok(column)
+    }
+
+    /// The type of the values a bound expression gives.
+    pub(crate) fn expr_type(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Column { table, name } => self
+                .resolve(table.as_deref(), name)
+                .map_or("NULL".to_string(), |column| column.data_type.clone()),
+            Expr::Literal(value) => value.get_type().to_string(),
+            Expr::Null => "NULL".to_string(),
+            Expr::Binary { op, .. } if op.is_arithmetic() => "INTEGER".to_string(),
+            Expr::Function { func, args } => match func {
+                Function::Upper | Function::Lower => "TEXT".to_string(),
+                Function::Length | Function::Abs => "INTEGER".to_string(),
+                Function::Coalesce => args
+                    .iter()
+                    .map(|arg| self.expr_type(arg))
+                    .find(|data_type| data_type != "NULL")
+                    .unwrap_or_else(|| "NULL".to_string()),
+            },
+            Expr::Aggregate { func, arg, .. } => match (func, arg) {
+                (AggregateFunction::Min | AggregateFunction::Max, Some(arg)) => self.expr_type(arg),
+                _ => "INTEGER".to_string(),
+            },
+            Expr::Window { func, args, .. } => match (func, args.first()) {
+                (
+                    WindowFunction::Lag
+                    | WindowFunction::Lead
+                    | WindowFunction::FirstValue
+                    | WindowFunction::LastValue
+                    | WindowFunction::Aggregate(AggregateFunction::Min | AggregateFunction::Max),
+                    Some(arg),
+                ) => self.expr_type(arg),
+                _ => "INTEGER".to_string(),
+            },
+            _ => "BOOLEAN".to_string(),
+        }
+    }
+}
+
+impl Outer {
+    pub(crate) fn new(scope: Scope, row: HashMap<String, DataType>) -> Self {
+        Outer {
+            scope,
+            row,
+            read: Cell::new(false),
+        }
+    }
+
+    /// Whether the subquery depends on the row, rather than giving the same
+    /// result for every row of the enclosing query.
+    pub(crate) fn was_read(&self) -> bool {
+        self.read.get()
+    }
+
+    /// The value of a column of the row, or of a query further out.
+    fn value(&self, qualifier: Option<&str>, name: &str) -> Result<Expr, FerrousDBError> {
+        let column = self.scope.bind(&Expr::Column {
+            table: qualifier.map(str::to_string),
+            name: name.to_string(),
+        })?;
+        self.read.set(true);
+        Ok(match evaluator::evaluate(&column, &self.row)? {
+            Some(value) => Expr::Literal(value),
+            None => Expr::Null,
+        })
+    }
+}
+
+/// The rows of a group, along with one of them giving the values of the
+/// grouped columns: any row of the group, as they are the same for all.
+type Group<'a> = (
+    Vec<&'a HashMap<String, DataType>>,
+    &'a HashMap<String, DataType>,
+);
+
+/// The clauses of a `SELECT` computed from the rows left after `WHERE`.
+pub(crate) struct Clauses<'a> {
+    pub projection: &'a [SelectItem],
+    pub distinct: Option<&'a Distinct>,
+    pub group_by: &'a [Expr],
+    pub having: Option<&'a Expr>,
+    pub order_by: &'a [OrderByItem],
+}
+
+/// Computes the result of a `SELECT` from the rows left after `WHERE`:
+/// groups them if needed, computes window functions, evaluates the select
+/// list, sorts the result and leaves out duplicates for `DISTINCT`.
+pub(crate) fn select(
+    scope: &Scope,
+    mut rows: Vec<&HashMap<String, DataType>>,
+    clauses: &Clauses,
+    subqueries: &dyn Subqueries,
+) -> Result<ResultSet, FerrousDBError> {
+    if clauses.is_aggregate() {
+        return aggregate(scope, &rows, clauses, subqueries);
+    }
+    let outputs = outputs(scope, clauses.projection)?;
+    let order_exprs = order_by_exprs(scope, &outputs, clauses.order_by)?;
+    let distinct_exprs = distinct_exprs(scope, &outputs, clauses.distinct, &order_exprs)?;
+    let exprs = outputs.iter().map(|output| &output.expr);
+    let windows = window::windows(exprs.chain(&order_exprs).chain(&distinct_exprs))?;
+    let window_values = window::compute(&windows, rows.len(), |row, expr| {
+        evaluator::evaluate_with(expr, rows[row], subqueries)
+    })?;
+    // Evaluates an expression for a row, given the values of the window
+    // functions for it
+    let evaluate = |expr: &Expr, row, values: &[Option<DataType>]| {
+        let expr = window::substitute(expr, &windows, values);
+        evaluator::evaluate_with(&expr, row, subqueries)
+    };
+
+    let mut rows: Vec<_> = rows.drain(..).zip(window_values).collect();
+    let mut keys = Vec::with_capacity(rows.len());
+    for (row, values) in &rows {
+        keys.push(
+            order_exprs
+                .iter()
+                .map(|expr| evaluate(expr, row, values))
+                .collect::<Result<Vec<_>, _>>()?,
+        );
+    }
+    sort_by_keys(&mut rows, keys, clauses.order_by);
+
+    let mut result = ResultSet {
+        columns: result_columns(scope, &outputs),
+        rows: Vec::with_capacity(rows.len()),
+    };
+    let mut distinct_keys = Vec::new();
+    for (row, values) in rows {
+        result.rows.push(
+            outputs
+                .iter()
+                .map(|output| evaluate(&output.expr, row, &values))
+                .collect::<Result<_, _>>()?,
+        );
+        if !distinct_exprs.is_empty() {
+            distinct_keys.push(
+                distinct_exprs
+                    .iter()
+                    .map(|expr| evaluate(expr, row, &values))
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+    }
+    retain_distinct(&mut result.rows, clauses.distinct, distinct_keys);
+    Ok(result)
+}
+
+/// Combines the results of the two sides of a set operation, then sorts
+/// the rows by `order_by`, whose keys name or number columns of the result.
+///
+/// Rows are compared as a whole, NULLs being equal to each other. With
+/// `all`, a row returned `n` times by the left side and `m` times by the
+/// right one comes out `n + m` times for `UNION`, `min(n, m)` times for
+/// `INTERSECT` and `n - m` times for `EXCEPT`.
+pub(crate) fn combine(
+    op: SetOperator,
+    all: bool,
+    left: ResultSet,
+    right: ResultSet,
+    order_by: &[OrderByItem],
+) -> Result<ResultSet, FerrousDBError> {
+    let columns = combine_columns(op, left.columns, &right.columns)?;
+    let mut counts: HashMap<&[Option<DataType>], usize> = HashMap::new();
+    if op != SetOperator::Union {
+        for row in &right.rows {
+            *counts.entry(row).or_default() += 1;
+        }
+    }
+    let appended: &[Vec<Option<DataType>>] = match op {
+        SetOperator::Union => &right.rows,
+        _ => &[],
+    };
+    let mut rows = Vec::new();
+    let mut seen = HashSet::new();
+    for row in left.rows.iter().chain(appended) {
+        let keep = match op {
+            SetOperator::Union => true,
+            _ => {
+                let found = match counts.get_mut(row.as_slice()) {
+                    Some(count) if *count > 0 => {
+                        // With ALL, each row of the right side matches a
+                        // single row of the left one
+                        if all {
+                            *count -= 1;
+                        }
+                        true
+                    }
+                    _ => false,
+                };
+                found == (op == SetOperator::Intersect)
+            }
+        };
+        if keep && (all || seen.insert(row)) {
+            rows.push(row.clone());
+        }
+    }
+
+    let mut positions = Vec::with_capacity(order_by.len());
+    for order in order_by {
+        positions.push(result_position(&columns, &order.expr)?);
+    }
+    let keys = rows
+        .iter()
+        .map(|row| positions.iter().map(|&i| row[i].clone()).collect())
+        .collect();
+    sort_by_keys(&mut rows, keys, order_by);
+    Ok(ResultSet { columns, rows })
+}
+
+/// The columns of a set operation, named after those of the left side. A
+/// column only holding NULLs on the left takes its type from the right.
+fn combine_columns(
+    op: SetOperator,
+    mut columns: Vec<ResultColumn>,
+    right: &[ResultColumn],
+) -> Result<Vec<ResultColumn>, FerrousDBError> {
+    if columns.len() != right.len() {
+        return Err(FerrousDBError::InvalidExpression(format!(
+            "each {} query must have the same number of columns, not {} and {}",
+            op,
+            columns.len(),
+            right.len()
+        )));
+    }
+    for (column, other) in columns.iter_mut().zip(right) {
+        if column.data_type == "NULL" {
+            column.data_type = other.data_type.clone();
+        } else if other.data_type != "NULL"
+            && !column.data_type.eq_ignore_ascii_case(&other.data_type)
+        {
+            return Err(FerrousDBError::InvalidExpression(format!(
+                "{} types {} and {} of column '{}' cannot be matched",
+                op, column.data_type, other.data_type, column.name
+            )));
+        }
+    }
+    Ok(columns)
+}
+
+/// Computes a recursive query, `seed UNION [ALL] step`, to a fixpoint.
+/// `step` gives the rows of the recursive term for the rows found by the
+/// last iteration, and the query stops when it finds none. Without `all`,
+/// rows found before are left out.
+///
+/// Fails once `step` has run `max_iterations` times without reaching the
+/// fixpoint.
+pub(crate) fn fixpoint(
+    name: &str,
+    all: bool,
+    seed: ResultSet,
+    max_iterations: usize,
+    mut step: impl FnMut(ResultSet) -> Result<ResultSet, FerrousDBError>,
+) -> Result<ResultSet, FerrousDBError> {
+    let mut seen = HashSet::new();
+    let mut found = seed.rows;
+    found.retain(|row| all || seen.insert(row.clone()));
+    let mut result = ResultSet {
+        columns: seed.columns,
+        rows: Vec::new(),
+    };
+    let mut iterations = 0;
+    while !found.is_empty() {
+        if iterations == max_iterations {
+            return Err(FerrousDBError::RecursionLimit(format!(
+                "recursive query '{}' did not finish within {} iterations",
+                name, max_iterations
+            )));
+        }
+        iterations += 1;
+        result.rows.extend(found.iter().cloned());
+        let next = step(ResultSet {
+            columns: result.columns.clone(),
+            rows: found,
+        })?;
+        result.columns = combine_columns(SetOperator::Union, result.columns, &next.columns)?;
+        found = next.rows;
+        found.retain(|row| all || seen.insert(row.clone()));
+    }
+    Ok(result)
+}
+
+/// The position of the result column a key of `ORDER BY` names or numbers.
+fn result_position(columns: &[ResultColumn], expr: &Expr) -> Result<usize, FerrousDBError> {
+    match expr {
+        Expr::Literal(DataType::Integer(position)) => usize::try_from(*position)
+            .ok()
+            .and_then(|position| position.checked_sub(1))
+            .filter(|&position| position < columns.len())
+            .ok_or_else(|| {
+                FerrousDBError::InvalidExpression(format!(
+                    "ORDER BY position {} is not in the select list",
+                    position
+                ))
+            }),
+        Expr::Column { table: None, name } => columns
+            .iter()
+            .position(|column| column.name == *name)
+            .ok_or_else(|| FerrousDBError::ColumnNotFound(name.clone())),
+        _ => Err(FerrousDBError::InvalidExpression(format!(
+            "ORDER BY '{}' must be a column of the combined result",
+            expr
+        ))),
+    }
+}
+
+/// Skips the first `offset` items, then keeps at most `limit` of the rest.
+pub(crate) fn limit<T>(items: &mut Vec<T>, limit: Option<usize>, offset: usize) {
+    items.drain(..offset.min(items.len()));
+    if let Some(limit) = limit {
+        items.truncate(limit);
+    }
+}
+
+impl Clauses<'_> {
+    /// Whether the `SELECT` computes one row per group rather than one per
+    /// row.
+    fn is_aggregate(&self) -> bool {
+        !self.group_by.is_empty()
+            || self.having.is_some()
+            || self.projection.iter().any(|item| match item {
+                SelectItem::Expr { expr, .. } => expr.contains_aggregate(),
+                SelectItem::Wildcard(_) => false,
+            })
+            || self
+                .order_by
+                .iter()
+                .any(|order| order.expr.contains_aggregate())
+    }
+}
+
+/// Groups `rows` by the values of `group_by`, keeps the groups for which
+/// `having` holds and evaluates the select list once for each of them.
+///
+/// Groups come out sorted by `order_by`, then in the order of their first
+/// row. Without `GROUP BY` all rows form a single group, even when there are
+/// none.
+fn aggregate(
+    scope: &Scope,
+    rows: &[&HashMap<String, DataType>],
+    clauses: &Clauses,
+    subqueries: &dyn Subqueries,
+) -> Result<ResultSet, FerrousDBError> {
+    if clauses
+        .projection
+        .iter()
+        .any(|item| matches!(item, SelectItem::Wildcard(_)))
+    {
+        return Err(FerrousDBError::InvalidExpression(
+            "* can't be selected along with GROUP BY or aggregates".to_string(),
+        ));
+    }
+    let group_by = clauses
+        .group_by
+        .iter()
+        .map(|expr| scope.bind_condition(expr))
+        .collect::<Result<Vec<_>, _>>()?;
+    let having = clauses
+        .having
+        .map(|having| scope.bind(having))
+        .transpose()?;
+    let outputs = outputs(scope, clauses.projection)?;
+    let order_exprs = order_by_exprs(scope, &outputs, clauses.order_by)?;
+    let distinct_exprs = distinct_exprs(scope, &outputs, clauses.distinct, &order_exprs)?;
+    let exprs: Vec<&Expr> = outputs.iter().map(|output| &output.expr).collect();
+    let checked = exprs.iter().copied().chain(&having);
+    for expr in checked.chain(&order_exprs).chain(&distinct_exprs) {
+        check_grouped(expr, &group_by)?;
+    }
+    if group_by.iter().chain(&having).any(Expr::contains_window) {
+        return Err(FerrousDBError::InvalidExpression(
+            "window functions are not allowed in GROUP BY or HAVING".to_string(),
+        ));
+    }
+    let windows = window::windows(
+        exprs
+            .iter()
+            .copied()
+            .chain(&order_exprs)
+            .chain(&distinct_exprs),
+    )?;
+
+    let mut groups: Vec<Vec<&HashMap<String, DataType>>> = Vec::new();
+    if group_by.is_empty() {
+        groups.push(rows.to_vec());
+    } else {
+        let mut positions = HashMap::new();
+        for row in rows {
+            let key = group_by
+                .iter()
+                .map(|expr| evaluator::evaluate_with(expr, row, subqueries))
+                .collect::<Result<Vec<_>, _>>()?;
+            let position = *positions.entry(key).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[position].push(row);
+        }
+    }
+
+    let mut kept: Vec<Group> = Vec::with_capacity(groups.len());
+    let no_row = HashMap::new();
+    for group in groups {
+        let first = group.first().copied().unwrap_or(&no_row);
+        if let Some(having) = &having {
+            let having = evaluator::resolve_aggregates(having, &group)?;
+            if !evaluator::matches_with(&having, first, subqueries)? {
+                continue;
+            }
+        }
+        kept.push((group, first));
+    }
+    let evaluate = |expr: &Expr, (group, first): &Group, values: &[Option<DataType>]| {
+        let expr = window::substitute(expr, &windows, values);
+        let expr = evaluator::resolve_aggregates(&expr, group)?;
+        evaluator::evaluate_with(&expr, first, subqueries)
+    };
+    let window_values = window::compute(&windows, kept.len(), |group, expr| {
+        evaluate(expr, &kept[group], &[])
+    })?;
+
+    // The values of every group kept, with its key for `DISTINCT ON`
+    let mut items = Vec::with_capacity(kept.len());
+    let mut keys = Vec::new();
+    for (group, window_values) in kept.iter().zip(&window_values) {
+        let mut values = Vec::with_capacity(exprs.len());
+        for expr in &exprs {
+            values.push(evaluate(expr, group, window_values)?);
+        }
+        let mut key = Vec::with_capacity(order_exprs.len());
+        for expr in &order_exprs {
+            key.push(evaluate(expr, group, window_values)?);
+        }
+        keys.push(key);
+        let mut distinct_key = Vec::with_capacity(distinct_exprs.len());
+        for expr in &distinct_exprs {
+            distinct_key.push(evaluate(expr, group, window_values)?);
+        }
+        items.push((values, distinct_key));
+    }
+    sort_by_keys(&mut items, keys, clauses.order_by);
+
+    let (rows, distinct_keys): (Vec<_>, Vec<_>) = items.into_iter().unzip();
+    let mut result = ResultSet {
+        columns: result_columns(scope, &outputs),
+        rows,
+    };
+    retain_distinct(&mut result.rows, clauses.distinct, distinct_keys);
+    Ok(result)
+}
+
+/// Binds the select list, expanding wildcards into their columns.
+///
+/// A column selected without an alias is named after the column, qualified
+/// with its table when the result has another column of that name.
+fn outputs(scope: &Scope, projection: &[SelectItem]) -> Result<Vec<Output>, FerrousDBError> {
+    // Every output along with the table of the column it shows, if any
+    let mut outputs = Vec::new();
+    for item in projection {
+        match item {
+            SelectItem::Wildcard(qualifier) => {
+                let columns: Vec<&SourceColumn> = scope
+                    .columns()
+                    .iter()
+                    .filter(|column| qualifier.as_ref().is_none_or(|q| column.qualifier == *q))
+                    .collect();
+                if let (Some(qualifier), true) = (qualifier, columns.is_empty()) {
+                    return Err(FerrousDBError::TableNotFound(qualifier.clone()));
+                }
+                for column in columns {
+                    let expr = Expr::Column {
+                        table: Some(column.qualifier.clone()),
+                        name: column.name.clone(),
+                    };
+                    let output = Output {
+                        name: column.name.clone(),
+                        expr: scope.bind(&expr)?,
+                    };
+                    outputs.push((output, Some(column.qualifier.clone())));
+                }
+            }
+            SelectItem::Expr { expr, alias } => {
+                let output = Output {
+                    name: item.output_name().unwrap_or_default(),
+                    expr: scope.bind(expr)?,
+                };
+                // Columns of an enclosing query are values by now
+                let qualifier = match (alias, expr) {
+                    (None, Expr::Column { table, name }) => scope
+                        .resolve(table.as_deref(), name)
+                        .ok()
+                        .map(|column| column.qualifier.clone()),
+                    _ => None,
+                };
+                outputs.push((output, qualifier));
+            }
+        }
+    }
+
+    // Only names shared by outputs from different tables are qualified,
+    // repeating a column of one table keeps its name
+    let mut sources: HashMap<String, HashSet<Option<String>>> = HashMap::new();
+    for (output, qualifier) in &outputs {
+        sources
+            .entry(output.name.clone())
+            .or_default()
+            .insert(qualifier.clone());
+    }
+    Ok(outputs
+        .into_iter()
+        .map(|(mut output, qualifier)| {
+            if let (Some(qualifier), true) = (qualifier, sources[&output.name].len() > 1) {
+                output.name = format!("{}.{}", qualifier, output.name);
+            }
+            output
+        })
+        .collect())
+}
+
+fn result_columns(scope: &Scope, outputs: &[Output]) -> Vec<ResultColumn> {
+    outputs
+        .iter()
+        .map(|output| ResultColumn::new(&output.name, scope.expr_type(&output.expr)))
+        .collect()
+}
+
+/// The bound expressions to sort by.
+fn order_by_exprs(
+    scope: &Scope,
+    outputs: &[Output],
+    order_by: &[OrderByItem],
+) -> Result<Vec<Expr>, FerrousDBError> {
+    order_by
+        .iter()
+        .map(|order| key_expr(scope, outputs, &order.expr, "ORDER BY"))
+        .collect()
+}
+
+/// The bound expressions of `DISTINCT ON`, none for any other `SELECT`.
+///
+/// As in PostgreSQL, `ORDER BY` may only sort a `SELECT DISTINCT` by
+/// columns of its result, and must start with the expressions of
+/// `DISTINCT ON`, in any order, so that the row kept for each of their
+/// values is well defined.
+fn distinct_exprs(
+    scope: &Scope,
+    outputs: &[Output],
+    distinct: Option<&Distinct>,
+    order_exprs: &[Expr],
+) -> Result<Vec<Expr>, FerrousDBError> {
+    match distinct {
+        None => Ok(Vec::new()),
+        Some(Distinct::All) => {
+            if order_exprs
+                .iter()
+                .any(|expr| !outputs.iter().any(|output| output.expr == *expr))
+            {
+                return Err(FerrousDBError::InvalidExpression(
+                    "for SELECT DISTINCT, ORDER BY expressions must appear in select list"
+                        .to_string(),
+                ));
+            }
+            Ok(Vec::new())
+        }
+        Some(Distinct::On(exprs)) => {
+            let exprs = exprs
+                .iter()
+                .map(|expr| key_expr(scope, outputs, expr, "DISTINCT ON"))
+                .collect::<Result<Vec<_>, _>>()?;
+            let leading = order_exprs.get(..exprs.len());
+            if !order_exprs.is_empty()
+                && leading.is_none_or(|leading| {
+                    leading.iter().any(|expr| !exprs.contains(expr))
+                        || exprs.iter().any(|expr| !leading.contains(expr))
+                })
+            {
+                return Err(FerrousDBError::InvalidExpression(
+                    "SELECT DISTINCT ON expressions must match initial ORDER BY expressions"
+                        .to_string(),
+                ));
+            }
+            Ok(exprs)
+        }
+    }
+}
+
+/// Binds a key of `clause`. A key that is a bare name of an output column,
+/// or its position starting at 1, stands for that column's expression.
+fn key_expr(
+    scope: &Scope,
+    outputs: &[Output],
+    expr: &Expr,
+    clause: &str,
+) -> Result<Expr, FerrousDBError> {
+    match expr {
+        Expr::Literal(DataType::Integer(position)) => usize::try_from(*position)
+            .ok()
+            .and_then(|position| outputs.get(position.checked_sub(1)?))
+            .map(|output| output.expr.clone())
+            .ok_or_else(|| {
+                FerrousDBError::InvalidExpression(format!(
+                    "{} position {} is not in the select list",
+                    clause, position
+                ))
+            }),
+        Expr::Column { table: None, name } => {
+            match outputs.iter().find(|output| output.name == *name) {
+                Some(output) => Ok(output.expr.clone()),
+                None => scope.bind(expr),
+            }
+        }
+        expr => scope.bind(expr),
+    }
+}
+
+/// Leaves out the rows of a `SELECT DISTINCT` equal to an earlier one, in
+/// all their values or, for `DISTINCT ON`, in their `keys`.
+fn retain_distinct(
+    rows: &mut Vec<Vec<Option<DataType>>>,
+    distinct: Option<&Distinct>,
+    keys: Vec<Vec<Option<DataType>>>,
+) {
+    let mut seen = HashSet::new();
+    match distinct {
+        None => {}
+        Some(Distinct::All) => rows.retain(|row| seen.insert(row.clone())),
+        Some(Distinct::On(_)) => {
+            let mut keys = keys.into_iter();
+            rows.retain(|_| keys.next().is_some_and(|key| seen.insert(key)));
+        }
+    }
+}
+
+/// Sorts `items` by their `keys`, the values of the `order_by` keys for
+/// each of them. Items with equal keys keep their order.
+pub(crate) fn sort_by_keys<T>(
+    items: &mut Vec<T>,
+    keys: Vec<Vec<Option<DataType>>>,
+    order_by: &[OrderByItem],
+) {
+    let mut keyed: Vec<_> = keys.into_iter().zip(items.drain(..)).collect();
+    keyed.sort_by(|(a, _), (b, _)| {
+        order_by
+            .iter()
+            .zip(a.iter().zip(b))
+            .map(|(order, (a, b))| order.compare(a.as_ref(), b.as_ref()))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    items.extend(keyed.into_iter().map(|(_, item)| item));
+}
+
+/// Checks that a bound expression only reads columns through aggregates or
+/// through the expressions of `group_by`.
+fn check_grouped(expr: &Expr, group_by: &[Expr]) -> Result<(), FerrousDBError> {
+    if matches!(expr, Expr::Aggregate { .. }) || group_by.contains(expr) {
+        return Ok(());
+    }
+    if let Expr::Column { .. } = expr {
+        return Err(FerrousDBError::InvalidExpression(format!(
+            "column '{}' must appear in GROUP BY or be used in an aggregate function",
+            expr
+        )));
+    }
+    expr.children()
+        .into_iter()
+        .try_for_each(|child| check_grouped(child, group_by))
+}
@@ -0,0 +1,162 @@
+//! The results statements return.
+
+use std::fmt::{self, Display};
+
+use crate::DataType;
+
+/// What running a statement produced.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryResult {
+    /// The rows a `SELECT` returned.
+    Rows(ResultSet),
+    /// The number of rows an `INSERT`, `UPDATE` or `DELETE` changed.
+    RowsAffected(usize),
+    /// What a statement that doesn't return rows did, such as
+    /// `CREATE TABLE` or `COMMIT`.
+    Message(String),
+}
+
+/// The result of a `SELECT`: its columns, in order, and for every row one
+/// value per column, where `None` stands for NULL.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResultSet {
+    pub columns: Vec<ResultColumn>,
+    pub rows: Vec<Vec<Option<DataType>>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultColumn {
+    pub name: String,
+    /// The type of the column's values, `NULL` for a column that is always
+    /// NULL.
+    pub data_type: String,
+}
+
+impl QueryResult {
+    /// The rows, if the statement returned any.
+    pub fn rows(&self) -> Option<&ResultSet> {
+        match self {
+            QueryResult::Rows(rows) => Some(rows),
+            _ => None,
+        }
+    }
+}
+
+impl ResultSet {
+    pub fn column_names(&self) -> Vec<&str> {
+        self.columns
+            .iter()
+            .map(|column| column.name.as_str())
+            .collect()
+    }
+
+    /// The values of the first column called `name`, if there is one.
+    pub fn column(&self, name: &str) -> Option<Vec<Option<&DataType>>> {
+        let position = self.columns.iter().position(|column| column.name == name)?;
+        Some(self.rows.iter().map(|row| row[position].as_ref()).collect())
+    }
+}
+
+impl ResultColumn {
+    pub fn new(name: impl Into<String>, data_type: impl Into<String>) -> Self {
+        ResultColumn {
+            name: name.into(),
+            data_type: data_type.into(),
+        }
+    }
+}
+
+impl Display for QueryResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryResult::Rows(rows) => write!(f, "{}", rows),
+            QueryResult::RowsAffected(count) => write!(f, "{} row(s) affected", count),
+            QueryResult::Message(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Renders the rows as a text table with a header, followed by the number of
+/// rows.
+impl Display for ResultSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|value| value.as_ref().map_or("NULL".to_string(), |v| v.get_value()))
+                    .collect()
+            })
+            .collect();
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let header = column.name.chars().count().max(column.data_type.len());
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .fold(header, usize::max)
+            })
+            .collect();
+
+        let line = |f: &mut fmt::Formatter<'_>, values: Vec<&str>| -> fmt::Result {
+            let padded: Vec<String> = values
+                .iter()
+                .zip(&widths)
+                .map(|(value, width)| format!(" {:<width$} ", value, width = width))
+                .collect();
+            writeln!(f, "{}", padded.join("|").trim_end())
+        };
+        line(f, self.column_names())?;
+        line(
+            f,
+            self.columns
+                .iter()
+                .map(|column| column.data_type.as_str())
+                .collect(),
+        )?;
+        let rule: Vec<String> = widths.iter().map(|width| "-".repeat(width + 2)).collect();
+        writeln!(f, "{}", rule.join("+"))?;
+        for row in &cells {
+            line(f, row.iter().map(String::as_str).collect())?;
+        }
+        write!(f, "({} row(s))", self.rows.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_result_set() {
+        let rows = ResultSet {
+            columns: vec![
+                ResultColumn::new("name", "TEXT"),
+                ResultColumn::new("age", "INTEGER"),
+            ],
+            rows: vec![
+                vec![Some(DataType::Text("Alice".to_string())), None],
+                vec![
+                    Some(DataType::Text("Bob".to_string())),
+                    Some(DataType::Integer(42)),
+                ],
+            ],
+        };
+        assert_eq!(
+            QueryResult::Rows(rows).to_string(),
+            [
+                " name  | age",
+                " TEXT  | INTEGER",
+                "-------+---------",
+                " Alice | NULL",
+                " Bob   | 42",
+                "(2 row(s))",
+            ]
+            .join("\n")
+        );
+    }
+}
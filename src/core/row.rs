@@ -1,7 +1,9 @@
-use std::{collections::HashMap, fmt::Display, str::FromStr};
+use std::{cmp::Ordering, collections::HashMap, fmt::Display, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
+use super::{storage::heap::RowId, transaction::Snapshot};
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub enum DataType {
     Text(String),
@@ -25,6 +27,34 @@ impl DataType {
             DataType::Boolean(value) => value.to_string(),
         }
     }
+
+    fn type_rank(&self) -> u8 {
+        match self {
+            DataType::Boolean(_) => 0,
+            DataType::Integer(_) => 1,
+            DataType::Text(_) => 2,
+        }
+    }
+}
+
+/// Values of the same type compare naturally. Values of different types,
+/// which a column can't hold, sort by type: booleans, then integers, then
+/// text.
+impl Ord for DataType {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (DataType::Integer(a), DataType::Integer(b)) => a.cmp(b),
+            (DataType::Text(a), DataType::Text(b)) => a.cmp(b),
+            (DataType::Boolean(a), DataType::Boolean(b)) => a.cmp(b),
+            _ => self.type_rank().cmp(&other.type_rank()),
+        }
+    }
+}
+
+impl PartialOrd for DataType {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl Display for DataType {
@@ -56,4 +86,42 @@ impl FromStr for DataType {
 pub struct Row {
     /// The data in the row.
     pub data: HashMap<String, DataType>,
+    /// Where the row is stored on disk, if it has been persisted.
+    #[serde(skip)]
+    pub(crate) location: Option<RowId>,
+    /// Transaction that wrote this version, 0 once it is committed.
+    #[serde(skip)]
+    pub(crate) xmin: u64,
+    /// Transaction that deleted this version, if any.
+    #[serde(skip)]
+    pub(crate) xmax: Option<u64>,
+    /// The version this one replaced, kept while its replacement is
+    /// uncommitted.
+    #[serde(skip)]
+    pub(crate) previous: Option<Box<Row>>,
+}
+
+impl Row {
+    pub fn new(data: HashMap<String, DataType>) -> Self {
+        Row {
+            data,
+            location: None,
+            xmin: 0,
+            xmax: None,
+            previous: None,
+        }
+    }
+
+    /// Returns the version of this row `snapshot` sees, or `None` if the row
+    /// didn't exist yet or was already deleted for it.
+    pub(crate) fn visible(&self, snapshot: Snapshot) -> Option<&Row> {
+        let mut version = self;
+        while !snapshot.includes(version.xmin) {
+            version = version.previous.as_deref()?;
+        }
+        match version.xmax {
+            Some(xmax) if snapshot.includes(xmax) => None,
+            _ => Some(version),
+        }
+    }
 }
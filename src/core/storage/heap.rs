@@ -0,0 +1,175 @@
+use std::{collections::HashMap, io};
+
+use super::{
+    page::{PageId, PageKind, MAX_RECORD_SIZE, NO_PAGE},
+    pager::Pager,
+};
+
+/// Physical location of a record: the page it lives in and its slot there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RowId {
+    pub page: PageId,
+    pub slot: u16,
+}
+
+/// An unordered collection of records stored in a linked list of slotted
+/// pages.
+///
+/// The heap remembers how much room each of its pages has left, so inserts
+/// reuse the space of deleted and moved records before adding a page.
+pub struct TableHeap {
+    pages: Vec<PageId>,
+    free_space: HashMap<PageId, usize>,
+}
+
+impl TableHeap {
+    pub fn create(pager: &mut Pager) -> io::Result<Self> {
+        let first = pager.allocate(PageKind::Heap)?;
+        let free_space = HashMap::from([(first, pager.page(first)?.reclaimable_space())]);
+        Ok(TableHeap {
+            pages: vec![first],
+            free_space,
+        })
+    }
+
+    /// Opens an existing heap by following the page links from `first_page`.
+    pub fn open(pager: &mut Pager, first_page: PageId) -> io::Result<Self> {
+        let mut pages = Vec::new();
+        let mut free_space = HashMap::new();
+        let mut current = first_page;
+        while current != NO_PAGE {
+            let page = pager.page(current)?;
+            if page.kind() != PageKind::Heap {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("page {} is not a heap page", current),
+                ));
+            }
+            pages.push(current);
+            free_space.insert(current, page.reclaimable_space());
+            current = page.next_page();
+        }
+        Ok(TableHeap { pages, free_space })
+    }
+
+    pub fn first_page(&self) -> PageId {
+        self.pages[0]
+    }
+
+    pub fn insert(&mut self, pager: &mut Pager, record: &[u8]) -> io::Result<RowId> {
+        if record.len() > MAX_RECORD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "row of {} bytes exceeds the maximum of {} bytes",
+                    record.len(),
+                    MAX_RECORD_SIZE
+                ),
+            ));
+        }
+
+        for &page_id in &self.pages {
+            if self.free_space[&page_id] < record.len() {
+                continue;
+            }
+            let page = pager.page_mut(page_id)?;
+            let slot = page.insert_record(record);
+            self.free_space.insert(page_id, page.reclaimable_space());
+            if let Some(slot) = slot {
+                return Ok(RowId {
+                    page: page_id,
+                    slot,
+                });
+            }
+        }
+
+        let last = *self.pages.last().expect("heap has at least one page");
+        let new_page = pager.allocate(PageKind::Heap)?;
+        pager.page_mut(last)?.set_next_page(new_page);
+        self.pages.push(new_page);
+        let page = pager.page_mut(new_page)?;
+        let slot = page
+            .insert_record(record)
+            .expect("record fits in an empty page");
+        self.free_space.insert(new_page, page.reclaimable_space());
+        Ok(RowId {
+            page: new_page,
+            slot,
+        })
+    }
+
+    /// Rewrites the record at `rid`, moving it to another page if it no longer
+    /// fits. Returns the record's new location.
+    pub fn update(&mut self, pager: &mut Pager, rid: RowId, record: &[u8]) -> io::Result<RowId> {
+        let page = pager.page_mut(rid.page)?;
+        if page.update_record(rid.slot, record) {
+            self.free_space.insert(rid.page, page.reclaimable_space());
+            return Ok(rid);
+        }
+        self.delete(pager, rid)?;
+        self.insert(pager, record)
+    }
+
+    pub fn delete(&mut self, pager: &mut Pager, rid: RowId) -> io::Result<()> {
+        let page = pager.page_mut(rid.page)?;
+        page.delete_record(rid.slot);
+        self.free_space.insert(rid.page, page.reclaimable_space());
+        Ok(())
+    }
+
+    pub fn scan(&self, pager: &mut Pager) -> io::Result<Vec<(RowId, Vec<u8>)>> {
+        let mut records = Vec::new();
+        for &page_id in &self.pages {
+            let page = pager.page(page_id)?;
+            records.extend(page.records().map(|(slot, record)| {
+                (
+                    RowId {
+                        page: page_id,
+                        slot,
+                    },
+                    record.to_vec(),
+                )
+            }));
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::storage::pager::AccessMode;
+    use std::fs;
+
+    #[test]
+    fn test_insert_reuses_space_of_deleted_records() {
+        let path =
+            std::env::temp_dir().join(format!("ferrous_heap_reuse_{}.ferrous", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut pager = Pager::open(&path, AccessMode::ReadWrite).unwrap();
+        let mut heap = TableHeap::create(&mut pager).unwrap();
+        // Three records fill a page
+        let record = vec![7u8; MAX_RECORD_SIZE / 4];
+        let rids: Vec<RowId> = (0..12)
+            .map(|_| heap.insert(&mut pager, &record).unwrap())
+            .collect();
+        assert_eq!(heap.pages.len(), 4);
+
+        let first = heap.first_page();
+        for rid in rids.iter().filter(|rid| rid.page == first) {
+            heap.delete(&mut pager, *rid).unwrap();
+        }
+        assert_eq!(heap.insert(&mut pager, &record).unwrap().page, first);
+
+        // A reopened heap finds the room left as well
+        let mut heap = TableHeap::open(&mut pager, first).unwrap();
+        for _ in 0..2 {
+            assert_eq!(heap.insert(&mut pager, &record).unwrap().page, first);
+        }
+        assert_ne!(heap.insert(&mut pager, &record).unwrap().page, first);
+        assert_eq!(heap.pages.len(), 5);
+
+        drop(pager);
+        let _ = fs::remove_file(&path);
+    }
+}
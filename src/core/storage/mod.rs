@@ -0,0 +1,243 @@
+//! Page-based on-disk storage.
+//!
+//! The database file is a sequence of [`page::PAGE_SIZE`] pages. Page 0 is the
+//! file header, the catalog (table schemas and index definitions) lives in a
+//! chain of overflow pages, and every table owns a [`heap::TableHeap`] of
+//! slotted pages holding its rows. Mutations only dirty the pages they touch,
+//! and [`Storage::flush`] writes just those back, through a journal that
+//! makes the flush atomic.
+
+pub mod heap;
+pub mod page;
+pub mod pager;
+
+use std::{collections::HashMap, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    index::{Index, IndexType},
+    row::Row,
+    table::{ColumnSchema, Table},
+};
+use heap::{RowId, TableHeap};
+use page::{Page, PageId, PageKind, NO_PAGE};
+use pager::{AccessMode, Pager};
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Catalog {
+    pub tables: Vec<TableEntry>,
+    pub indexes: Vec<IndexEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TableEntry {
+    pub name: String,
+    pub schema: Vec<ColumnSchema>,
+    pub first_page: PageId,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub table_name: String,
+    pub column_name: String,
+    pub index_type: IndexType,
+}
+
+pub struct Storage {
+    pager: Pager,
+    heaps: HashMap<String, TableHeap>,
+}
+
+fn invalid_data<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl Storage {
+    pub fn open(path: &Path, mode: AccessMode) -> io::Result<Self> {
+        Ok(Storage {
+            pager: Pager::open(path, mode)?,
+            heaps: HashMap::new(),
+        })
+    }
+
+    /// Reads the catalog and opens the heap of every table listed in it.
+    pub fn read_catalog(&mut self) -> io::Result<Catalog> {
+        let mut bytes = Vec::new();
+        let mut current = self.pager.catalog_page()?;
+        while current != NO_PAGE {
+            let page = self.pager.page(current)?;
+            if page.overflow_data().is_empty() {
+                break;
+            }
+            bytes.extend_from_slice(page.overflow_data());
+            current = page.next_page();
+        }
+        if bytes.is_empty() {
+            return Ok(Catalog::default());
+        }
+
+        let catalog: Catalog = bincode::deserialize(&bytes).map_err(invalid_data)?;
+        for entry in &catalog.tables {
+            let heap = TableHeap::open(&mut self.pager, entry.first_page)?;
+            self.heaps.insert(entry.name.clone(), heap);
+        }
+        Ok(catalog)
+    }
+
+    /// Rewrites the catalog from the current tables and indexes, reusing the
+    /// existing chain of catalog pages.
+    pub fn write_catalog(
+        &mut self,
+        tables: &HashMap<String, Table>,
+        indexes: &HashMap<String, Index>,
+    ) -> io::Result<()> {
+        let mut catalog = Catalog::default();
+        for table in tables.values() {
+            let heap = self
+                .heaps
+                .get(&table.name)
+                .ok_or_else(|| missing_heap(&table.name))?;
+            catalog.tables.push(TableEntry {
+                name: table.name.clone(),
+                schema: table.schema.clone(),
+                first_page: heap.first_page(),
+            });
+        }
+        for index in indexes.values() {
+            catalog.indexes.push(IndexEntry {
+                table_name: index.table_name().to_string(),
+                column_name: index.column_name().to_string(),
+                index_type: index.index_type().clone(),
+            });
+        }
+        let bytes = bincode::serialize(&catalog).map_err(invalid_data)?;
+
+        let mut current = self.pager.catalog_page()?;
+        if current == NO_PAGE {
+            current = self.pager.allocate(PageKind::Overflow)?;
+            self.pager.set_catalog_page(current)?;
+        }
+        let mut chunks = bytes.chunks(Page::OVERFLOW_CAPACITY).peekable();
+        while let Some(chunk) = chunks.next() {
+            let page = self.pager.page_mut(current)?;
+            page.set_overflow_data(chunk);
+            let mut next = page.next_page();
+            if chunks.peek().is_some() && next == NO_PAGE {
+                next = self.pager.allocate(PageKind::Overflow)?;
+                self.pager.page_mut(current)?.set_next_page(next);
+            }
+            current = next;
+        }
+        // Leftover pages from a larger catalog stay linked but empty.
+        while current != NO_PAGE {
+            let page = self.pager.page_mut(current)?;
+            page.set_overflow_data(&[]);
+            current = page.next_page();
+        }
+        Ok(())
+    }
+
+    pub fn create_heap(&mut self, table_name: &str) -> io::Result<()> {
+        let heap = TableHeap::create(&mut self.pager)?;
+        self.heaps.insert(table_name.to_string(), heap);
+        Ok(())
+    }
+
+    /// Forgets a table's heap. Its pages stay allocated but unreachable.
+    pub fn drop_heap(&mut self, table_name: &str) {
+        self.heaps.remove(table_name);
+    }
+
+    /// Reads every row of a table along with its location.
+    pub fn scan(&mut self, table_name: &str) -> io::Result<Vec<Row>> {
+        let heap = self
+            .heaps
+            .get(table_name)
+            .ok_or_else(|| missing_heap(table_name))?;
+        let records = heap.scan(&mut self.pager)?;
+        records
+            .into_iter()
+            .map(|(rid, record)| {
+                let mut row: Row = bincode::deserialize(&record).map_err(invalid_data)?;
+                row.location = Some(rid);
+                Ok(row)
+            })
+            .collect()
+    }
+
+    pub fn insert_row(&mut self, table_name: &str, row: &Row) -> io::Result<RowId> {
+        let record = bincode::serialize(row).map_err(invalid_data)?;
+        let heap = self
+            .heaps
+            .get_mut(table_name)
+            .ok_or_else(|| missing_heap(table_name))?;
+        heap.insert(&mut self.pager, &record)
+    }
+
+    pub fn update_row(&mut self, table_name: &str, rid: RowId, row: &Row) -> io::Result<RowId> {
+        let record = bincode::serialize(row).map_err(invalid_data)?;
+        let heap = self
+            .heaps
+            .get_mut(table_name)
+            .ok_or_else(|| missing_heap(table_name))?;
+        heap.update(&mut self.pager, rid, &record)
+    }
+
+    pub fn delete_row(&mut self, table_name: &str, rid: RowId) -> io::Result<()> {
+        let heap = self
+            .heaps
+            .get_mut(table_name)
+            .ok_or_else(|| missing_heap(table_name))?;
+        heap.delete(&mut self.pager, rid)
+    }
+
+    pub fn wal_position(&mut self) -> io::Result<u64> {
+        self.pager.wal_position()
+    }
+
+    /// Records how much of the write-ahead log the next flush makes durable.
+    pub fn set_wal_position(&mut self, position: u64) -> io::Result<()> {
+        self.pager.set_wal_position(position)
+    }
+
+    pub fn checkpoint_lsn(&mut self) -> io::Result<u64> {
+        self.pager.checkpoint_lsn()
+    }
+
+    pub fn set_checkpoint_lsn(&mut self, lsn: u64) -> io::Result<()> {
+        self.pager.set_checkpoint_lsn(lsn)
+    }
+
+    pub fn last_txn_id(&mut self) -> io::Result<u64> {
+        self.pager.last_txn_id()
+    }
+
+    pub fn set_last_txn_id(&mut self, txn_id: u64) -> io::Result<()> {
+        self.pager.set_last_txn_id(txn_id)
+    }
+
+    /// Throws away every change since the last flush and reopens the heaps
+    /// from the catalog on disk.
+    pub fn discard_changes(&mut self) -> io::Result<()> {
+        self.pager.discard_dirty()?;
+        self.heaps.clear();
+        self.read_catalog()?;
+        Ok(())
+    }
+
+    /// Writes all dirty pages to disk.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.pager.flush()
+    }
+
+    /// See [`Pager::flush_torn`].
+    #[cfg(test)]
+    pub fn flush_torn(&mut self, written: impl Fn(PageId) -> bool) -> io::Result<()> {
+        self.pager.flush_torn(written)
+    }
+}
+
+fn missing_heap(table_name: &str) -> io::Error {
+    invalid_data(format!("table '{}' has no heap", table_name))
+}
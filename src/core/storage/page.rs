@@ -0,0 +1,288 @@
+//! Fixed-size pages and the slotted layout used by table heaps.
+//!
+//! A heap page starts with a small header followed by the slot directory,
+//! which grows towards the end of the page. Record bytes are packed from the
+//! end of the page backwards, so free space is always the gap in between.
+
+pub const PAGE_SIZE: usize = 4096;
+
+pub type PageId = u32;
+
+/// Page 0 is always the file header, so it doubles as the "no page" marker in
+/// page links.
+pub const NO_PAGE: PageId = 0;
+
+const KIND_OFFSET: usize = 0;
+const SLOT_COUNT_OFFSET: usize = 2;
+const FREE_END_OFFSET: usize = 4;
+const NEXT_PAGE_OFFSET: usize = 6;
+const PAGE_HEADER_SIZE: usize = 16;
+const SLOT_SIZE: usize = 4;
+
+/// Largest record that fits in an empty heap page.
+pub const MAX_RECORD_SIZE: usize = PAGE_SIZE - PAGE_HEADER_SIZE - SLOT_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageKind {
+    Header = 0,
+    Heap = 1,
+    Overflow = 2,
+}
+
+#[derive(Clone)]
+pub struct Page {
+    data: Box<[u8; PAGE_SIZE]>,
+}
+
+impl Page {
+    pub fn new(kind: PageKind) -> Self {
+        let mut page = Page {
+            data: Box::new([0; PAGE_SIZE]),
+        };
+        page.data[KIND_OFFSET] = kind as u8;
+        if kind == PageKind::Heap {
+            page.set_free_end(PAGE_SIZE);
+        }
+        page
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut data = Box::new([0; PAGE_SIZE]);
+        data.copy_from_slice(&bytes[..PAGE_SIZE]);
+        Page { data }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..]
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data[..]
+    }
+
+    pub fn kind(&self) -> PageKind {
+        match self.data[KIND_OFFSET] {
+            1 => PageKind::Heap,
+            2 => PageKind::Overflow,
+            _ => PageKind::Header,
+        }
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]])
+    }
+
+    pub fn write_u16(&mut self, offset: usize, value: u16) {
+        self.data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.data[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    pub fn write_u32(&mut self, offset: usize, value: u32) {
+        self.data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn read_u64(&self, offset: usize) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&self.data[offset..offset + 8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    pub fn write_u64(&mut self, offset: usize, value: u64) {
+        self.data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn next_page(&self) -> PageId {
+        self.read_u32(NEXT_PAGE_OFFSET)
+    }
+
+    pub fn set_next_page(&mut self, next: PageId) {
+        self.write_u32(NEXT_PAGE_OFFSET, next);
+    }
+
+    // --- Heap (slotted) pages ---
+
+    pub fn slot_count(&self) -> u16 {
+        self.read_u16(SLOT_COUNT_OFFSET)
+    }
+
+    fn set_slot_count(&mut self, count: u16) {
+        self.write_u16(SLOT_COUNT_OFFSET, count);
+    }
+
+    fn free_end(&self) -> usize {
+        self.read_u16(FREE_END_OFFSET) as usize
+    }
+
+    fn set_free_end(&mut self, offset: usize) {
+        self.write_u16(FREE_END_OFFSET, offset as u16);
+    }
+
+    fn slot_offset(slot: u16) -> usize {
+        PAGE_HEADER_SIZE + slot as usize * SLOT_SIZE
+    }
+
+    fn slot(&self, slot: u16) -> (usize, usize) {
+        let at = Self::slot_offset(slot);
+        (self.read_u16(at) as usize, self.read_u16(at + 2) as usize)
+    }
+
+    fn set_slot(&mut self, slot: u16, offset: usize, len: usize) {
+        let at = Self::slot_offset(slot);
+        self.write_u16(at, offset as u16);
+        self.write_u16(at + 2, len as u16);
+    }
+
+    /// Contiguous free bytes between the slot directory and the record area.
+    pub fn free_space(&self) -> usize {
+        self.free_end() - Self::slot_offset(self.slot_count())
+    }
+
+    /// Bytes that would be free after compacting away deleted records.
+    pub fn reclaimable_space(&self) -> usize {
+        let used: usize = (0..self.slot_count()).map(|s| self.slot(s).1).sum();
+        PAGE_SIZE - Self::slot_offset(self.slot_count()) - used
+    }
+
+    /// Stores `record` and returns its slot, or `None` if the page is full.
+    pub fn insert_record(&mut self, record: &[u8]) -> Option<u16> {
+        let free_slot = (0..self.slot_count()).find(|&s| self.slot(s) == (0, 0));
+        let needed = record.len() + if free_slot.is_some() { 0 } else { SLOT_SIZE };
+        if self.reclaimable_space() < needed {
+            return None;
+        }
+        if self.free_space() < needed {
+            self.compact();
+        }
+
+        let slot = match free_slot {
+            Some(slot) => slot,
+            None => {
+                let slot = self.slot_count();
+                self.set_slot_count(slot + 1);
+                slot
+            }
+        };
+        self.write_record(slot, record);
+        Some(slot)
+    }
+
+    fn write_record(&mut self, slot: u16, record: &[u8]) {
+        let offset = self.free_end() - record.len();
+        self.data[offset..offset + record.len()].copy_from_slice(record);
+        self.set_free_end(offset);
+        self.set_slot(slot, offset, record.len());
+    }
+
+    pub fn record(&self, slot: u16) -> Option<&[u8]> {
+        if slot >= self.slot_count() {
+            return None;
+        }
+        match self.slot(slot) {
+            (0, 0) => None,
+            (offset, len) => Some(&self.data[offset..offset + len]),
+        }
+    }
+
+    pub fn delete_record(&mut self, slot: u16) {
+        if slot < self.slot_count() {
+            self.set_slot(slot, 0, 0);
+        }
+    }
+
+    /// Replaces the record in `slot`, keeping its slot number. Returns `false`
+    /// if the new record does not fit in this page.
+    pub fn update_record(&mut self, slot: u16, record: &[u8]) -> bool {
+        let (offset, len) = self.slot(slot);
+        if record.len() <= len {
+            self.data[offset..offset + record.len()].copy_from_slice(record);
+            self.set_slot(slot, offset, record.len());
+            return true;
+        }
+        if self.reclaimable_space() + len < record.len() {
+            return false;
+        }
+        self.set_slot(slot, 0, 0);
+        if self.free_space() < record.len() {
+            self.compact();
+        }
+        self.write_record(slot, record);
+        true
+    }
+
+    /// Iterates over the live records of a heap page.
+    pub fn records(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        (0..self.slot_count()).filter_map(move |slot| self.record(slot).map(|r| (slot, r)))
+    }
+
+    /// Packs live records at the end of the page so all free space is contiguous.
+    fn compact(&mut self) {
+        let records: Vec<(u16, Vec<u8>)> = self
+            .records()
+            .map(|(slot, record)| (slot, record.to_vec()))
+            .collect();
+        self.set_free_end(PAGE_SIZE);
+        for (slot, record) in records {
+            self.write_record(slot, &record);
+        }
+    }
+
+    // --- Overflow pages ---
+
+    /// Usable payload bytes in an overflow page.
+    pub const OVERFLOW_CAPACITY: usize = PAGE_SIZE - PAGE_HEADER_SIZE;
+
+    pub fn overflow_data(&self) -> &[u8] {
+        let len = self.read_u16(SLOT_COUNT_OFFSET) as usize;
+        &self.data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + len]
+    }
+
+    pub fn set_overflow_data(&mut self, bytes: &[u8]) {
+        self.write_u16(SLOT_COUNT_OFFSET, bytes.len() as u16);
+        self.data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + bytes.len()].copy_from_slice(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_read_records() {
+        let mut page = Page::new(PageKind::Heap);
+        let a = page.insert_record(b"alice").unwrap();
+        let b = page.insert_record(b"bob").unwrap();
+        assert_eq!(page.record(a), Some(&b"alice"[..]));
+        assert_eq!(page.record(b), Some(&b"bob"[..]));
+        assert_eq!(page.records().count(), 2);
+    }
+
+    #[test]
+    fn test_deleted_slot_is_reused_after_compaction() {
+        let mut page = Page::new(PageKind::Heap);
+        let big = vec![7u8; MAX_RECORD_SIZE / 2];
+        let first = page.insert_record(&big).unwrap();
+        page.insert_record(&big[..100]).unwrap();
+        assert!(page.insert_record(&big).is_none());
+
+        page.delete_record(first);
+        assert_eq!(page.record(first), None);
+        assert_eq!(page.insert_record(&big), Some(first));
+        assert_eq!(page.record(first), Some(&big[..]));
+    }
+
+    #[test]
+    fn test_update_record_grows_in_place_slot() {
+        let mut page = Page::new(PageKind::Heap);
+        let slot = page.insert_record(b"short").unwrap();
+        page.insert_record(b"other").unwrap();
+        assert!(page.update_record(slot, b"a much longer record"));
+        assert_eq!(page.record(slot), Some(&b"a much longer record"[..]));
+        assert_eq!(page.record(1), Some(&b"other"[..]));
+        assert!(!page.update_record(slot, &[0u8; PAGE_SIZE]));
+    }
+}
@@ -0,0 +1,389 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use super::page::{Page, PageId, PageKind, PAGE_SIZE};
+use crate::core::write_ahead_log::crc32;
+
+const MAGIC: &[u8; 8] = b"FERROUS\0";
+const FORMAT_VERSION: u32 = 1;
+
+const VERSION_OFFSET: usize = 8;
+const PAGE_COUNT_OFFSET: usize = 12;
+const CATALOG_PAGE_OFFSET: usize = 16;
+const WAL_POSITION_OFFSET: usize = 24;
+const CHECKPOINT_LSN_OFFSET: usize = 32;
+const LAST_TXN_ID_OFFSET: usize = 40;
+
+/// Size of a journal entry: a page id followed by the page.
+const JOURNAL_ENTRY_SIZE: usize = 4 + PAGE_SIZE;
+
+/// Number of pages kept in the buffer cache before clean pages get evicted.
+pub const DEFAULT_CACHE_CAPACITY: usize = 256;
+
+/// How a database file is opened, and the advisory lock taken on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessMode {
+    /// Readable and writable, created if missing, locked exclusively.
+    ReadWrite,
+    /// Only readable, must already exist, locked shared with other readers.
+    ReadOnly,
+}
+
+struct Frame {
+    page: Page,
+    dirty: bool,
+    last_used: u64,
+}
+
+/// Reads and writes fixed-size pages of the database file through an LRU
+/// buffer cache.
+///
+/// Modified pages stay pinned in the cache until [`Pager::flush`] writes them
+/// back, so the file only ever sees complete, flushed states.
+///
+/// A flush first writes the pages to a journal next to the file, with a
+/// `.journal` extension, so that one interrupted by a crash is completed
+/// when the file is opened again.
+pub struct Pager {
+    file: File,
+    journal_path: PathBuf,
+    /// Pages of a journal left by a crash that a read-only pager can't write
+    /// back, read in place of those in the file.
+    journaled: HashMap<PageId, Page>,
+    page_count: u32,
+    frames: HashMap<PageId, Frame>,
+    capacity: usize,
+    clock: u64,
+}
+
+impl Pager {
+    /// Opens the database file and locks it for `mode`. Fails with
+    /// [`io::ErrorKind::WouldBlock`] if another handle holds a conflicting
+    /// lock.
+    pub fn open(path: &Path, mode: AccessMode) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(mode == AccessMode::ReadWrite)
+            .create(mode == AccessMode::ReadWrite)
+            .truncate(false)
+            .open(path)?;
+        let locked = match mode {
+            AccessMode::ReadWrite => file.try_lock(),
+            AccessMode::ReadOnly => file.try_lock_shared(),
+        };
+        match locked {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "database file is locked by another process",
+                ))
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+
+        let mut pager = Pager {
+            file,
+            journal_path: path.with_extension("journal"),
+            journaled: HashMap::new(),
+            page_count: 0,
+            frames: HashMap::new(),
+            capacity: DEFAULT_CACHE_CAPACITY,
+            clock: 0,
+        };
+
+        if let Some(pages) = read_journal(&pager.journal_path)? {
+            match mode {
+                AccessMode::ReadWrite => {
+                    for (id, page) in &pages {
+                        pager.write_page(*id, page)?;
+                    }
+                    pager.file.sync_data()?;
+                }
+                AccessMode::ReadOnly => pager.journaled = pages.into_iter().collect(),
+            }
+        }
+        if mode == AccessMode::ReadWrite {
+            remove_journal(&pager.journal_path)?;
+        }
+
+        if pager.file.metadata()?.len() == 0 && mode == AccessMode::ReadWrite {
+            let mut header = Page::new(PageKind::Header);
+            header.as_bytes_mut()[..MAGIC.len()].copy_from_slice(MAGIC);
+            header.write_u32(VERSION_OFFSET, FORMAT_VERSION);
+            header.write_u32(PAGE_COUNT_OFFSET, 1);
+            pager.page_count = 1;
+            pager.insert_frame(0, header, true);
+            pager.flush()?;
+        } else {
+            pager.page_count = 1;
+            let header = pager.page(0)?;
+            if &header.as_bytes()[..MAGIC.len()] != MAGIC
+                || header.read_u32(VERSION_OFFSET) != FORMAT_VERSION
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a FerrousDB database file",
+                ));
+            }
+            pager.page_count = header.read_u32(PAGE_COUNT_OFFSET);
+        }
+        Ok(pager)
+    }
+
+    pub fn page(&mut self, id: PageId) -> io::Result<&Page> {
+        self.load(id)?;
+        let frame = self.touch(id);
+        Ok(&frame.page)
+    }
+
+    /// Returns the page for modification and marks it dirty.
+    pub fn page_mut(&mut self, id: PageId) -> io::Result<&mut Page> {
+        self.load(id)?;
+        let frame = self.touch(id);
+        frame.dirty = true;
+        Ok(&mut frame.page)
+    }
+
+    /// Appends a fresh page of the given kind to the file.
+    pub fn allocate(&mut self, kind: PageKind) -> io::Result<PageId> {
+        let id = self.page_count;
+        self.page_count += 1;
+        let page_count = self.page_count;
+        self.page_mut(0)?.write_u32(PAGE_COUNT_OFFSET, page_count);
+        self.insert_frame(id, Page::new(kind), true);
+        Ok(id)
+    }
+
+    pub fn catalog_page(&mut self) -> io::Result<PageId> {
+        Ok(self.page(0)?.read_u32(CATALOG_PAGE_OFFSET))
+    }
+
+    pub fn set_catalog_page(&mut self, id: PageId) -> io::Result<()> {
+        self.page_mut(0)?.write_u32(CATALOG_PAGE_OFFSET, id);
+        Ok(())
+    }
+
+    /// Byte offset in the write-ahead log up to which changes are reflected in
+    /// the database file.
+    pub fn wal_position(&mut self) -> io::Result<u64> {
+        Ok(self.page(0)?.read_u64(WAL_POSITION_OFFSET))
+    }
+
+    pub fn set_wal_position(&mut self, position: u64) -> io::Result<()> {
+        self.page_mut(0)?.write_u64(WAL_POSITION_OFFSET, position);
+        Ok(())
+    }
+
+    /// LSN of the last checkpoint, i.e. where the current log file starts.
+    pub fn checkpoint_lsn(&mut self) -> io::Result<u64> {
+        Ok(self.page(0)?.read_u64(CHECKPOINT_LSN_OFFSET))
+    }
+
+    pub fn set_checkpoint_lsn(&mut self, lsn: u64) -> io::Result<()> {
+        self.page_mut(0)?.write_u64(CHECKPOINT_LSN_OFFSET, lsn);
+        Ok(())
+    }
+
+    /// Highest transaction id made durable in the database file.
+    pub fn last_txn_id(&mut self) -> io::Result<u64> {
+        Ok(self.page(0)?.read_u64(LAST_TXN_ID_OFFSET))
+    }
+
+    pub fn set_last_txn_id(&mut self, txn_id: u64) -> io::Result<()> {
+        self.page_mut(0)?.write_u64(LAST_TXN_ID_OFFSET, txn_id);
+        Ok(())
+    }
+
+    /// Writes every dirty page back to the file and syncs it.
+    ///
+    /// The pages are synced to the journal before any of them reaches the
+    /// file. The header goes last, once the others are synced, since it
+    /// records how much of the write-ahead log they reflect.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let dirty = self.dirty_pages();
+        if dirty.is_empty() {
+            return Ok(());
+        }
+        self.write_journal(&dirty)?;
+
+        let (header, pages): (Vec<PageId>, Vec<PageId>) = dirty.iter().partition(|&&id| id == 0);
+        for ids in [pages, header] {
+            if ids.is_empty() {
+                continue;
+            }
+            for id in ids {
+                let page = self.frames[&id].page.clone();
+                self.write_page(id, &page)?;
+            }
+            self.file.sync_data()?;
+        }
+        remove_journal(&self.journal_path)?;
+
+        for id in dirty {
+            self.frames
+                .get_mut(&id)
+                .expect("dirty page must be cached")
+                .dirty = false;
+        }
+        self.evict();
+        Ok(())
+    }
+
+    /// Writes the journal of a flush and then only the dirty pages `written`
+    /// accepts, as a crash in the middle of [`Pager::flush`] would.
+    #[cfg(test)]
+    pub fn flush_torn(&mut self, written: impl Fn(PageId) -> bool) -> io::Result<()> {
+        let dirty = self.dirty_pages();
+        self.write_journal(&dirty)?;
+        for id in dirty.into_iter().filter(|&id| written(id)) {
+            let page = self.frames[&id].page.clone();
+            self.write_page(id, &page)?;
+        }
+        self.file.sync_data()
+    }
+
+    /// Drops every modified page from the cache, so reads see the file as of
+    /// the last flush again.
+    pub fn discard_dirty(&mut self) -> io::Result<()> {
+        self.frames.retain(|_, frame| !frame.dirty);
+        self.page_count = self.page(0)?.read_u32(PAGE_COUNT_OFFSET);
+        Ok(())
+    }
+
+    fn load(&mut self, id: PageId) -> io::Result<()> {
+        if self.frames.contains_key(&id) {
+            return Ok(());
+        }
+        if let Some(page) = self.journaled.get(&id) {
+            self.insert_frame(id, page.clone(), false);
+            return Ok(());
+        }
+        if id >= self.page_count {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("page {} is past the end of the database file", id),
+            ));
+        }
+
+        let mut buffer = vec![0; PAGE_SIZE];
+        self.file
+            .seek(SeekFrom::Start(id as u64 * PAGE_SIZE as u64))?;
+        self.file.read_exact(&mut buffer)?;
+        self.insert_frame(id, Page::from_bytes(&buffer), false);
+        Ok(())
+    }
+
+    /// The ids of the modified pages, in order.
+    fn dirty_pages(&self) -> Vec<PageId> {
+        let mut dirty: Vec<PageId> = self
+            .frames
+            .iter()
+            .filter(|(_, frame)| frame.dirty)
+            .map(|(id, _)| *id)
+            .collect();
+        dirty.sort_unstable();
+        dirty
+    }
+
+    fn write_page(&mut self, id: PageId, page: &Page) -> io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start(id as u64 * PAGE_SIZE as u64))?;
+        self.file.write_all(page.as_bytes())
+    }
+
+    /// Writes and syncs the journal of the pages `ids`: every page after its
+    /// id, then their number and a CRC32 of all of it.
+    fn write_journal(&self, ids: &[PageId]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(ids.len() * JOURNAL_ENTRY_SIZE + 8);
+        for id in ids {
+            bytes.extend_from_slice(&id.to_le_bytes());
+            bytes.extend_from_slice(self.frames[id].page.as_bytes());
+        }
+        bytes.extend_from_slice(&(ids.len() as u32).to_le_bytes());
+        let crc = crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+
+        let mut journal = File::create(&self.journal_path)?;
+        journal.write_all(&bytes)?;
+        journal.sync_data()
+    }
+
+    fn insert_frame(&mut self, id: PageId, page: Page, dirty: bool) {
+        self.clock += 1;
+        self.frames.insert(
+            id,
+            Frame {
+                page,
+                dirty,
+                last_used: self.clock,
+            },
+        );
+        self.evict();
+    }
+
+    fn touch(&mut self, id: PageId) -> &mut Frame {
+        self.clock += 1;
+        let frame = self.frames.get_mut(&id).expect("page must be cached");
+        frame.last_used = self.clock;
+        frame
+    }
+
+    /// Drops least recently used clean pages until the cache fits its
+    /// capacity. Dirty pages are never evicted, only flushed.
+    fn evict(&mut self) {
+        while self.frames.len() > self.capacity {
+            let victim = self
+                .frames
+                .iter()
+                .filter(|(id, frame)| !frame.dirty && **id != 0)
+                .min_by_key(|(_, frame)| frame.last_used)
+                .map(|(id, _)| *id);
+            match victim {
+                Some(id) => {
+                    self.frames.remove(&id);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// Reads the pages of the journal at `path`. There are none if it is missing,
+/// or cut short by a crash before any of them was written to the file.
+fn read_journal(path: &Path) -> io::Result<Option<Vec<(PageId, Page)>>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let Some(entries_len) = bytes.len().checked_sub(8) else {
+        return Ok(None);
+    };
+    let (body, crc) = bytes.split_at(bytes.len() - 4);
+    let count = u32::from_le_bytes(body[entries_len..].try_into().unwrap()) as usize;
+    if crc32(body) != u32::from_le_bytes(crc.try_into().unwrap())
+        || count * JOURNAL_ENTRY_SIZE != entries_len
+    {
+        return Ok(None);
+    }
+    let pages = body[..entries_len]
+        .chunks_exact(JOURNAL_ENTRY_SIZE)
+        .map(|entry| {
+            let id = PageId::from_le_bytes(entry[..4].try_into().unwrap());
+            (id, Page::from_bytes(&entry[4..]))
+        })
+        .collect();
+    Ok(Some(pages))
+}
+
+fn remove_journal(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
 
-use super::row::Row;
+use super::{
+    expr::Expr,
+    row::{DataType, Row},
+    transaction::Snapshot,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Constraint {
@@ -11,7 +15,10 @@ pub enum Constraint {
         ref_table: String,
         ref_column: String,
     },
-    Check(String),  // Expression to check
+    /// Rejects rows for which the expression is false. NULL passes.
+    Check(Expr),
+    /// The value an `INSERT` gives the column when it leaves it out.
+    Default(DataType),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -37,6 +44,14 @@ impl ColumnSchema {
             constraints,
         }
     }
+
+    /// The value of the column's `DEFAULT` constraint, if it has one.
+    pub fn default_value(&self) -> Option<&DataType> {
+        self.constraints.iter().find_map(|constraint| match constraint {
+            Constraint::Default(value) => Some(value),
+            _ => None,
+        })
+    }
 }
 
 impl std::fmt::Display for ColumnSchema {
@@ -94,6 +109,14 @@ impl Table {
         }
     }
 
+    /// Returns the rows `snapshot` sees, along with their positions in `rows`.
+    pub(crate) fn visible_rows(&self, snapshot: Snapshot) -> impl Iterator<Item = (usize, &Row)> {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter_map(move |(idx, row)| row.visible(snapshot).map(|version| (idx, version)))
+    }
+
     pub fn get_page(&self, mut page_number: usize, page_size: usize) -> Option<Vec<&Row>> {
         if page_number > self.total_pages(page_size) {
             return None;
@@ -0,0 +1,85 @@
+//! Undo records for rolling back uncommitted changes.
+//!
+//! Every mutation of [`FerrousDB`](super::db::FerrousDB) pushes an
+//! [`UndoEntry`] describing how to put the tables and indexes back the way
+//! they were. Rolling back applies them newest first. The entries are dropped
+//! once the changes they cover have been committed.
+//!
+//! Until a transaction commits, the rows it changes keep their previous
+//! versions, tagged with the transaction that wrote or deleted them, so a
+//! [`Snapshot`] of the last commit still sees the rows as they were.
+//!
+//! A [`Savepoint`] remembers how long the undo log was when it was set, so
+//! rolling back to it only reverts the entries pushed after that.
+
+use super::{index::Index, row::Row};
+
+#[derive(Debug, Clone)]
+pub(crate) enum UndoEntry {
+    /// A table was created and has to be dropped again.
+    CreateTable { name: String },
+    /// An index was created, possibly replacing an older one of the same name.
+    CreateIndex {
+        name: String,
+        replaced: Option<Index>,
+    },
+    /// A row was inserted at `position` in the table.
+    Insert { table: String, position: usize },
+    /// The row at `position` held `old` before it was updated.
+    Update {
+        table: String,
+        position: usize,
+        old: Row,
+    },
+    /// `row` was removed from `position` in the table.
+    Delete {
+        table: String,
+        position: usize,
+        row: Row,
+    },
+}
+
+impl UndoEntry {
+    /// Table and position of the row the entry restores, if any.
+    pub(crate) fn row(&self) -> Option<(&str, usize)> {
+        match self {
+            UndoEntry::Insert { table, position }
+            | UndoEntry::Update {
+                table, position, ..
+            }
+            | UndoEntry::Delete {
+                table, position, ..
+            } => Some((table, *position)),
+            UndoEntry::CreateTable { .. } | UndoEntry::CreateIndex { .. } => None,
+        }
+    }
+}
+
+/// The set of transactions whose changes a reader sees: every transaction up
+/// to and including `txn_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Snapshot {
+    txn_id: u64,
+}
+
+impl Snapshot {
+    pub(crate) fn new(txn_id: u64) -> Self {
+        Snapshot { txn_id }
+    }
+
+    /// Whether the changes of transaction `txn_id` are visible. Committed rows
+    /// carry transaction id 0 and are visible to every snapshot.
+    pub(crate) fn includes(&self, txn_id: u64) -> bool {
+        txn_id <= self.txn_id
+    }
+}
+
+/// A named point inside a transaction that can be rolled back to.
+#[derive(Debug, Clone)]
+pub(crate) struct Savepoint {
+    pub(crate) name: String,
+    /// Length of the undo log when the savepoint was set.
+    pub(crate) undo_mark: usize,
+    /// Length of the pending write-ahead log changes when the savepoint was set.
+    pub(crate) log_mark: usize,
+}
@@ -0,0 +1,331 @@
+//! Computes window functions.
+//!
+//! A window function gives a value for every row of a `SELECT`, or for every
+//! group when it aggregates, from the rows of its partition: those for which
+//! the `PARTITION BY` expressions give the same values. Within a partition,
+//! rows are sorted by the window's `ORDER BY`, and rows it doesn't tell
+//! apart are peers. Aggregates, `first_value` and `last_value` only read the
+//! rows of the frame, a range of the partition around the current row.
+//!
+//! Window functions are computed before the `ORDER BY`, `DISTINCT` and
+//! `LIMIT` of the `SELECT`, whose expressions then read their values.
+
+use std::{borrow::Cow, collections::HashMap};
+
+use super::{
+    error_handling::FerrousDBError,
+    evaluator,
+    expr::{Expr, FrameBound, FrameUnits, WindowFrame, WindowFunction},
+    query,
+};
+use crate::DataType;
+
+/// The frame of a window without one: the partition up to the last peer of
+/// the current row.
+const DEFAULT_FRAME: WindowFrame = WindowFrame {
+    units: FrameUnits::Range,
+    start: FrameBound::UnboundedPreceding,
+    end: FrameBound::CurrentRow,
+};
+
+/// The window functions `exprs` call, each once.
+pub(crate) fn windows<'a>(
+    exprs: impl IntoIterator<Item = &'a Expr>,
+) -> Result<Vec<Expr>, FerrousDBError> {
+    let mut windows = Vec::new();
+    for expr in exprs {
+        collect(expr, &mut windows)?;
+    }
+    Ok(windows)
+}
+
+fn collect(expr: &Expr, windows: &mut Vec<Expr>) -> Result<(), FerrousDBError> {
+    if let Expr::Window { .. } = expr {
+        if expr.children().iter().any(|child| child.contains_window()) {
+            return Err(FerrousDBError::InvalidExpression(format!(
+                "window function calls cannot be nested in '{}'",
+                expr
+            )));
+        }
+        if !windows.contains(expr) {
+            windows.push(expr.clone());
+        }
+        return Ok(());
+    }
+    expr.children()
+        .into_iter()
+        .try_for_each(|child| collect(child, windows))
+}
+
+/// Computes the values of `windows` for `count` rows, `evaluate` giving the
+/// value of an expression for one of them. The values of row `i` come
+/// `i`-th, in the order of `windows`.
+pub(crate) fn compute(
+    windows: &[Expr],
+    count: usize,
+    evaluate: impl Fn(usize, &Expr) -> Result<Option<DataType>, FerrousDBError>,
+) -> Result<Vec<Vec<Option<DataType>>>, FerrousDBError> {
+    let mut values = vec![Vec::with_capacity(windows.len()); count];
+    for window in windows {
+        let Expr::Window { func, args, over } = window else {
+            unreachable!("'{}' is not a window function", window);
+        };
+
+        // Partitions come in the order of their first row
+        let mut partitions: Vec<Vec<usize>> = Vec::new();
+        let mut positions = HashMap::new();
+        for row in 0..count {
+            let key = over
+                .partition_by
+                .iter()
+                .map(|expr| evaluate(row, expr))
+                .collect::<Result<Vec<_>, _>>()?;
+            let position = *positions.entry(key).or_insert_with(|| {
+                partitions.push(Vec::new());
+                partitions.len() - 1
+            });
+            partitions[position].push(row);
+        }
+
+        let mut results = vec![None; count];
+        for mut partition in partitions {
+            let mut keys = Vec::with_capacity(partition.len());
+            for &row in &partition {
+                keys.push(
+                    over.order_by
+                        .iter()
+                        .map(|order| evaluate(row, &order.expr))
+                        .collect::<Result<Vec<_>, _>>()?,
+                );
+            }
+            let mut sorted: Vec<_> = partition.drain(..).zip(keys.clone()).collect();
+            query::sort_by_keys(&mut sorted, keys, &over.order_by);
+            let partition = Partition::new(sorted);
+            // The values of the first argument, read for other rows too
+            let arguments = match args.first() {
+                Some(arg) => partition
+                    .rows
+                    .iter()
+                    .map(|&row| evaluate(row, arg))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => Vec::new(),
+            };
+            let frame = over.frame.as_ref().unwrap_or(&DEFAULT_FRAME);
+
+            for (position, &row) in partition.rows.iter().enumerate() {
+                results[row] = match func {
+                    WindowFunction::RowNumber => Some(DataType::Integer(position as i64 + 1)),
+                    WindowFunction::Rank => {
+                        Some(DataType::Integer(partition.peers[position].0 as i64 + 1))
+                    }
+                    WindowFunction::DenseRank => {
+                        Some(DataType::Integer(partition.groups[position] as i64))
+                    }
+                    WindowFunction::Lag | WindowFunction::Lead => {
+                        let offset = match args.get(1) {
+                            Some(offset) => match evaluate(row, offset)? {
+                                Some(DataType::Integer(offset)) => Some(offset),
+                                None => None,
+                                Some(value) => {
+                                    return Err(FerrousDBError::InvalidExpression(format!(
+                                        "{}() offset must be an INTEGER, got {}",
+                                        func.name(),
+                                        value.get_type()
+                                    )))
+                                }
+                            },
+                            None => Some(1),
+                        };
+                        let target = offset.and_then(|offset| {
+                            let offset = if *func == WindowFunction::Lag {
+                                offset.checked_neg()?
+                            } else {
+                                offset
+                            };
+                            let target = (position as i64).checked_add(offset)?;
+                            usize::try_from(target)
+                                .ok()
+                                .filter(|&target| target < arguments.len())
+                        });
+                        match (offset, target, args.get(2)) {
+                            (None, _, _) => None,
+                            (_, Some(target), _) => arguments[target].clone(),
+                            (_, None, Some(default)) => evaluate(row, default)?,
+                            (_, None, None) => None,
+                        }
+                    }
+                    WindowFunction::FirstValue => partition
+                        .frame(frame, position)
+                        .and_then(|(start, _)| arguments[start].clone()),
+                    WindowFunction::LastValue => partition
+                        .frame(frame, position)
+                        .and_then(|(_, end)| arguments[end].clone()),
+                    WindowFunction::Aggregate(aggregate) => {
+                        let (start, end) = match partition.frame(frame, position) {
+                            Some((start, end)) => (start, end + 1),
+                            None => (0, 0),
+                        };
+                        match args.first() {
+                            Some(arg) => {
+                                let values = arguments[start..end].iter().flatten().cloned();
+                                evaluator::fold(*aggregate, arg, values.collect())?
+                            }
+                            // count(*)
+                            None => Some(DataType::Integer((end - start) as i64)),
+                        }
+                    }
+                };
+            }
+        }
+        for (row, result) in results.into_iter().enumerate() {
+            values[row].push(result);
+        }
+    }
+    Ok(values)
+}
+
+/// Replaces the window functions of `expr` by their `values` for a row,
+/// given in the order of `windows`.
+pub(crate) fn substitute<'a>(
+    expr: &'a Expr,
+    windows: &[Expr],
+    values: &[Option<DataType>],
+) -> Cow<'a, Expr> {
+    if !expr.contains_window() {
+        return Cow::Borrowed(expr);
+    }
+    let mut expr = expr.clone();
+    replace(&mut expr, windows, values);
+    Cow::Owned(expr)
+}
+
+fn replace(expr: &mut Expr, windows: &[Expr], values: &[Option<DataType>]) {
+    if let Some(position) = windows.iter().position(|window| window == expr) {
+        *expr = match &values[position] {
+            Some(value) => Expr::Literal(value.clone()),
+            None => Expr::Null,
+        };
+        return;
+    }
+    for child in expr.children_mut() {
+        replace(child, windows, values);
+    }
+}
+
+/// The rows of a partition in the order of the window.
+struct Partition {
+    rows: Vec<usize>,
+    /// The positions of the first and last peers of every row.
+    peers: Vec<(usize, usize)>,
+    /// The number of every row's group of peers, starting at 1.
+    groups: Vec<usize>,
+}
+
+impl Partition {
+    /// A partition from its rows, sorted along with their keys.
+    fn new(sorted: Vec<(usize, Vec<Option<DataType>>)>) -> Self {
+        let mut peers = Vec::with_capacity(sorted.len());
+        let mut groups = Vec::with_capacity(sorted.len());
+        let mut start = 0;
+        while start < sorted.len() {
+            let mut end = start;
+            while end + 1 < sorted.len() && sorted[end + 1].1 == sorted[start].1 {
+                end += 1;
+            }
+            let group = groups.last().map_or(1, |group| group + 1);
+            for _ in start..=end {
+                peers.push((start, end));
+                groups.push(group);
+            }
+            start = end + 1;
+        }
+        Partition {
+            rows: sorted.into_iter().map(|(row, _)| row).collect(),
+            peers,
+            groups,
+        }
+    }
+
+    /// The positions of the first and last rows of the frame of the row at
+    /// `position`, `None` when the frame is empty.
+    fn frame(&self, frame: &WindowFrame, position: usize) -> Option<(usize, usize)> {
+        let last = self.rows.len() as i64 - 1;
+        let offset = |offset: usize| i64::try_from(offset).unwrap_or(i64::MAX);
+        let bound = |bound: FrameBound, start: bool| -> i64 {
+            let (first_peer, last_peer) = self.peers[position];
+            match bound {
+                FrameBound::UnboundedPreceding => 0,
+                FrameBound::Preceding(n) => (position as i64).saturating_sub(offset(n)),
+                FrameBound::CurrentRow => match (frame.units, start) {
+                    (FrameUnits::Rows, _) => position as i64,
+                    (FrameUnits::Range, true) => first_peer as i64,
+                    (FrameUnits::Range, false) => last_peer as i64,
+                },
+                FrameBound::Following(n) => (position as i64).saturating_add(offset(n)),
+                FrameBound::UnboundedFollowing => last,
+            }
+        };
+        let start = bound(frame.start, true).max(0);
+        let end = bound(frame.end, false).min(last);
+        (start <= end).then_some((start as usize, end as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(units: FrameUnits, start: FrameBound, end: FrameBound) -> WindowFrame {
+        WindowFrame { units, start, end }
+    }
+
+    #[test]
+    fn test_partition_frames() {
+        // Keys 1, 2, 2, 3 for rows 3, 0, 2, 1
+        let key = |value| vec![Some(DataType::Integer(value))];
+        let partition = Partition::new(vec![(3, key(1)), (0, key(2)), (2, key(2)), (1, key(3))]);
+        assert_eq!(partition.rows, [3, 0, 2, 1]);
+        assert_eq!(partition.peers, [(0, 0), (1, 2), (1, 2), (3, 3)]);
+        assert_eq!(partition.groups, [1, 2, 2, 3]);
+
+        assert_eq!(partition.frame(&DEFAULT_FRAME, 1), Some((0, 2)));
+        let rows = frame(
+            FrameUnits::Rows,
+            FrameBound::UnboundedPreceding,
+            FrameBound::CurrentRow,
+        );
+        assert_eq!(partition.frame(&rows, 1), Some((0, 1)));
+        let sliding = frame(
+            FrameUnits::Rows,
+            FrameBound::Preceding(1),
+            FrameBound::Following(1),
+        );
+        assert_eq!(partition.frame(&sliding, 0), Some((0, 1)));
+        assert_eq!(partition.frame(&sliding, 3), Some((2, 3)));
+        let ahead = frame(
+            FrameUnits::Rows,
+            FrameBound::Following(2),
+            FrameBound::UnboundedFollowing,
+        );
+        assert_eq!(partition.frame(&ahead, 1), Some((3, 3)));
+        assert_eq!(partition.frame(&ahead, 2), None);
+        let peers = frame(
+            FrameUnits::Range,
+            FrameBound::CurrentRow,
+            FrameBound::UnboundedFollowing,
+        );
+        assert_eq!(partition.frame(&peers, 2), Some((1, 3)));
+        let huge = frame(
+            FrameUnits::Rows,
+            FrameBound::Preceding(usize::MAX),
+            FrameBound::Following(usize::MAX),
+        );
+        assert_eq!(partition.frame(&huge, 2), Some((0, 3)));
+        let behind = frame(
+            FrameUnits::Rows,
+            FrameBound::UnboundedPreceding,
+            FrameBound::Preceding(usize::MAX),
+        );
+        assert_eq!(partition.frame(&behind, 2), None);
+    }
+}
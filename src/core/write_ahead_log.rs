@@ -1,97 +1,412 @@
+//! Write-ahead log.
+//!
+//! The log is a sequence of binary frames:
+//!
+//! ```text
+//! +-----------+-----------+-----------+-----------+----------------------+
+//! | crc (u32) | len (u32) | lsn (u64) | txn (u64) | payload (len bytes)  |
+//! +-----------+-----------+-----------+-----------+----------------------+
+//! ```
+//!
+//! The payload is a bincode-encoded [`LogEntry`], and the CRC32 covers
+//! everything after it: the length, the LSN, the transaction id and the
+//! payload. An LSN is the record's byte
+//! position in the stream of all logs ever written: every log file starts
+//! with a [`LogEntry::Checkpoint`] frame whose LSN is the checkpoint it was
+//! started by, and the LSN of every later frame is that base plus the frame's
+//! offset in the file.
+
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
-    fs::{File, OpenOptions},
-    io::{self, BufWriter, Write},
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
 };
-use serde::{Deserialize, Serialize};
-use serde_json;
 
 use crate::{
-    core::parser::{command::SQLCommand, sql_parser::parse_sql},
+    core::{error_handling::FerrousDBError, index::IndexType, table::ColumnSchema},
     DataType,
 };
 
+const FRAME_HEADER_SIZE: usize = 24;
+
 pub struct WriteAheadLog {
     writer: BufWriter<File>,
-    log_path: String,
+    log_path: PathBuf,
+    /// LSN of the first byte of the log file.
+    base_lsn: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A change recorded in the log. Row changes carry the full row images, so
+/// recovery can redo them without re-running any SQL.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum LogEntry {
-    Command(String),
-    SelectFrom {
+    CreateTable {
+        name: String,
+        columns: Vec<ColumnSchema>,
+    },
+    CreateIndex {
         table: String,
-        page_size: usize,
-        page: usize,
-        group_by: Option<String>,
-        order_by: Option<(String, bool)>,
+        column: String,
+        index_type: IndexType,
     },
-    DeleteFrom {
+    Insert {
         table: String,
-        condition: Option<String>,
+        row: HashMap<String, DataType>,
     },
     Update {
         table: String,
-        assignments: HashMap<String, DataType>,
-        condition: Option<String>,
+        old: HashMap<String, DataType>,
+        new: HashMap<String, DataType>,
     },
+    Delete {
+        table: String,
+        row: HashMap<String, DataType>,
+    },
+    /// Marks the end of a transaction. Entries of transactions without a
+    /// commit are discarded by recovery.
+    Commit,
+    /// First record of every log file; its LSN is the checkpoint that started
+    /// the file.
+    Checkpoint,
+}
+
+/// A decoded log frame.
+#[derive(Debug)]
+pub struct LogRecord {
+    pub lsn: u64,
+    pub txn_id: u64,
+    pub entry: LogEntry,
+}
+
+/// The committed part of a log, as found by [`WriteAheadLog::recover`].
+#[derive(Debug)]
+pub struct RecoveredLog {
+    /// Records of committed transactions in log order, without the commit
+    /// markers.
+    pub records: Vec<LogRecord>,
+    /// Byte offset just past the last commit marker.
+    pub end: u64,
+    /// Highest transaction id found in the log.
+    pub last_txn_id: u64,
 }
 
 impl WriteAheadLog {
-    pub fn new(log_path: &str) -> io::Result<Self> {
-        let file = OpenOptions::new()
+    pub fn new<P: AsRef<Path>>(log_path: P) -> io::Result<Self> {
+        let log_path = log_path.as_ref();
+        let mut file = OpenOptions::new()
             .create(true)
+            .read(true)
             .append(true)
             .open(log_path)?;
-        
+
+        // The checkpoint frame at the start of the file carries the base LSN
+        let mut header = [0; FRAME_HEADER_SIZE];
+        let base_lsn = match file.read_exact(&mut header) {
+            Ok(()) => u64::from_le_bytes(header[8..16].try_into().unwrap()),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+            Err(e) => return Err(e),
+        };
+
         Ok(WriteAheadLog {
             writer: BufWriter::new(file),
-            log_path: log_path.to_string(),
+            log_path: log_path.to_path_buf(),
+            base_lsn,
         })
     }
 
-    pub fn log(&mut self, sql: &str) -> io::Result<()> {
-        let command = parse_sql(sql).unwrap();
-        let entry = match command {
-            SQLCommand::CreateTable { name, columns } => {
-                LogEntry::Command(format!("CREATE TABLE {} ({:?})", name, columns))
+    /// Appends `entry` as part of transaction `txn_id` and returns its LSN.
+    pub fn log(&mut self, txn_id: u64, entry: &LogEntry) -> io::Result<u64> {
+        self.append(txn_id, entry)
+    }
+
+    /// Writes a commit marker for `txn_id` and syncs the log to disk.
+    pub fn commit(&mut self, txn_id: u64) -> io::Result<u64> {
+        let lsn = self.append(txn_id, &LogEntry::Commit)?;
+        self.writer.get_ref().sync_data()?;
+        Ok(lsn)
+    }
+
+    /// Current size of the log file in bytes.
+    pub fn position(&mut self) -> io::Result<u64> {
+        self.writer.flush()?;
+        Ok(self.writer.get_ref().metadata()?.len())
+    }
+
+    /// Writes a fresh log starting with a checkpoint marker for `lsn` next to
+    /// the current one, and returns its length. The old log stays in place
+    /// until [`WriteAheadLog::complete_rotation`] swaps the two.
+    pub fn prepare_rotation<P: AsRef<Path>>(log_path: P, lsn: u64) -> io::Result<u64> {
+        let rotated_path = Self::rotated_path(log_path.as_ref());
+        let mut wal = WriteAheadLog {
+            writer: BufWriter::new(File::create(&rotated_path)?),
+            log_path: rotated_path,
+            base_lsn: lsn,
+        };
+        wal.append(0, &LogEntry::Checkpoint)?;
+        wal.writer.get_ref().sync_all()?;
+        wal.position()
+    }
+
+    /// Atomically replaces the log with the one written by
+    /// [`WriteAheadLog::prepare_rotation`].
+    pub fn complete_rotation<P: AsRef<Path>>(log_path: P) -> io::Result<()> {
+        let log_path = log_path.as_ref();
+        fs::rename(Self::rotated_path(log_path), log_path)
+    }
+
+    /// Finishes or abandons a rotation interrupted by a crash. The new log is
+    /// installed only if the database already recorded its checkpoint.
+    pub fn recover_rotation<P: AsRef<Path>>(log_path: P, checkpoint_lsn: u64) -> io::Result<()> {
+        let log_path = log_path.as_ref();
+        let rotated_path = Self::rotated_path(log_path);
+        let bytes = match fs::read(&rotated_path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        match decode_frame(&bytes, 0) {
+            Frame::Valid(record, _) if record.lsn == checkpoint_lsn => {
+                Self::complete_rotation(log_path)
+            }
+            _ => fs::remove_file(rotated_path),
+        }
+    }
+
+    fn rotated_path(log_path: &Path) -> PathBuf {
+        let mut rotated = log_path.as_os_str().to_owned();
+        rotated.push(".tmp");
+        PathBuf::from(rotated)
+    }
+
+    /// Appends a frame and returns its LSN.
+    fn append(&mut self, txn_id: u64, entry: &LogEntry) -> io::Result<u64> {
+        let lsn = self.base_lsn + self.position()?;
+        let payload = bincode::serialize(entry)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+        frame.extend_from_slice(&[0; 4]);
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&lsn.to_le_bytes());
+        frame.extend_from_slice(&txn_id.to_le_bytes());
+        frame.extend_from_slice(&payload);
+        let crc = crc32(&frame[4..]);
+        frame[0..4].copy_from_slice(&crc.to_le_bytes());
+
+        self.writer.write_all(&frame)?;
+        self.writer.flush()?;
+        Ok(lsn)
+    }
+
+    /// Reads the committed records written at or after byte offset `start`
+    /// and truncates the log right after the last of them.
+    ///
+    /// Recovery stops at the first record that is incomplete or fails its
+    /// checksum. If no intact record follows it, it is a torn write, and it
+    /// is truncated away along with any records after the last commit
+    /// marker. Otherwise the log is damaged, and the returned
+    /// [`FerrousDBError::RecoveryError`] names the LSN where it diverged. A
+    /// log started by a different checkpoint than `checkpoint_lsn` is
+    /// rejected the same way.
+    pub fn recover<P: AsRef<Path>>(
+        log_path: P,
+        start: u64,
+        checkpoint_lsn: u64,
+    ) -> Result<RecoveredLog, FerrousDBError> {
+        let log_path = log_path.as_ref();
+        let recovered = Self::read_committed(log_path, start, checkpoint_lsn)?;
+        if fs::metadata(log_path).is_ok_and(|m| m.len() > recovered.end) {
+            let file = OpenOptions::new().write(true).open(log_path)?;
+            file.set_len(recovered.end)?;
+            file.sync_data()?;
+        }
+        Ok(recovered)
+    }
+
+    /// Reads the committed records like [`WriteAheadLog::recover`], but
+    /// leaves the log file as it is.
+    pub fn read_committed<P: AsRef<Path>>(
+        log_path: P,
+        start: u64,
+        checkpoint_lsn: u64,
+    ) -> Result<RecoveredLog, FerrousDBError> {
+        let log_path = log_path.as_ref();
+        let bytes = match fs::read(log_path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        if start > bytes.len() as u64 {
+            return Err(FerrousDBError::RecoveryError(format!(
+                "log '{}' is {} bytes long but the database expects at least {}",
+                log_path.display(),
+                bytes.len(),
+                start
+            )));
+        }
+
+        let base_lsn = match decode_frame(&bytes, 0) {
+            Frame::Valid(
+                LogRecord {
+                    lsn,
+                    entry: LogEntry::Checkpoint,
+                    ..
+                },
+                _,
+            ) => lsn,
+            _ if bytes.is_empty() => checkpoint_lsn,
+            _ => {
+                return Err(FerrousDBError::RecoveryError(format!(
+                    "log '{}' does not start with a checkpoint record",
+                    log_path.display()
+                )))
             }
-            SQLCommand::InsertInto { table, values } => {
-                LogEntry::Command(format!("INSERT INTO {} VALUES ({:?})", table, values))
+        };
+        if base_lsn != checkpoint_lsn {
+            return Err(FerrousDBError::RecoveryError(format!(
+                "log '{}' starts at checkpoint LSN {} but the database was checkpointed at LSN {}",
+                log_path.display(),
+                base_lsn,
+                checkpoint_lsn
+            )));
+        }
+
+        let mut records = Vec::new();
+        let mut pending: HashMap<u64, Vec<LogRecord>> = HashMap::new();
+        let mut end = start;
+        let mut last_txn_id = 0;
+        let mut offset = start as usize;
+        while offset < bytes.len() {
+            let (record, next) = match decode_frame(&bytes, offset) {
+                Frame::Valid(record, next) => (record, next),
+                // A damaged length can make any frame look torn, so only the
+                // last one in the log is taken as a torn write
+                _ if !intact_frame_after(&bytes, offset, base_lsn) => break,
+                bad => {
+                    return Err(FerrousDBError::RecoveryError(format!(
+                        "log '{}' diverged at LSN {} (byte {}): {}",
+                        log_path.display(),
+                        base_lsn + offset as u64,
+                        offset,
+                        match bad {
+                            Frame::Torn => "record runs past the end of the log",
+                            _ => "checksum mismatch",
+                        }
+                    )))
+                }
+            };
+            if record.lsn != base_lsn + offset as u64 {
+                return Err(FerrousDBError::RecoveryError(format!(
+                    "log '{}' diverged at LSN {} (byte {}): record claims LSN {}",
+                    log_path.display(),
+                    base_lsn + offset as u64,
+                    offset,
+                    record.lsn
+                )));
             }
-            SQLCommand::SelectFrom {
-                table,
-                page_size,
-                page,
-                group_by,
-                order_by,
-            } => LogEntry::SelectFrom {
-                table,
-                page_size,
-                page,
-                group_by,
-                order_by,
-            },
-            SQLCommand::DeleteFrom { table, condition } => {
-                LogEntry::DeleteFrom { table, condition }
+
+            last_txn_id = last_txn_id.max(record.txn_id);
+            match record.entry {
+                LogEntry::Commit => {
+                    records.extend(pending.remove(&record.txn_id).unwrap_or_default());
+                    end = next as u64;
+                }
+                LogEntry::Checkpoint => end = next as u64,
+                _ => pending.entry(record.txn_id).or_default().push(record),
             }
-            SQLCommand::Update {
-                table,
-                assignments,
-                condition,
-            } => LogEntry::Update {
-                table,
-                assignments,
-                condition,
+            offset = next;
+        }
+
+        Ok(RecoveredLog {
+            records,
+            end,
+            last_txn_id,
+        })
+    }
+}
+
+enum Frame {
+    /// A well-formed record and the offset of the next frame.
+    Valid(LogRecord, usize),
+    /// The frame runs past the end of the file.
+    Torn,
+    /// The frame fails its checksum or can't be decoded.
+    Corrupt,
+}
+
+fn decode_frame(bytes: &[u8], offset: usize) -> Frame {
+    let Some(header) = bytes.get(offset..offset + FRAME_HEADER_SIZE) else {
+        return Frame::Torn;
+    };
+    let crc = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    let len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+    let next = offset + FRAME_HEADER_SIZE + len;
+    let Some(frame) = bytes.get(offset + 4..next) else {
+        return Frame::Torn;
+    };
+    if crc32(frame) != crc {
+        return Frame::Corrupt;
+    }
+
+    match bincode::deserialize(&frame[20..]) {
+        Ok(entry) => Frame::Valid(
+            LogRecord {
+                lsn: u64::from_le_bytes(frame[4..12].try_into().unwrap()),
+                txn_id: u64::from_le_bytes(frame[12..20].try_into().unwrap()),
+                entry,
             },
-            SQLCommand::CreateView { name, query, columns } => {
-                LogEntry::Command(format!("CREATE VIEW {} AS {} ({})", name, query, columns.join(", ")))
-            }
-        };
+            next,
+        ),
+        Err(_) => Frame::Corrupt,
+    }
+}
 
-        let entry_str = serde_json::to_string(&entry)?;
-        writeln!(self.writer, "{}", entry_str)?;
-        self.writer.flush()?;
-        Ok(())
+/// Whether an intact frame, recognized by its LSN matching its position,
+/// follows the bad one at `offset`.
+fn intact_frame_after(bytes: &[u8], offset: usize, base_lsn: u64) -> bool {
+    (offset + 1..bytes.len().saturating_sub(FRAME_HEADER_SIZE - 1)).any(|candidate| {
+        let lsn = u64::from_le_bytes(bytes[candidate + 8..candidate + 16].try_into().unwrap());
+        lsn == base_lsn + candidate as u64
+            && matches!(decode_frame(bytes, candidate), Frame::Valid(..))
+    })
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE 802.3), as used by zlib and PNG.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &b| {
+        CRC32_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
     }
 }
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use druid::{
     widget::{Button, Flex, Label, TextBox},
-    Widget, WidgetExt, Env
+    Widget, WidgetExt, Env, FontDescriptor, FontFamily,
 };
 
 use crate::{theme, FerrousDBState};
@@ -27,7 +27,8 @@ pub fn sql_queries() -> impl Widget<FerrousDBState> {
     let execute_button = Button::new("Executar SQL")
         .on_click(|ctx, data: &mut FerrousDBState, _| {
             match data.db.execute_sql(&data.sql_command) {
-                Ok(message) => data.sql_output = message,
+                // Rows come out as a text table, hence the monospaced font below
+                Ok(result) => data.sql_output = result.to_string(),
                 Err(e) => data.sql_output = format!("Erro: {}", e),
             }
             ctx.request_update();
@@ -46,6 +47,7 @@ pub fn sql_queries() -> impl Widget<FerrousDBState> {
 
     let sql_output = Label::new(|data: &FerrousDBState, _env: &Env| data.sql_output.clone())
         .with_text_size(theme::TEXT_SIZE_MEDIUM)
+        .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
         .with_text_color(theme::TEXT_COLOR)
         .padding(theme::PADDING_MEDIUM)
         .background(theme::SURFACE_COLOR)
@@ -1,9 +1,10 @@
 use std::sync::Arc;
 
 use builder::ui_builder;
+use druid::widget::Label;
 use druid::Selector;
-use druid::{AppLauncher, Data, Lens, LocalizedString, PlatformError, WindowDesc};
-use ferrous_db::Row;
+use druid::{AppLauncher, Data, Lens, LocalizedString, PlatformError, WidgetExt, WindowDesc};
+use ferrous_db::{FerrousDB, Row, DEFAULT_DB_PATH};
 
 mod app_controller;
 mod builder;
@@ -31,8 +32,14 @@ impl Data for FerrousDBState {
 const SELECT_TABLE: Selector<String> = Selector::new("app.select-table");
 
 fn main() -> Result<(), PlatformError> {
+    let db = match FerrousDB::open(DEFAULT_DB_PATH) {
+        Ok(db) => db,
+        // Never fall back to a database that would be thrown away on exit,
+        // e.g. while the REPL holds the lock
+        Err(e) => return show_open_error(format!("Could not open '{}': {}", DEFAULT_DB_PATH, e)),
+    };
     let mut data = FerrousDBState {
-        db: ferrous_db::FerrousDB::new(),
+        db,
         rows: Vec::new(),
         new_table_name: "".to_string(),
         new_columns: "".to_string(),
@@ -56,3 +63,23 @@ fn main() -> Result<(), PlatformError> {
         .log_to_console()
         .launch(data)
 }
+
+/// Shows a window with nothing but the reason the database could not be
+/// opened.
+fn show_open_error(message: String) -> Result<(), PlatformError> {
+    let label = Label::new(message)
+        .with_text_color(theme::TEXT_COLOR)
+        .padding(theme::PADDING_LARGE)
+        .center()
+        .background(theme::BACKGROUND_COLOR);
+    let window = WindowDesc::new(label)
+        .title(LocalizedString::new("ferrousdb-win-title").with_placeholder("FerrousDB"))
+        .window_size((480.0, 160.0));
+
+    AppLauncher::with_window(window)
+        .configure_env(|env, _| {
+            theme::configure_env(env);
+        })
+        .log_to_console()
+        .launch(())
+}
@@ -1,6 +1,8 @@
 mod core;
 
+pub use core::database::*;
 pub use core::db::*;
 pub use core::parser::*;
+pub use core::result_set::*;
 pub use core::row::*;
 pub use core::table::*;